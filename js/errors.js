@@ -0,0 +1,120 @@
+// The errors thrown by gizmo, each with a code property.
+// wasm-bindgen imports a copy of this file, so the classes are kept in a global registry
+// to make instanceof work with the classes exported from gizmo.js as well.
+
+const REGISTRY = Symbol.for("gizmo-db.errors");
+
+function defineErrors() {
+    class GizmoError extends Error {
+        constructor(message, code, name = "GizmoError") {
+            super(message);
+            this.name = name;
+            this.code = code;
+        }
+    }
+
+    class NotFoundError extends GizmoError {
+        constructor(message) {
+            super(message, "NOT_FOUND", "NotFoundError");
+        }
+    }
+
+    class DuplicateQuadError extends GizmoError {
+        constructor(message) {
+            super(message, "DUPLICATE_QUAD", "DuplicateQuadError");
+        }
+    }
+
+    class StorageError extends GizmoError {
+        constructor(message) {
+            super(message, "STORAGE", "StorageError");
+        }
+    }
+
+    class InvalidInputError extends GizmoError {
+        constructor(message) {
+            super(message, "INVALID_INPUT", "InvalidInputError");
+        }
+    }
+
+    class QuotaExceededError extends GizmoError {
+        constructor(message) {
+            super(message, "QUOTA_EXCEEDED", "QuotaExceededError");
+        }
+    }
+
+    class AbortedError extends GizmoError {
+        constructor(message) {
+            super(message, "ABORTED", "AbortedError");
+        }
+    }
+
+    class BlockedError extends GizmoError {
+        constructor(message) {
+            super(message, "BLOCKED", "BlockedError");
+        }
+    }
+
+    class VersionChangeError extends GizmoError {
+        constructor(message) {
+            super(message, "VERSION_CHANGE", "VersionChangeError");
+        }
+    }
+
+    class UnsupportedError extends GizmoError {
+        constructor(message) {
+            super(message, "UNSUPPORTED", "UnsupportedError");
+        }
+    }
+
+    return {
+        GizmoError,
+        NotFoundError,
+        DuplicateQuadError,
+        StorageError,
+        InvalidInputError,
+        QuotaExceededError,
+        AbortedError,
+        BlockedError,
+        VersionChangeError,
+        UnsupportedError,
+    }
+}
+
+const errors = globalThis[REGISTRY] || (globalThis[REGISTRY] = defineErrors());
+
+export const GizmoError = errors.GizmoError;
+export const NotFoundError = errors.NotFoundError;
+export const DuplicateQuadError = errors.DuplicateQuadError;
+export const StorageError = errors.StorageError;
+export const InvalidInputError = errors.InvalidInputError;
+export const QuotaExceededError = errors.QuotaExceededError;
+export const AbortedError = errors.AbortedError;
+export const BlockedError = errors.BlockedError;
+export const VersionChangeError = errors.VersionChangeError;
+export const UnsupportedError = errors.UnsupportedError;
+
+function newError(code, message) {
+    switch (code) {
+        case "NOT_FOUND": return new NotFoundError(message);
+        case "DUPLICATE_QUAD": return new DuplicateQuadError(message);
+        case "STORAGE": return new StorageError(message);
+        case "INVALID_INPUT": return new InvalidInputError(message);
+        case "QUOTA_EXCEEDED": return new QuotaExceededError(message);
+        case "ABORTED": return new AbortedError(message);
+        case "BLOCKED": return new BlockedError(message);
+        case "VERSION_CHANGE": return new VersionChangeError(message);
+        case "UNSUPPORTED": return new UnsupportedError(message);
+        default: return new GizmoError(message, code);
+    }
+}
+
+// called from Rust, see src/error.rs
+// errors lists the {index, reason} of every malformed quad when a whole batch was rejected
+export function createError(code, message, errors) {
+    let error = newError(code, message);
+    if (errors !== undefined) {
+        error.errors = errors;
+    }
+    return error;
+}
@@ -1,5 +1,6 @@
 import * as lib from "../pkg/index.js";
 export * from "../pkg/index.js";
+export * from "./errors.js";
 
 // args[0].prototype.contructor.name == "Path"
 function has_path(args) {
@@ -9,7 +10,12 @@ function has_path(args) {
     return false
 }
 
-// the argument at idx is an object or an array of objects
+// a Date, {iri}, {bnode} or {literal, datatype, lang}
+function is_value_object(o) {
+    return o instanceof Date || typeof o.iri === "string" || "bnode" in o || "literal" in o
+}
+
+// the argument at idx is an object or an array of objects, that are not values
 function has_filter(args, idx) {
     return args.length > idx &&
     (
@@ -17,11 +23,13 @@ function has_filter(args, idx) {
             Array.isArray(args[idx]) && 
             args[idx].length > 0 && 
             args[idx][0] != null && 
-            typeof args[idx][0] === 'object'
+            typeof args[idx][0] === 'object' &&
+            !is_value_object(args[idx][0])
         ) || 
         (
             args[idx] != null && 
-            typeof args[idx] === 'object'
+            typeof args[idx] === 'object' &&
+            !is_value_object(args[idx])
         )
     )
 }
@@ -33,6 +41,23 @@ lib.QuadIterator.prototype[Symbol.iterator] = function() { return this; }
 // lib.GraphWrapper.prototype.write
 // lib.GraphWrapper.prototype.delete
 
+// the nodes of a path and the edges between them, or the quads matching a read filter
+lib.GraphWrapper.prototype.exportGraphML = function(source) {
+    if (has_path(arguments)) {
+        return this._export_graph_path(source, "graphml")
+    } else {
+        return this._export_graph_filter(source, "graphml")
+    }
+}
+
+lib.GraphWrapper.prototype.exportGexf = function(source) {
+    if (has_path(arguments)) {
+        return this._export_graph_path(source, "gexf")
+    } else {
+        return this._export_graph_filter(source, "gexf")
+    }
+}
+
 lib.Graph.prototype.V = function() {
     return this._v(Array.prototype.slice.call(arguments));
 }
@@ -245,4 +270,27 @@ export function like(pattern) {
     return {
         like: pattern,
     }
-}
\ No newline at end of file
+}
+
+export function iri(value) {
+    return {
+        iri: value,
+    }
+}
+
+export function bnode(label) {
+    return {
+        bnode: label,
+    }
+}
+
+export function literal(value, datatype, lang) {
+    let l = { literal: value };
+    if (datatype !== undefined && datatype !== null) {
+        l.datatype = datatype;
+    }
+    if (lang !== undefined && lang !== null) {
+        l.lang = lang;
+    }
+    return l
+}
@@ -482,6 +482,988 @@
         ["smart_person"],
     ) 
 
+    /////////////////////////
+
+    {
+        let strict = gizmo.NewMemoryGraph();
+        let error = null;
+
+        try {
+            strict.write([
+                ["<alice>", "<follows>", "<bob>"],
+                ["<alice>", "<follows>"],
+                ["<alice>", null, "<bob>"]
+            ]);
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "strict write rejects malformed quads",
+            error instanceof gizmo.InvalidInputError && error.errors.map(e => e.index).join(","),
+            "1,2"
+        )
+
+        run_test (
+            "strict write rejects the whole batch",
+            strict.g().V("<alice>").out("<follows>").all(),
+            [],
+        )
+
+        strict.write([
+            ["<alice>", "<follows>", "<bob>"],
+            ["<alice>", "<follows>"]
+        ], { strict: false });
+
+        run_test (
+            "lenient write skips malformed quads",
+            strict.g().V("<alice>").out("<follows>").all(),
+            ["<bob>"],
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let objects = gizmo.NewMemoryGraph();
+
+        objects.write([
+            { subject: "<alice>", predicate: "<follows>", object: "<bob>" },
+            { s: "<bob>", p: "<follows>", o: "<fred>", l: "<social>" }
+        ]);
+
+        run_test (
+            "write quads as objects",
+            objects.g().V("<alice>").out("<follows>").out("<follows>").all(),
+            ["<fred>"],
+        )
+
+        run_test_direct (
+            "read quads as objects",
+            JSON.stringify(Array.from(objects.read({ sub: "<bob>" }, { objects: true }))),
+            JSON.stringify([{ subject: "<bob>", predicate: "<follows>", object: "<fred>", label: "<social>" }])
+        )
+
+        objects.delete([{ s: "<alice>", p: "<follows>", o: "<bob>" }]);
+
+        run_test (
+            "delete quads as objects",
+            objects.g().V("<alice>").out("<follows>").all(),
+            [],
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let nquads = gizmo.NewMemoryGraph();
+
+        let count = await nquads.importNQuads([
+            '<alice> <follows> <bob> .',
+            '# a comment',
+            '<bob> <name> "Bob \\"the builder\\"" <people> .',
+            '<bob> <age> "42"^^<http://www.w3.org/2001/XMLSchema#integer> .',
+            '_:b0 <follows> <alice> .',
+        ].join("\n"));
+
+        run_test_direct (
+            "import N-Quads",
+            count,
+            4
+        )
+
+        run_test (
+            "query imported N-Quads",
+            nquads.g().V("<bob>").out("<age>").all(),
+            [42],
+        )
+
+        let copy = gizmo.NewMemoryGraph();
+        await copy.importNQuads(nquads.exportNQuads());
+
+        run_test_direct (
+            "N-Quads round-trip",
+            copy.exportNQuads({ sub: "<bob>", pred: "<name>" }),
+            '<bob> <name> "Bob \\"the builder\\"" <people> .\n'
+        )
+
+        let error = null;
+        try {
+            await nquads.importNQuads('<alice> <follows> <bob>');
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "N-Quads syntax errors report the position",
+            error.message,
+            "line 1, column 24: expected '.'"
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let turtle = gizmo.NewMemoryGraph();
+
+        let count = await turtle.importTurtle(`
+            @prefix ex: <http://example.org/> .
+            PREFIX foaf: <http://xmlns.com/foaf/0.1/>
+
+            ex:alice a foaf:Person ;
+                foaf:knows ex:bob, ex:carol ;
+                foaf:name "Alice"@en .
+
+            ex:people {
+                ex:bob foaf:age 42 ; foaf:nick """Bobby""" .
+            }
+        `);
+
+        run_test_direct (
+            "import Turtle and TriG",
+            count,
+            6
+        )
+
+        run_test (
+            "query imported Turtle",
+            turtle.g().V("<http://example.org/alice>").out("<http://xmlns.com/foaf/0.1/knows>").all(),
+            ["<http://example.org/bob>", "<http://example.org/carol>"],
+        )
+
+        run_test (
+            "TriG graphs become labels",
+            turtle.g().V("<http://example.org/bob>").labelContext("<http://example.org/people>").out("<http://xmlns.com/foaf/0.1/age>").all(),
+            [42],
+        )
+
+        let error = null;
+        try {
+            await turtle.importTurtle("@prefix ex: <http://example.org/> .\nex:a ex:b <http://example.org/d> .\nex:a ex:b undefined:c .");
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "Turtle syntax errors report the line and column",
+            error.message,
+            "line 3, column 11: undefined prefix 'undefined:'"
+        )
+
+        run_test (
+            "a Turtle syntax error writes nothing",
+            turtle.g().V("<http://example.org/a>").out("<http://example.org/b>").all(),
+            [],
+        )
+
+        let blank = gizmo.NewMemoryGraph();
+        await blank.importTurtle('_:a <http://example.org/name> "first" . [] <http://example.org/name> "anonymous" .');
+        await blank.importTurtle('_:a <http://example.org/name> "second" .');
+
+        run_test_direct (
+            "blank nodes of separate Turtle imports are kept apart",
+            blank.g().V().has("<http://example.org/name>").count(),
+            3
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let jsonld = gizmo.NewMemoryGraph();
+
+        let context = {
+            "@vocab": "http://schema.org/",
+            "ex": "http://example.org/",
+            "knows": { "@id": "http://schema.org/knows", "@type": "@id" }
+        };
+
+        let count = await jsonld.importJsonLd({
+            "@context": context,
+            "@id": "ex:people",
+            "@graph": [
+                { "@id": "ex:alice", "@type": "Person", "name": "Alice", "knows": "ex:bob" },
+                { "@id": "ex:bob", "@type": "Person", "name": "Bob", "age": 42 }
+            ]
+        });
+
+        run_test_direct (
+            "import JSON-LD",
+            count,
+            6
+        )
+
+        run_test (
+            "JSON-LD named graphs become labels",
+            jsonld.g().V("<http://example.org/alice>").labelContext("<http://example.org/people>").out("<http://schema.org/knows>").all(),
+            ["<http://example.org/bob>"],
+        )
+
+        let exported = jsonld.exportJsonLd({ "@context": context, "@id": "ex:bob" });
+
+        run_test_direct (
+            "export JSON-LD",
+            JSON.stringify(exported["@graph"]),
+            JSON.stringify([{ "@id": "ex:people", "@graph": [{ "@id": "ex:bob", "@type": "Person", "name": "Bob", "age": 42 }] }])
+        )
+
+        let blank = gizmo.NewMemoryGraph();
+        await blank.importJsonLd({ "@id": "_:a", "http://schema.org/name": "first" });
+        await blank.importJsonLd({ "@id": "_:a", "http://schema.org/name": "second" });
+
+        run_test_direct (
+            "blank nodes of separate JSON-LD imports are kept apart",
+            blank.g().V().has("<http://schema.org/name>").count(),
+            2
+        )
+
+        let unset = gizmo.NewMemoryGraph();
+        await unset.importJsonLd({ "@id": "http://example.org/a", "http://schema.org/name": "a", "http://schema.org/age": undefined });
+
+        run_test_direct (
+            "JSON-LD properties set to undefined are left out",
+            unset.g().V("<http://example.org/a>").out().count(),
+            1
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let terms = gizmo.NewMemoryGraph();
+
+        terms.write([
+            [{ iri: "http://example.org/chat" }, "<label>", { literal: "chat", lang: "fr" }],
+            [{ iri: "http://example.org/chat" }, "<label>", { literal: "cat", lang: "en" }],
+            [{ bnode: "b0" }, "<code>", { literal: "12ab", datatype: "xsd:token" }],
+        ]);
+
+        run_test_direct (
+            "language tagged literals are kept apart",
+            terms.g().V(gizmo.iri("http://example.org/chat")).out("<label>").toArray().map(v => JSON.stringify(v)).sort().join(),
+            [{ literal: "cat", lang: "en" }, { literal: "chat", lang: "fr" }].map(v => JSON.stringify(v)).join()
+        )
+
+        run_test_direct (
+            "has a typed literal",
+            JSON.stringify(terms.g().V().has("<code>", gizmo.literal("12ab", "xsd:token")).toArray()),
+            JSON.stringify([{ bnode: "b0" }])
+        )
+
+        terms.write([
+            ["<item>", "<quantity>", { literal: "12", datatype: "xsd:int" }],
+            ["<item>", "<price>", { literal: "1.50", datatype: "xsd:decimal" }],
+        ]);
+
+        run_test_direct (
+            "an xsd:int literal keeps its datatype",
+            JSON.stringify(terms.g().V("<item>").out("<quantity>").toArray()),
+            JSON.stringify([{ literal: "12", datatype: "http://www.w3.org/2001/XMLSchema#int" }])
+        )
+
+        terms.write([
+            ["<note>", "<text>", "_:x"],
+            ["<note>", "<text>", '"hi"@en'],
+            ["<note>", "<text>", { bnode: "x" }],
+        ]);
+
+        run_test_direct (
+            "plain strings are kept apart from blank nodes and literals",
+            terms.g().V("<note>").out("<text>").toArray().map(v => JSON.stringify(v)).sort().join(),
+            ['"hi"@en', "_:x", { bnode: "x" }].map(v => JSON.stringify(v)).sort().join()
+        )
+
+        run_test_direct (
+            "an xsd:decimal literal keeps its lexical form",
+            terms.exportNQuads({ sub: "<item>", pred: "<price>" }).includes('"1.50"^^<http://www.w3.org/2001/XMLSchema#decimal>'),
+            true
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let events = gizmo.NewMemoryGraph();
+
+        events.write([
+            ["<launch>", "<at>", new Date("2020-03-01T10:00:00Z")],
+            ["<meeting>", "<at>", new Date("2020-01-15T09:30:00Z")],
+            ["<party>", "<at>", new Date("2019-12-31T23:00:00Z")],
+        ]);
+
+        run_test (
+            "filter dates",
+            events.g().V().has("<at>", gizmo.gte(new Date("2020-01-01T00:00:00Z"))).all(),
+            ["<launch>", "<meeting>"],
+        )
+
+        run_test_direct (
+            "order dates",
+            events.g().V().out("<at>").order().toArray().map(d => d.toISOString()).join(),
+            "2019-12-31T23:00:00.000Z,2020-01-15T09:30:00.000Z,2020-03-01T10:00:00.000Z"
+        )
+
+        run_test_direct (
+            "dates are returned as Date",
+            events.g().V("<party>").out("<at>").toArray()[0] instanceof Date,
+            true
+        )
+
+        events.write([
+            ["<rumor>", "<at>", "sometime soon"],
+            ["<standup>", "<at>", { literal: "2020-02-01T09:00:00", datatype: "xsd:dateTime" }],
+        ]);
+
+        run_test (
+            "filter dates next to plain strings",
+            events.g().V().has("<at>", gizmo.gt(new Date("2020-02-15T00:00:00Z"))).all(),
+            ["<launch>"],
+        )
+
+        run_test_direct (
+            "dates without a time zone are returned as literals",
+            JSON.stringify(events.g().V("<standup>").out("<at>").toArray()[0]),
+            JSON.stringify({ literal: "2020-02-01T09:00:00", datatype: "http://www.w3.org/2001/XMLSchema#dateTime" })
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let ids = gizmo.NewMemoryGraph();
+
+        ids.write([
+            ["<tweet1>", "<id>", 1234567890123456789n],
+            ["<tweet2>", "<id>", 1234567890123456790n],
+            ["<tweet3>", "<id>", 42n],
+            ["<tweet4>", "<id>", 41.5],
+        ]);
+
+        run_test_direct (
+            "large integers come back as BigInt",
+            ids.g().V("<tweet1>").out("<id>").toArray()[0],
+            1234567890123456789n
+        )
+
+        run_test_direct (
+            "small integers come back as numbers",
+            ids.g().V("<tweet3>").out("<id>").toArray()[0],
+            42
+        )
+
+        run_test (
+            "a number finds a BigInt",
+            ids.g().V().has("<id>", 42).all(),
+            ["<tweet3>"],
+        )
+
+        run_test (
+            "filter mixed integers and floats",
+            ids.g().V().has("<id>", gizmo.gt(41.9)).all(),
+            ["<tweet1>", "<tweet2>", "<tweet3>"],
+        )
+
+        run_test_direct (
+            "order mixed integers and floats",
+            ids.g().V().out("<id>").order().toArray().map(n => n.toString()).join(),
+            "41.5,42,1234567890123456789,1234567890123456790"
+        )
+
+        run_test_direct (
+            "export integers exactly",
+            ids.exportNQuads({ sub: "<tweet1>" }).includes('"1234567890123456789"^^<http://www.w3.org/2001/XMLSchema#integer>'),
+            true
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let original = gizmo.NewMemoryGraph();
+
+        original.write([
+            ["<alice>", "<follows>", "<bob>"],
+            ["<bob>", "<age>", 1234567890123456789n],
+            ["<bob>", "<height>", 1.85, "<people>"],
+            ["<bob>", "<name>", gizmo.literal("Bob", null, "en")],
+            [gizmo.bnode("b0"), "<active>", true],
+        ]);
+
+        let bytes = original.exportSnapshot();
+        let copy = gizmo.NewMemoryGraphFromSnapshot(bytes);
+
+        run_test_direct (
+            "a snapshot is a Uint8Array",
+            bytes instanceof Uint8Array,
+            true
+        )
+
+        run_test_direct (
+            "restore a snapshot",
+            copy.exportNQuads().split("\n").sort().join("\n"),
+            original.exportNQuads().split("\n").sort().join("\n")
+        )
+
+        let corrupt = bytes.slice();
+        corrupt[10] ^= 0xff;
+
+        let error = null;
+        try {
+            gizmo.NewMemoryGraphFromSnapshot(corrupt);
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "a corrupt snapshot is rejected",
+            error instanceof gizmo.InvalidInputError && error.message.includes("checksum"),
+            true
+        )
+
+        let seeded = await gizmo.NewIndexedDbGraph("gizmo_snapshot_test_" + Date.now());
+
+        run_test_direct (
+            "seed an IndexedDB graph from a snapshot",
+            await seeded.importSnapshot(bytes),
+            5
+        )
+
+        run_test (
+            "query a seeded IndexedDB graph",
+            await seeded.g().V("<alice>").out("<follows>").allAsync(),
+            ["<bob>"],
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let bulk = gizmo.NewMemoryGraph();
+
+        let quads = [];
+        for (let i = 0; i < 2500; i++) {
+            quads.push(["<n" + i + ">", "<next>", "<n" + (i + 1) + ">"]);
+        }
+
+        let progress = [];
+
+        run_test_direct (
+            "bulk load an array",
+            await bulk.bulkLoad(quads, { batchSize: 1000, onProgress: p => progress.push(p.loaded + "/" + p.total) }),
+            2500
+        )
+
+        run_test_direct (
+            "bulk load reports progress",
+            progress.join(),
+            "1000/2500,2000/2500,2500/2500"
+        )
+
+        let encoder = new TextEncoder();
+        let chunks = ['<alice> <follows> <bo', 'b> .\n<bob> <name> "B\u00f6b" .\n', '<bob> <follows> <fred> .'];
+
+        let stream = new ReadableStream({
+            start(controller) {
+                // the ö is split across two chunks
+                let bytes = encoder.encode(chunks[1]);
+                controller.enqueue(chunks[0]);
+                controller.enqueue(bytes.slice(0, 21));
+                controller.enqueue(bytes.slice(21));
+                controller.enqueue(chunks[2]);
+                controller.close();
+            }
+        });
+
+        let streamed = gizmo.NewMemoryGraph();
+
+        run_test_direct (
+            "bulk load a stream of N-Quads",
+            await streamed.bulkLoad(stream, {}),
+            3
+        )
+
+        run_test_direct (
+            "bulk load decodes split UTF-8",
+            streamed.g().V("<bob>").out("<name>").toArray()[0],
+            "Böb"
+        )
+
+        let mixed = new ReadableStream({
+            start(controller) {
+                controller.enqueue(encoder.encode("<carol> <follows> "));
+                controller.enqueue("<dave> .\n<dave> <follows> ");
+                controller.enqueue(encoder.encode("<carol> .\n"));
+                controller.close();
+            }
+        });
+
+        let mixed_graph = gizmo.NewMemoryGraph();
+        await mixed_graph.bulkLoad(mixed, {});
+
+        run_test (
+            "bulk load a stream of text and bytes in order",
+            mixed_graph.g().V("<carol>").out("<follows>").out("<follows>").all(),
+            ["<carol>"]
+        )
+
+        let controller = new AbortController();
+        let aborted = gizmo.NewMemoryGraph();
+        let error = null;
+
+        try {
+            await aborted.bulkLoad(quads, { batchSize: 1000, signal: controller.signal, onProgress: () => controller.abort() });
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "abort a bulk load",
+            error instanceof gizmo.AbortedError && aborted.g().V().out("<next>").count(),
+            1000
+        )
+
+        let failing = new ReadableStream({
+            pull(controller) {
+                controller.error(new TypeError("connection reset"));
+            }
+        });
+
+        error = null;
+
+        try {
+            await gizmo.NewMemoryGraph().bulkLoad(failing, {});
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "a failing source stream is invalid input, not a storage error",
+            error instanceof gizmo.InvalidInputError && error.message.includes("connection reset"),
+            true
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let people = gizmo.NewMemoryGraph();
+
+        let csv = 'id,name,age,homepage\r\n' +
+            '1,Alice,34,http://alice.example\r\n' +
+            '2,"Bob ""The Builder"", Jr.",,\r\n' +
+            '3,Carol,old,\r\n';
+
+        let mapping = {
+            subject: "<http://example.org/person/{id}>",
+            columns: {
+                name: "<name>",
+                age: { predicate: "<age>", type: "integer" },
+                homepage: { predicate: "<homepage>", type: "iri" },
+            },
+        };
+
+        let error = null;
+        try {
+            await people.importCsv(csv, mapping);
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "a bad row rejects a strict CSV import",
+            error instanceof gizmo.InvalidInputError && JSON.stringify(error.errors),
+            JSON.stringify([{ line: 4, reason: 'column "age": "old" is not an integer' }])
+        )
+
+        let result = await people.importCsv(csv, Object.assign({ strict: false }, mapping));
+
+        run_test_direct (
+            "import CSV",
+            result.quads + " " + result.errors.map(e => e.line).join(),
+            "5 4"
+        )
+
+        run_test_direct (
+            "import quoted CSV cells",
+            people.g().V("<http://example.org/person/2>").out("<name>").toArray()[0],
+            'Bob "The Builder", Jr.'
+        )
+
+        run_test (
+            "import typed CSV cells",
+            people.g().V().has("<age>", 34).has("<homepage>", "<http://alice.example>").all(),
+            ["<http://example.org/person/1>"],
+        )
+
+        let tsv = gizmo.NewMemoryGraph();
+
+        await tsv.importCsv("a\tknows\tb\nb\tknows\tc\n", {
+            delimiter: "\t",
+            header: false,
+            subject: "<{0}>",
+            columns: { 2: { predicate: "<knows>", type: "iri" } },
+        });
+
+        run_test (
+            "import TSV without a header",
+            tsv.g().V("<a>").out("<knows>").out("<knows>").all(),
+            ["<c>"],
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let social = gizmo.NewMemoryGraph();
+
+        social.write([
+            ["<alice>", "<follows>", "<bob>"],
+            ["<bob>", "<follows>", "<fred>"],
+            ["<alice>", "<age>", 34],
+            ["<bob>", "<name>", "Bob & Co"],
+        ]);
+
+        let parser = new DOMParser();
+
+        let graphml = parser.parseFromString(social.exportGraphML(social.g().V("<alice>", "<bob>")), "application/xml");
+
+        run_test_direct (
+            "export a path as GraphML",
+            graphml.getElementsByTagName("node").length + " " + graphml.getElementsByTagName("edge").length,
+            "2 1"
+        )
+
+        run_test_direct (
+            "fold literals into GraphML node attributes",
+            Array.from(graphml.querySelectorAll("key[for=node]")).map(k => k.getAttribute("attr.name") + ":" + k.getAttribute("attr.type")).sort().join(),
+            "age:long,label:string,name:string"
+        )
+
+        let gexf = parser.parseFromString(social.exportGexf({ pred: "<follows>" }), "application/xml");
+
+        run_test_direct (
+            "export a read filter as GEXF",
+            Array.from(gexf.getElementsByTagName("node")).map(n => n.getAttribute("label")).join() + " " +
+            Array.from(gexf.getElementsByTagName("edge")).map(e => e.getAttribute("label")).join(),
+            "alice,bob,fred follows,follows"
+        )
+    }
+
+    /////////////////////////
+
+    run_test (
+        "run a text query",
+        session.query('g.V("<alice>").out("<follows>").all()'),
+        ["<bob>"],
+    )
+
+    run_test (
+        "run a text query without a final",
+        session.query("g.V('<dani>', '<emily>').out('<follows>')"),
+        ["<bob>", "<fred>", "<greg>"],
+    )
+
+    run_test (
+        "run a text query with a path and a filter",
+        session.query('g.V().has("<status>", "cool_person").follow(g.M().out("<follows>")).has("<status>", gizmo.like("cool*")).all()'),
+        ["<bob>", "<greg>"],
+    )
+
+    run_test_direct (
+        "run a text query with a value final",
+        session.query('g.V("<bob>").in("<follows>").count();'),
+        3
+    )
+
+    let query_error = null;
+    try {
+        session.query('g.V("<alice>").outt("<follows>")');
+    } catch (e) {
+        query_error = e;
+    }
+
+    run_test_direct (
+        "a text query with an unknown step throws",
+        query_error instanceof gizmo.InvalidInputError && query_error.message,
+        "column 16: unknown step outt"
+    )
+
+    /////////////////////////
+
+    run_test_tag (
+        "run a SPARQL query",
+        session.sparql("SELECT ?x WHERE { ?x <follows> <bob> }"),
+        ["<alice>", "<charlie>", "<dani>"],
+        "x"
+    )
+
+    run_test_tag (
+        "run a SPARQL query with a join and DISTINCT",
+        session.sparql('SELECT DISTINCT ?a WHERE { ?a <follows> ?b . ?b <status> "cool_person" }'),
+        ["<alice>", "<charlie>", "<dani>", "<fred>"],
+        "a"
+    )
+
+    run_test_tag (
+        "run a SPARQL query with GRAPH",
+        session.sparql('PREFIX ex: <> SELECT * WHERE { GRAPH ex:smart_graph { ?x ex:status "smart_person" } }'),
+        ["<emily>", "<greg>"],
+        "x"
+    )
+
+    run_test_tag (
+        "run a SPARQL query with OPTIONAL",
+        session.sparql("SELECT ?x ?s WHERE { ?x <follows> <bob> OPTIONAL { ?x <status> ?s } }"),
+        ["cool_person"],
+        "s"
+    )
+
+    run_test_tag (
+        "run a SPARQL query with a string FILTER",
+        session.sparql('SELECT ?x WHERE { ?x <status> ?s FILTER STRSTARTS(?s, "smart") }'),
+        ["<emily>", "<greg>"],
+        "x"
+    )
+
+    run_test_direct (
+        "run a SPARQL query with FILTER, ORDER BY and LIMIT",
+        Array.from(session.sparql("SELECT DISTINCT ?x WHERE { ?x <follows> ?y FILTER (?x < <d>) } ORDER BY DESC(?x) LIMIT 2")).map(r => r.x).join(),
+        "<charlie>,<bob>"
+    )
+
+    run_test_direct (
+        "run a SPARQL query ordered by a variable that is not selected",
+        Array.from(session.sparql("SELECT ?x WHERE { ?x <status> ?s } ORDER BY DESC(?s) ?x")).map(r => r.x + (r.s === undefined ? "" : r.s)).join(),
+        "<emily>,<greg>,<bob>,<dani>,<greg>"
+    )
+
+    run_test_direct (
+        "run a SPARQL query with a variable GRAPH",
+        Array.from(session.sparql('SELECT ?x ?g WHERE { GRAPH ?g { ?x <status> ?s } FILTER STRSTARTS(?s, "smart") }')).map(r => r.x + " " + r.g).sort().join(),
+        "<emily> <smart_graph>,<greg> <smart_graph>"
+    )
+
+    let graph_error = null;
+    try {
+        session.sparql("SELECT * WHERE { GRAPH ?g { ?x <status> ?s . ?x <follows> ?y } }");
+    } catch (e) {
+        graph_error = e;
+    }
+
+    run_test_direct (
+        "a SPARQL query with a variable GRAPH around several patterns throws",
+        graph_error instanceof gizmo.InvalidInputError && graph_error.message,
+        "column 46: variable GRAPHs with more than one pattern are not supported"
+    )
+
+    let optional_errors = [
+        "SELECT * WHERE { ?x <follows> <bob> OPTIONAL { ?x <follows> ?y . ?y <status> ?s } }",
+        "SELECT * WHERE { ?x <follows> ?y OPTIONAL { ?x <status> ?y } }",
+    ].map(query => {
+        try {
+            session.sparql(query);
+        } catch (e) {
+            return e instanceof gizmo.InvalidInputError && e.message;
+        }
+    });
+
+    run_test_direct (
+        "a SPARQL OPTIONAL pattern that is not joined to a required one or binds nothing new throws",
+        optional_errors.join("\n"),
+        "column 66: OPTIONAL patterns that are not joined to a required pattern are not supported\n" +
+        "column 45: OPTIONAL patterns that do not bind a new variable are not supported"
+    )
+
+    let sparql_error = null;
+    try {
+        session.sparql("SELECT ?p WHERE { <alice> ?p <bob> }");
+    } catch (e) {
+        sparql_error = e;
+    }
+
+    run_test_direct (
+        "a SPARQL query with a variable predicate throws",
+        sparql_error instanceof gizmo.InvalidInputError && sparql_error.message,
+        "column 19: variable predicates are not supported"
+    )
+
+    /////////////////////////
+
+    run_test_direct (
+        "run a GraphQL query",
+        JSON.stringify(session.graphql("{ nodes(id: <alice>) { id follows { id } } }")),
+        '{"nodes":[{"id":"<alice>","follows":{"id":"<bob>"}}]}'
+    )
+
+    {
+        let result = session.graphql(`{
+            nodes(id: <bob>) {
+                followers: follows @rev @filter(gt: "<c>") {
+                    id
+                    status @opt
+                }
+            }
+        }`);
+        let followers = result.nodes[0].followers;
+
+        run_test_direct (
+            "run a GraphQL query with @rev, @filter and @opt",
+            followers.map(f => f.id + ":" + f.status).sort().join(),
+            "<charlie>:undefined,<dani>:cool_person"
+        )
+    }
+
+    run_test_direct (
+        "a GraphQL field that reaches nothing drops the node",
+        session.graphql('{ nodes(status: "cool_person") { id follows { id } } }').nodes.map(n => n.id).sort().join(),
+        "<bob>,<dani>"
+    )
+
+    run_test_direct (
+        "run a GraphQL query with @label",
+        session.graphql("{ nodes(id: <greg>) { status @label(v: <smart_graph>) } }").nodes[0].status,
+        "smart_person"
+    )
+
+    let graphql_error = null;
+    try {
+        session.graphql("{ nodes {\n  id follows @unnest { id } } }");
+    } catch (e) {
+        graphql_error = e;
+    }
+
+    run_test_direct (
+        "a GraphQL query with an unknown directive throws",
+        graphql_error instanceof gizmo.InvalidInputError && graphql_error.message,
+        "line 2, column 14: unknown directive @unnest"
+    )
+
+    /////////////////////////
+
+    {
+        let tree = g.V("<charlie>", "<dani>").tag("person").out("<follows>").toTree({
+            $key: "person",
+            name: "person",
+            friends: ["id"],
+            follows: { $key: "id", id: "id" }
+        });
+
+        run_test_direct (
+            "group results into a tree",
+            tree.map(p => p.name + ":" + p.friends.sort().join("|")).sort().join(),
+            "<charlie>:<bob>|<dani>,<dani>:<bob>|<greg>"
+        )
+
+        run_test_direct (
+            "nest templates in a tree",
+            tree.map(p => p.follows.map(f => f.id).sort().join("|")).sort().join(),
+            "<bob>|<dani>,<bob>|<greg>"
+        )
+    }
+
+    run_test_direct (
+        "group the results of a text query into a tree",
+        JSON.stringify(session.query('g.V("<alice>").tag("p").out("<follows>").toTree({"$key": "p", friends: ["id"]})')),
+        '[{"friends":["<bob>"]}]'
+    )
+
+    /////////////////////////
+
+    {
+        let plan = g.V("<alice>").out("<follows>").has("<status>", "cool_person").explain();
+        let shapes = (node) => 1 + node.children.reduce((n, c) => n + shapes(c), 0);
+
+        run_test_direct (
+            "explain a path",
+            typeof plan.unoptimized.type === "string" && typeof plan.optimized.size === "number" && shapes(plan.unoptimized) > 1,
+            true
+        )
+
+        run_test_direct (
+            "explain a text query",
+            typeof session.query('g.V("<alice>").out("<follows>").explain()').optimized.type,
+            "string"
+        )
+    }
+
+    /////////////////////////
+
+    {
+        let idb = await gizmo.NewIndexedDbGraph("gizmo_test");
+
+        await idb.writeAsync([
+            ["<alice>", "<follows>", "<bob>"],
+            ["<bob>", "<follows>", "<fred>"]
+        ]);
+
+        run_test (
+            "use an IndexedDB graph",
+            await idb.g().V("<alice>").out("<follows>").allAsync(),
+            ["<bob>"],
+        )
+
+        run_test (
+            "list all nodes of an IndexedDB graph",
+            await idb.g().V().allAsync(),
+            ["<alice>", "<bob>", "<fred>", "<follows>"],
+        )
+
+        run_test_direct (
+            "read all quads of an IndexedDB graph",
+            Array.from(await idb.readAsync({})).length,
+            2
+        )
+
+        await idb.deleteAsync([
+            ["<alice>", "<follows>", "<bob>"],
+            ["<bob>", "<follows>", "<fred>"]
+        ]);
+
+        run_test (
+            "delete from an IndexedDB graph",
+            await idb.g().V("<alice>").out("<follows>").allAsync(),
+            [],
+        )
+
+        let error = null;
+        try {
+            idb.write([["<alice>", "<follows>", "<bob>"]]);
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "a synchronous write to an IndexedDB graph throws a typed error",
+            error instanceof gizmo.UnsupportedError && error.code,
+            "UNSUPPORTED"
+        )
+    }
+
+    {
+        let name = "gizmo_version_change_test_" + Date.now();
+        let idb = await gizmo.NewIndexedDbGraph(name);
+
+        // another tab upgrading the database closes the connection of the graph
+        await new Promise((resolve, reject) => {
+            let request = indexedDB.open(name, 100);
+            request.onsuccess = () => { request.result.close(); resolve(); };
+            request.onerror = () => reject(request.error);
+        });
+
+        let error = null;
+        try {
+            await idb.writeAsync([["<alice>", "<follows>", "<bob>"]]);
+        } catch (e) {
+            error = e;
+        }
+
+        run_test_direct (
+            "writing after another tab upgraded the database rejects with a typed error",
+            error instanceof gizmo.VersionChangeError && error.code,
+            "VERSION_CHANGE"
+        )
+    }
+
 
 
 })()
@@ -0,0 +1,309 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use gizmo_db::graph::quad::{Quad, Delta, Procedure, IgnoreOptions};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use crate::{Session, js_to_quads};
+use crate::error::Error;
+use crate::format::nquads;
+
+
+const DEFAULT_BATCH_SIZE: usize = 10000;
+
+
+// Loads quads in batches, each applied with a single apply_deltas (or one IndexedDB transaction).
+// Between batches the progress is reported, the event loop gets to run and the signal is checked,
+// so a large import does not freeze the page and can be cancelled. Batches that were applied
+// before a cancellation or an error are kept.
+pub struct Loader {
+    session: Rc<RefCell<Session>>,
+    options: JsValue,
+    batch_size: usize,
+    on_progress: Option<js_sys::Function>,
+    signal: JsValue,
+    // the total number of quads, if it is known up front
+    total: Option<usize>,
+    pending: Vec<Quad>,
+    loaded: usize,
+}
+
+impl Loader {
+    // options: { batchSize: number, onProgress: function({loaded, total}), signal: AbortSignal, strict: bool }
+    pub fn new(session: Rc<RefCell<Session>>, options: &JsValue) -> Loader {
+        let get = |key: &str| {
+            if options.is_object() {
+                js_sys::Reflect::get(options, &JsValue::from_str(key)).unwrap_or(JsValue::undefined())
+            } else {
+                JsValue::undefined()
+            }
+        };
+
+        let batch_size = match get("batchSize").as_f64() {
+            Some(n) if n >= 1.0 => n as usize,
+            _ => DEFAULT_BATCH_SIZE
+        };
+
+        Loader {
+            session,
+            options: options.clone(),
+            batch_size,
+            on_progress: get("onProgress").dyn_into().ok(),
+            signal: get("signal"),
+            total: None,
+            pending: Vec::new(),
+            loaded: 0,
+        }
+    }
+
+    // source is an array of quads, N-Quads text, or a ReadableStream whose chunks are
+    // N-Quads text (strings or UTF-8 bytes) or arrays of quads. Resolves with the number of quads.
+    pub async fn load(mut self, source: JsValue) -> Result<u32, Error> {
+        if js_sys::Array::is_array(&source) {
+            self.load_array(&source.unchecked_into()).await?;
+        } else if let Some(text) = source.as_string() {
+            let quads = nquads::parse(&text)?;
+            self.total = Some(quads.len());
+            self.push(quads).await?;
+        } else if has_function(&source, "getReader") {
+            self.load_stream(&source).await?;
+        } else {
+            return Err(Error::InvalidInput("expected an array of quads, N-Quads text or a ReadableStream".to_string()))
+        }
+
+        self.flush().await?;
+
+        Ok(self.loaded as u32)
+    }
+
+    async fn load_array(&mut self, arr: &js_sys::Array) -> Result<(), Error> {
+        let len = arr.length() as usize;
+        self.total = Some(len);
+
+        let mut start = 0;
+
+        while start < len {
+            let end = (start + self.batch_size).min(len);
+            let chunk = arr.slice(start as u32, end as u32);
+            let quads = js_to_quads(&chunk, &self.options).map_err(|e| offset_errors(e, start))?;
+            self.push(quads).await?;
+            start = end;
+        }
+
+        Ok(())
+    }
+
+    async fn load_stream(&mut self, stream: &JsValue) -> Result<(), Error> {
+        let reader = call(stream, "getReader", &[])?;
+
+        let result = self.read_stream(&reader).await;
+
+        // stops the stream when the load did not reach its end
+        if result.is_err() {
+            let _ = call(&reader, "cancel", &[]);
+        } else {
+            let _ = call(&reader, "releaseLock", &[]);
+        }
+
+        result
+    }
+
+    async fn read_stream(&mut self, reader: &JsValue) -> Result<(), Error> {
+        // the text after the last complete line, and the line it starts on
+        let mut text = String::new();
+        // the start of a UTF-8 sequence that the next chunk of bytes completes
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut line = 1;
+        // the number of quads given as arrays, to number invalid quads across chunks
+        let mut index = 0;
+
+        loop {
+            if self.aborted() {
+                return Err(self.aborted_error())
+            }
+
+            let promise: js_sys::Promise = call(reader, "read", &[])?.unchecked_into();
+            let result = JsFuture::from(promise).await.map_err(|e| Error::from_source(&e, "Unable to read stream"))?;
+
+            let done = get(&result, "done").is_truthy();
+            let chunk = get(&result, "value");
+
+            if done {
+                break
+            }
+
+            // text and bytes go into the same buffer in the order they arrive
+            if let Some(s) = chunk.as_string() {
+                if !bytes.is_empty() {
+                    return Err(Error::InvalidInput("stream is not valid UTF-8".to_string()))
+                }
+                text.push_str(&s);
+            } else if let Some(arr) = chunk.dyn_ref::<js_sys::Uint8Array>() {
+                bytes.extend(arr.to_vec());
+                bytes = decode(bytes, &mut text)?;
+            } else if js_sys::Array::is_array(&chunk) {
+                if !text.is_empty() || !bytes.is_empty() {
+                    return Err(Error::InvalidInput("a chunk of quads can not follow text that does not end with a line break".to_string()))
+                }
+                let quads = js_to_quads(&chunk, &self.options).map_err(|e| offset_errors(e, index))?;
+                index += js_sys::Array::from(&chunk).length() as usize;
+                self.push(quads).await?;
+                continue
+            } else {
+                return Err(Error::InvalidInput("expected stream chunks of text, bytes or arrays of quads".to_string()))
+            }
+
+            if let Some(end) = text.rfind('\n') {
+                let rest = text.split_off(end + 1);
+                let lines = std::mem::replace(&mut text, rest);
+                let quads = nquads::parse_lines(&lines, line)?;
+                line += lines.matches('\n').count();
+                self.push(quads).await?;
+            }
+        }
+
+        if !bytes.is_empty() {
+            return Err(Error::InvalidInput("stream is not valid UTF-8".to_string()))
+        }
+
+        let quads = nquads::parse_lines(&text, line)?;
+        self.push(quads).await
+    }
+
+    // applies a batch whenever enough quads are pending
+    async fn push(&mut self, quads: Vec<Quad>) -> Result<(), Error> {
+        self.pending.extend(quads);
+
+        while self.pending.len() >= self.batch_size {
+            let rest = self.pending.split_off(self.batch_size);
+            let batch = std::mem::replace(&mut self.pending, rest);
+            self.apply(batch).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(())
+        }
+
+        let batch = std::mem::replace(&mut self.pending, Vec::new());
+        self.apply(batch).await
+    }
+
+    async fn apply(&mut self, batch: Vec<Quad>) -> Result<(), Error> {
+        if self.aborted() {
+            return Err(self.aborted_error())
+        }
+
+        let count = batch.len();
+
+        let deltas: Vec<Delta> = batch.into_iter().map(|quad| Delta {
+            quad,
+            action: Procedure::Add
+        }).collect();
+
+        let ignore_opts = IgnoreOptions{ignore_dup: true, ignore_missing: true};
+
+        let persist = self.session.borrow().persist.clone();
+
+        if let Some(p) = persist {
+            p.write_deltas(deltas, ignore_opts).await?;
+        } else {
+            let qs = self.session.borrow().qs.clone();
+            let res = qs.borrow_mut().apply_deltas(deltas, &ignore_opts);
+            res.map_err(Error::from_quad_writer)?;
+        }
+
+        self.loaded += count;
+        self.progress();
+
+        yield_to_event_loop().await;
+
+        Ok(())
+    }
+
+    fn progress(&self) {
+        if let Some(f) = &self.on_progress {
+            let obj = js_sys::Object::new();
+            let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("loaded"), &JsValue::from_f64(self.loaded as f64));
+            if let Some(total) = self.total {
+                let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("total"), &JsValue::from_f64(total as f64));
+            }
+            let _ = f.call1(&JsValue::NULL, &obj);
+        }
+    }
+
+    fn aborted(&self) -> bool {
+        self.signal.is_object() && get(&self.signal, "aborted").is_truthy()
+    }
+
+    fn aborted_error(&self) -> Error {
+        Error::Aborted(format!("Bulk load aborted after {} quads", self.loaded))
+    }
+}
+
+
+// the indexes of invalid quads in a chunk are made relative to the whole source
+fn offset_errors(e: Error, offset: usize) -> Error {
+    match e {
+        Error::InvalidQuads(errors) => Error::InvalidQuads(errors.into_iter().map(|(i, reason)| (i + offset, reason)).collect()),
+        e => e
+    }
+}
+
+
+// appends the complete UTF-8 sequences of bytes to text, and returns the bytes of a sequence
+// that is cut off at the end of the chunk
+fn decode(bytes: Vec<u8>, text: &mut String) -> Result<Vec<u8>, Error> {
+    let valid = match std::str::from_utf8(&bytes) {
+        Ok(s) => s.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => return Err(Error::InvalidInput("stream is not valid UTF-8".to_string()))
+    };
+
+    let mut bytes = bytes;
+    let rest = bytes.split_off(valid);
+    text.push_str(std::str::from_utf8(&bytes).unwrap_or_default());
+    Ok(rest)
+}
+
+
+fn get(obj: &JsValue, key: &str) -> JsValue {
+    js_sys::Reflect::get(obj, &JsValue::from_str(key)).unwrap_or(JsValue::undefined())
+}
+
+
+fn has_function(obj: &JsValue, key: &str) -> bool {
+    obj.is_object() && get(obj, key).is_function()
+}
+
+
+fn call(obj: &JsValue, key: &str, args: &[JsValue]) -> Result<JsValue, Error> {
+    let f: js_sys::Function = get(obj, key).dyn_into().map_err(|_| Error::InvalidInput(format!("{} is not a function", key)))?;
+    let args: js_sys::Array = args.iter().collect();
+    f.apply(obj, &args).map_err(|e| Error::from_source(&e, &format!("Unable to call {}", key)))
+}
+
+
+// resolves on a later turn of the event loop, so the page can handle input and render between batches
+async fn yield_to_event_loop() {
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let set_timeout = get(&js_sys::global(), "setTimeout");
+
+        match set_timeout.dyn_into::<js_sys::Function>() {
+            Ok(f) => {
+                let _ = f.call2(&JsValue::NULL, &resolve, &JsValue::from_f64(0.0));
+            },
+            Err(_) => {
+                let _ = resolve.call0(&JsValue::NULL);
+            }
+        }
+    });
+
+    let _ = JsFuture::from(promise).await;
+}
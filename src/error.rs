@@ -0,0 +1,159 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::DomException;
+
+use std::fmt;
+
+
+#[wasm_bindgen(module = "/js/errors.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = createError)]
+    fn create_error(code: &str, message: &str, errors: &JsValue) -> JsValue;
+}
+
+
+// The errors surfaced to JavaScript, each becomes an instance of the matching class in js/errors.js
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    NotFound(String),
+    DuplicateQuad(String),
+    Storage(String),
+    InvalidInput(String),
+    // the index and the reason of every malformed quad in a batch
+    InvalidQuads(Vec<(usize, String)>),
+    // the line and the reason of every row of a table that could not be imported
+    InvalidRows(Vec<(usize, String)>),
+    QuotaExceeded(String),
+    Aborted(String),
+    // the database is open in another tab with an older version
+    Blocked(String),
+    // the connection was closed because another tab upgraded the database
+    VersionChange(String),
+    // an operation the graph can not perform, such as a synchronous write to an IndexedDB graph
+    Unsupported(String),
+}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "NOT_FOUND",
+            Error::DuplicateQuad(_) => "DUPLICATE_QUAD",
+            Error::Storage(_) => "STORAGE",
+            Error::InvalidInput(_) => "INVALID_INPUT",
+            Error::InvalidQuads(_) => "INVALID_INPUT",
+            Error::InvalidRows(_) => "INVALID_INPUT",
+            Error::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+            Error::Aborted(_) => "ABORTED",
+            Error::Blocked(_) => "BLOCKED",
+            Error::VersionChange(_) => "VERSION_CHANGE",
+            Error::Unsupported(_) => "UNSUPPORTED",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::NotFound(m) => m.clone(),
+            Error::DuplicateQuad(m) => m.clone(),
+            Error::Storage(m) => m.clone(),
+            Error::InvalidInput(m) => m.clone(),
+            Error::InvalidQuads(errors) => {
+                let reasons: Vec<String> = errors.iter().map(|(i, reason)| format!("[{}] {}", i, reason)).collect();
+                format!("{} invalid quads: {}", errors.len(), reasons.join(", "))
+            },
+            Error::InvalidRows(errors) => {
+                let reasons: Vec<String> = errors.iter().map(|(line, reason)| format!("line {}: {}", line, reason)).collect();
+                format!("{} invalid rows: {}", errors.len(), reasons.join(", "))
+            },
+            Error::QuotaExceeded(m) => m.clone(),
+            Error::Aborted(m) => m.clone(),
+            Error::Blocked(m) => m.clone(),
+            Error::VersionChange(m) => m.clone(),
+            Error::Unsupported(m) => m.clone(),
+        }
+    }
+
+    // an array of {index, reason} objects for InvalidQuads, {line, reason} objects for InvalidRows, undefined otherwise
+    fn errors(&self) -> JsValue {
+        match self {
+            Error::InvalidQuads(errors) => reasons_to_js("index", errors),
+            Error::InvalidRows(errors) => reasons_to_js("line", errors),
+            _ => JsValue::undefined()
+        }
+    }
+
+    // the errors of QuadWriter and QuadStore::apply_deltas are strings
+    pub fn from_quad_writer(e: String) -> Error {
+        if e.contains("ErrQuadExists") {
+            return Error::DuplicateQuad("Quad already exists".to_string())
+        }
+
+        if e.contains("ErrQuadNotExist") {
+            return Error::NotFound("Quad does not exist".to_string())
+        }
+
+        return Error::Storage(e)
+    }
+
+    // an error raised by IndexedDB, context says what was being done
+    pub fn from_storage(e: &JsValue, context: &str) -> Error {
+        let (name, message) = describe(e, context);
+
+        match name.as_str() {
+            "QuotaExceededError" => Error::QuotaExceeded(message),
+            "BlockedError" => Error::Blocked(message),
+            "VersionChangeError" => Error::VersionChange(message),
+            _ => Error::Storage(message)
+        }
+    }
+
+    // an error raised by a source the caller handed in, such as a stream being loaded
+    pub fn from_source(e: &JsValue, context: &str) -> Error {
+        let (_, message) = describe(e, context);
+        return Error::InvalidInput(message)
+    }
+}
+
+
+// the name of a thrown JS error and its message prefixed with the context
+fn describe(e: &JsValue, context: &str) -> (String, String) {
+    let (name, message) = if let Some(e) = e.dyn_ref::<DomException>() {
+        (e.name(), e.message())
+    } else if let Some(e) = e.dyn_ref::<js_sys::Error>() {
+        (String::from(e.name()), String::from(e.message()))
+    } else {
+        (String::new(), e.as_string().unwrap_or_default())
+    };
+
+    let message = if name.is_empty() {
+        format!("{}: {}", context, message)
+    } else {
+        format!("{}: {}: {}", context, name, message)
+    };
+
+    return (name, message)
+}
+
+
+pub fn reasons_to_js(key: &str, errors: &[(usize, String)]) -> JsValue {
+    let arr = js_sys::Array::new();
+    for (i, reason) in errors {
+        let obj = js_sys::Object::new();
+        let _ = js_sys::Reflect::set(&obj, &JsValue::from_str(key), &JsValue::from_f64(*i as f64));
+        let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("reason"), &JsValue::from_str(reason));
+        arr.push(&obj);
+    }
+    arr.into()
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl From<Error> for JsValue {
+    fn from(e: Error) -> JsValue {
+        create_error(e.code(), &e.message(), &e.errors())
+    }
+}
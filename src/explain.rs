@@ -0,0 +1,61 @@
+use wasm_bindgen::prelude::*;
+
+use gizmo_db::graph::iterator::Shape;
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+// Describes the iterator tree of a query before and after it is optimized:
+//
+//   {
+//     unoptimized: { type: "And", size: 12, exact: false, nextCost: 2, containsCost: 1, children: [...] },
+//     optimized: { ... }
+//   }
+//
+// The sizes and costs are the estimates of stats(), which the optimizer uses to order the iterators.
+// A shape whose stats fail has an error instead. build is called once for each form, since optimizing
+// changes the tree it is given.
+pub fn explain(build: &dyn Fn() -> Rc<RefCell<dyn Shape>>) -> JsValue {
+    let unoptimized = build();
+
+    let optimized = build();
+    let replacement = optimized.borrow_mut().optimize();
+    let optimized = replacement.unwrap_or(optimized);
+
+    let obj = js_sys::Object::new();
+    set(&obj, "unoptimized", &describe(&unoptimized));
+    set(&obj, "optimized", &describe(&optimized));
+
+    obj.into()
+}
+
+
+fn describe(shape: &Rc<RefCell<dyn Shape>>) -> JsValue {
+    let obj = js_sys::Object::new();
+
+    let shape_type = shape.borrow_mut().shape_type();
+    set(&obj, "type", &JsValue::from_str(&format!("{:?}", shape_type)));
+
+    let stats = shape.borrow_mut().stats();
+    match stats {
+        Ok(costs) => {
+            set(&obj, "size", &JsValue::from_f64(costs.size.value as f64));
+            set(&obj, "exact", &JsValue::from_bool(costs.size.exact));
+            set(&obj, "nextCost", &JsValue::from_f64(costs.next_cost as f64));
+            set(&obj, "containsCost", &JsValue::from_f64(costs.contains_cost as f64));
+        },
+        Err(e) => set(&obj, "error", &JsValue::from_str(&e))
+    }
+
+    let children = shape.borrow().sub_iterators().unwrap_or_default();
+    let arr: js_sys::Array = children.iter().map(describe).collect();
+    set(&obj, "children", &arr);
+
+    obj.into()
+}
+
+
+fn set(obj: &js_sys::Object, key: &str, value: &JsValue) {
+    let _ = js_sys::Reflect::set(obj, &JsValue::from_str(key), value);
+}
@@ -0,0 +1,402 @@
+use gizmo_db::graph::quad::Quad;
+use gizmo_db::graph::value::Value;
+
+use wasm_bindgen::prelude::*;
+
+use super::term;
+use crate::error::Error;
+use crate::js_to_value;
+
+use std::collections::HashMap;
+
+
+// Turns the rows of a CSV (or TSV) table into quads. The mapping says how:
+// {
+//     delimiter: "," | "\t" | ...,       default ","
+//     header: bool,                      whether the first row names the columns, default true
+//     subject: "<http://example.org/person/{id}>",
+//     columns: {
+//         name: "<name>",
+//         age: { predicate: "<age>", type: "integer" },
+//         homepage: { predicate: "<homepage>", type: "iri" },
+//     },
+//     label: "graph",                    optional column holding the label of the quads of a row
+// }
+// Columns are named by the header, or by their index when there is no header. The {column}
+// placeholders of the subject template are replaced with the cells of the row. Cells are converted
+// like the values given to write, after the type of the column is applied:
+// string (the default), number, integer, boolean, date, iri, or a datatype such as xsd:decimal.
+// A column can also have a lang instead of a type. Empty cells produce no quad.
+//
+// Returns the quads, and the line and the reason of every row that could not be converted.
+pub fn import(text: &str, mapping: &JsValue) -> Result<(Vec<Quad>, Vec<(usize, String)>), Error> {
+    let mapping = Mapping::new(mapping)?;
+
+    let mut rows = parse(text, mapping.delimiter)?.into_iter();
+
+    let names: Vec<String> = if mapping.header {
+        match rows.next() {
+            Some((_, cells)) => cells.into_iter().map(|c| c.trim().to_string()).collect(),
+            None => return Ok((Vec::new(), Vec::new()))
+        }
+    } else {
+        Vec::new()
+    };
+
+    let column_index = |name: &str| -> Option<usize> {
+        if mapping.header {
+            names.iter().position(|n| n == name)
+        } else {
+            name.parse().ok()
+        }
+    };
+
+    // every column the mapping uses has to exist
+    let mut used: Vec<&str> = mapping.columns.iter().map(|c| c.name.as_str()).collect();
+    used.extend(placeholders(&mapping.subject));
+    if let Some(label) = &mapping.label {
+        used.push(label);
+    }
+
+    let mut indexes = HashMap::new();
+    for name in used {
+        match column_index(name) {
+            Some(i) => { indexes.insert(name.to_string(), i); },
+            None => return Err(Error::InvalidInput(format!("Unknown column {:?}", name)))
+        }
+    }
+
+    let mut quads = Vec::new();
+    let mut errors = Vec::new();
+
+    for (line, cells) in rows {
+        // a trailing blank line is not a row
+        if cells.len() == 1 && cells[0].trim().is_empty() {
+            continue
+        }
+
+        let cell = |name: &str| -> String {
+            cells.get(indexes[name]).cloned().unwrap_or_default()
+        };
+
+        match mapping.row(&cell) {
+            Ok(row) => quads.extend(row),
+            Err(reason) => errors.push((line, reason))
+        }
+    }
+
+    Ok((quads, errors))
+}
+
+
+struct Mapping {
+    delimiter: char,
+    header: bool,
+    subject: String,
+    columns: Vec<Column>,
+    label: Option<String>
+}
+
+struct Column {
+    name: String,
+    predicate: Value,
+    kind: Option<String>,
+    lang: Option<String>
+}
+
+impl Mapping {
+    fn new(js: &JsValue) -> Result<Mapping, Error> {
+        if !js.is_object() {
+            return Err(Error::InvalidInput("expected a mapping object".to_string()))
+        }
+
+        let delimiter = match get(js, "delimiter").as_string() {
+            Some(d) => {
+                let mut chars = d.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c != '"' && c != '\n' && c != '\r' => c,
+                    _ => return Err(Error::InvalidInput(format!("Invalid delimiter {:?}", d)))
+                }
+            },
+            None => ','
+        };
+
+        let subject = get(js, "subject").as_string()
+            .ok_or_else(|| Error::InvalidInput("the mapping has no subject template".to_string()))?;
+
+        let columns_js = get(js, "columns");
+        if !columns_js.is_object() {
+            return Err(Error::InvalidInput("the mapping has no columns".to_string()))
+        }
+
+        let mut columns = Vec::new();
+
+        for key in js_sys::Object::keys(columns_js.unchecked_ref()).iter() {
+            let name = key.as_string().unwrap_or_default();
+            let column = get(&columns_js, &name);
+
+            let (predicate, kind, lang) = if let Some(p) = column.as_string() {
+                (p, None, None)
+            } else {
+                let p = get(&column, "predicate").as_string()
+                    .ok_or_else(|| Error::InvalidInput(format!("column {:?} has no predicate", name)))?;
+                (p, get(&column, "type").as_string(), get(&column, "lang").as_string())
+            };
+
+            if let Some(k) = &kind {
+                if !is_known_type(k) {
+                    return Err(Error::InvalidInput(format!("column {:?} has an unknown type {:?}", name, k)))
+                }
+            }
+
+            columns.push(Column {
+                name,
+                predicate: Value::from(predicate),
+                kind,
+                lang
+            });
+        }
+
+        Ok(Mapping {
+            delimiter,
+            header: get(js, "header").as_bool().unwrap_or(true),
+            subject,
+            columns,
+            label: get(js, "label").as_string()
+        })
+    }
+
+    fn row(&self, cell: &dyn Fn(&str) -> String) -> Result<Vec<Quad>, String> {
+        let subject = self.fill(&self.subject, cell)?;
+        let subject = js_to_value(&JsValue::from_str(&subject)).unwrap_or(Value::None);
+
+        let label = match &self.label {
+            Some(name) if !cell(name).is_empty() => js_to_value(&JsValue::from_str(&cell(name))).unwrap_or(Value::None),
+            _ => Value::None
+        };
+
+        let mut quads = Vec::new();
+
+        for column in &self.columns {
+            let text = cell(&column.name);
+
+            if text.is_empty() {
+                continue
+            }
+
+            let object = column.convert(&text).map_err(|reason| format!("column {:?}: {}", column.name, reason))?;
+
+            quads.push(Quad {
+                subject: subject.clone(),
+                predicate: column.predicate.clone(),
+                object,
+                label: label.clone()
+            });
+        }
+
+        Ok(quads)
+    }
+
+    // the template with every {column} replaced by the cell of the row
+    fn fill(&self, template: &str, cell: &dyn Fn(&str) -> String) -> Result<String, String> {
+        let mut out = String::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break
+            };
+
+            let name = &rest[start + 1..end];
+            let value = cell(name);
+
+            if value.is_empty() {
+                return Err(format!("column {:?} of the subject is empty", name))
+            }
+
+            out.push_str(&rest[..start]);
+            escape_iri(value.trim(), &mut out);
+            rest = &rest[end + 1..];
+        }
+
+        out.push_str(rest);
+
+        Ok(out)
+    }
+}
+
+impl Column {
+    fn convert(&self, text: &str) -> Result<Value, String> {
+        let js = match (&self.kind, &self.lang) {
+            (_, Some(lang)) => literal(text, None, Some(lang)),
+            (None, None) => JsValue::from_str(text),
+            (Some(kind), None) => {
+                let trimmed = text.trim();
+
+                match kind.as_str() {
+                    "string" => JsValue::from_str(text),
+                    "number" => match trimmed.parse::<f64>() {
+                        Ok(n) => JsValue::from_f64(n),
+                        Err(_) => return Err(format!("{:?} is not a number", text))
+                    },
+                    // BigInts become the same integer values
+                    "integer" => match term::integer(trimmed) {
+                        Some(n) => return Ok(Value::Number(n)),
+                        None => return Err(format!("{:?} is not an integer", text))
+                    },
+                    "boolean" => match trimmed.to_lowercase().as_str() {
+                        "true" | "1" | "yes" => JsValue::from_bool(true),
+                        "false" | "0" | "no" => JsValue::from_bool(false),
+                        _ => return Err(format!("{:?} is not a boolean", text))
+                    },
+                    "date" => {
+                        let date = js_sys::Date::new(&JsValue::from_str(trimmed));
+                        if date.get_time().is_nan() {
+                            return Err(format!("{:?} is not a date", text))
+                        }
+                        date.into()
+                    },
+                    "iri" => {
+                        let iri = if trimmed.starts_with('<') && trimmed.ends_with('>') {
+                            trimmed.to_string()
+                        } else {
+                            format!("<{}>", trimmed)
+                        };
+                        JsValue::from_str(&iri)
+                    },
+                    datatype => literal(text, Some(datatype), None)
+                }
+            }
+        };
+
+        match js_to_value(&js) {
+            Some(Value::None) | None => Err(format!("{:?} can not be converted", text)),
+            Some(v) => Ok(v)
+        }
+    }
+}
+
+
+fn is_known_type(kind: &str) -> bool {
+    match kind {
+        "string" | "number" | "integer" | "boolean" | "date" | "iri" => true,
+        datatype => datatype.starts_with("xsd:") || (datatype.starts_with('<') && datatype.ends_with('>')) || datatype.contains("://")
+    }
+}
+
+
+// a {literal, datatype, lang} object, see js_object_to_term
+fn literal(value: &str, datatype: Option<&str>, lang: Option<&str>) -> JsValue {
+    let obj = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("literal"), &JsValue::from_str(value));
+    if let Some(d) = datatype {
+        let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("datatype"), &JsValue::from_str(d));
+    }
+    if let Some(l) = lang {
+        let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("lang"), &JsValue::from_str(l));
+    }
+    obj.into()
+}
+
+
+// the names of the {column} placeholders of a template
+fn placeholders(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        match rest[start..].find('}') {
+            Some(end) => {
+                names.push(&rest[start + 1..start + end]);
+                rest = &rest[start + end + 1..];
+            },
+            None => break
+        }
+    }
+
+    names
+}
+
+
+// percent-encodes the characters a cell can not contain inside an IRI
+fn escape_iri(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '\u{0}'..=' ' | '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' | '%' => {
+                out.push_str(&format!("%{:02X}", c as u32));
+            },
+            c => out.push(c)
+        }
+    }
+}
+
+
+fn get(obj: &JsValue, key: &str) -> JsValue {
+    js_sys::Reflect::get(obj, &JsValue::from_str(key)).unwrap_or(JsValue::undefined())
+}
+
+
+// The rows of the table with the line each starts on. Cells can be quoted with ",
+// in which case they can contain the delimiter, line breaks and "" for a quote.
+pub fn parse(text: &str, delimiter: char) -> Result<Vec<(usize, Vec<String>)>, Error> {
+    let mut rows = Vec::new();
+
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut line = 1;
+    let mut row_line = 1;
+
+    let mut chars = text.chars().peekable();
+
+    // a byte order mark is not part of the first cell
+    if chars.peek() == Some(&'\u{feff}') {
+        chars.next();
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if cell.is_empty() => {
+                let start = line;
+
+                loop {
+                    match chars.next() {
+                        Some('"') => {
+                            if chars.peek() == Some(&'"') {
+                                chars.next();
+                                cell.push('"');
+                            } else {
+                                break
+                            }
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            cell.push(c);
+                        },
+                        None => return Err(Error::InvalidInput(format!("line {}: unterminated quoted cell", start)))
+                    }
+                }
+            },
+            c if c == delimiter => {
+                row.push(std::mem::replace(&mut cell, String::new()));
+            },
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' | '\r' => {
+                row.push(std::mem::replace(&mut cell, String::new()));
+                rows.push((row_line, std::mem::replace(&mut row, Vec::new())));
+                line += 1;
+                row_line = line;
+            },
+            c => cell.push(c)
+        }
+    }
+
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push((row_line, row));
+    }
+
+    Ok(rows)
+}
@@ -0,0 +1,70 @@
+use super::subgraph::{Subgraph, attr_type, escape_xml};
+
+
+// GEXF 1.3, the format of Gephi. Edges are labelled with their predicate and have the label
+// of their quad as the graph attribute, every predicate with literal values is a node attribute
+pub fn write(graph: &Subgraph) -> String {
+    let mut out = String::new();
+
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">\n");
+    out.push_str("  <graph mode=\"static\" defaultedgetype=\"directed\">\n");
+
+    out.push_str("    <attributes class=\"node\">\n");
+    for (i, key) in graph.keys.iter().enumerate() {
+        out.push_str(&format!("      <attribute id=\"{}\" title=\"", i));
+        escape_xml(&key.name, &mut out);
+        out.push_str(&format!("\" type=\"{}\"/>\n", attr_type(key.kind)));
+    }
+    out.push_str("    </attributes>\n");
+
+    out.push_str("    <attributes class=\"edge\">\n");
+    out.push_str("      <attribute id=\"graph\" title=\"graph\" type=\"string\"/>\n");
+    out.push_str("    </attributes>\n");
+
+    out.push_str("    <nodes>\n");
+    for (i, node) in graph.nodes.iter().enumerate() {
+        out.push_str(&format!("      <node id=\"n{}\" label=\"", i));
+        escape_xml(&node.label, &mut out);
+
+        if node.attributes.is_empty() {
+            out.push_str("\"/>\n");
+            continue
+        }
+
+        out.push_str("\">\n        <attvalues>\n");
+        for (key, value) in &node.attributes {
+            attvalue(&mut out, &key.to_string(), value);
+        }
+        out.push_str("        </attvalues>\n      </node>\n");
+    }
+    out.push_str("    </nodes>\n");
+
+    out.push_str("    <edges>\n");
+    for (i, edge) in graph.edges.iter().enumerate() {
+        out.push_str(&format!("      <edge id=\"{}\" source=\"n{}\" target=\"n{}\" label=\"", i, edge.source, edge.target));
+        escape_xml(&edge.label, &mut out);
+
+        match &edge.graph {
+            Some(g) => {
+                out.push_str("\">\n        <attvalues>\n");
+                attvalue(&mut out, "graph", g);
+                out.push_str("        </attvalues>\n      </edge>\n");
+            },
+            None => out.push_str("\"/>\n")
+        }
+    }
+    out.push_str("    </edges>\n");
+
+    out.push_str("  </graph>\n");
+    out.push_str("</gexf>\n");
+
+    out
+}
+
+
+fn attvalue(out: &mut String, key: &str, value: &str) {
+    out.push_str(&format!("          <attvalue for=\"{}\" value=\"", key));
+    escape_xml(value, out);
+    out.push_str("\"/>\n");
+}
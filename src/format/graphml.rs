@@ -0,0 +1,52 @@
+use super::subgraph::{Subgraph, attr_type, escape_xml};
+
+
+// GraphML, as read by yEd and Gephi. Nodes and edges have a label, edges also the label of their quad,
+// and every predicate with literal values is a node key
+pub fn write(graph: &Subgraph) -> String {
+    let mut out = String::new();
+
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    out.push_str("  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n");
+    out.push_str("  <key id=\"edge_label\" for=\"edge\" attr.name=\"label\" attr.type=\"string\"/>\n");
+    out.push_str("  <key id=\"graph\" for=\"edge\" attr.name=\"graph\" attr.type=\"string\"/>\n");
+
+    for (i, key) in graph.keys.iter().enumerate() {
+        out.push_str(&format!("  <key id=\"d{}\" for=\"node\" attr.name=\"", i));
+        escape_xml(&key.name, &mut out);
+        out.push_str(&format!("\" attr.type=\"{}\"/>\n", attr_type(key.kind)));
+    }
+
+    out.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+
+    for (i, node) in graph.nodes.iter().enumerate() {
+        out.push_str(&format!("    <node id=\"n{}\">\n", i));
+        data(&mut out, "label", &node.label);
+        for (key, value) in &node.attributes {
+            data(&mut out, &format!("d{}", key), value);
+        }
+        out.push_str("    </node>\n");
+    }
+
+    for (i, edge) in graph.edges.iter().enumerate() {
+        out.push_str(&format!("    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">\n", i, edge.source, edge.target));
+        data(&mut out, "edge_label", &edge.label);
+        if let Some(g) = &edge.graph {
+            data(&mut out, "graph", g);
+        }
+        out.push_str("    </edge>\n");
+    }
+
+    out.push_str("  </graph>\n");
+    out.push_str("</graphml>\n");
+
+    out
+}
+
+
+fn data(out: &mut String, key: &str, value: &str) {
+    out.push_str(&format!("      <data key=\"{}\">", key));
+    escape_xml(value, out);
+    out.push_str("</data>\n");
+}
@@ -0,0 +1,712 @@
+use gizmo_db::graph::quad::Quad;
+use gizmo_db::graph::value::Value;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use super::term::{self, BlankNodes, Term, XSD};
+use crate::error::Error;
+
+use std::collections::HashMap;
+
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+
+// Expands and flattens a JSON-LD document into quads, named graphs become the label of their quads.
+// Only embedded contexts are supported, remote contexts can not be loaded synchronously.
+pub fn import(doc: &JsValue, context: &JsValue) -> Result<Vec<Quad>, Error> {
+    let doc = if let Some(text) = doc.as_string() {
+        js_sys::JSON::parse(&text).map_err(|_| Error::InvalidInput("the JSON-LD document is not valid JSON".to_string()))?
+    } else {
+        doc.clone()
+    };
+
+    let mut ctx = Context::default();
+
+    if !context.is_undefined() && !context.is_null() {
+        ctx = ctx.parse(context)?;
+    }
+
+    let mut importer = Importer {
+        quads: Vec::new(),
+        blank_nodes: BlankNodes::new()
+    };
+
+    if js_sys::Array::is_array(&doc) {
+        for node in js_sys::Array::from(&doc).iter() {
+            importer.node(&node, &ctx, &None)?;
+        }
+    } else if doc.is_object() {
+        let keys = keys(&doc);
+
+        // a document with only a context and a default graph
+        if keys.iter().any(|k| k == "@graph") && keys.iter().all(|k| k == "@graph" || k == "@context") {
+            if let Some(c) = get(&doc, "@context") {
+                ctx = ctx.parse(&c)?;
+            }
+
+            for node in values(&get(&doc, "@graph").unwrap()) {
+                importer.node(&node, &ctx, &None)?;
+            }
+        } else {
+            importer.node(&doc, &ctx, &None)?;
+        }
+    } else {
+        return Err(Error::InvalidInput("a JSON-LD document must be an object or an array".to_string()))
+    }
+
+    Ok(importer.quads)
+}
+
+
+// Compacts the quads into a JSON-LD document using the frame's @context.
+// The @id and @type of the frame select which nodes are included.
+pub fn export(quads: impl Iterator<Item = Quad>, frame: &JsValue) -> Result<JsValue, Error> {
+    let context = if frame.is_object() { get(frame, "@context") } else { None };
+
+    let ctx = match &context {
+        Some(c) => Context::default().parse(c)?,
+        None => Context::default()
+    };
+
+    let frame_ids: Vec<String> = frame_values(frame, "@id").iter().map(|id| ctx.expand_iri(id, false)).collect();
+    let frame_types: Vec<String> = frame_values(frame, "@type").iter().map(|t| ctx.expand_iri(t, true)).collect();
+
+    // the default graph comes first, then the named graphs in the order they were found
+    let mut graphs: Vec<(Option<Term>, Vec<Node>)> = vec![(None, Vec::new())];
+
+    for quad in quads {
+        let (subject, predicate, object) = match (
+            Term::from_value(&quad.subject),
+            Term::from_value(&quad.predicate),
+            Term::from_value(&quad.object)
+        ) {
+            (Some(s), Some(Term::Iri(p)), Some(o)) => (s, p, o),
+            _ => continue
+        };
+
+        let label = Term::from_value(&quad.label);
+
+        let g = match graphs.iter().position(|(l, _)| *l == label) {
+            Some(i) => i,
+            None => {
+                graphs.push((label, Vec::new()));
+                graphs.len() - 1
+            }
+        };
+
+        let nodes = &mut graphs[g].1;
+
+        let n = match nodes.iter().position(|n| n.id == subject) {
+            Some(i) => i,
+            None => {
+                nodes.push(Node { id: subject, properties: Vec::new() });
+                nodes.len() - 1
+            }
+        };
+
+        nodes[n].add(predicate, object);
+    }
+
+    let type_iri = format!("{}type", RDF);
+
+    let selected = |node: &Node| {
+        let id_matches = frame_ids.is_empty() || match &node.id {
+            Term::Iri(iri) => frame_ids.contains(iri),
+            Term::BlankNode(b) => frame_ids.contains(&format!("_:{}", b)),
+            _ => false
+        };
+
+        let type_matches = frame_types.is_empty() || node.properties.iter().any(|(p, objects)| {
+            *p == type_iri && objects.iter().any(|o| match o {
+                Term::Iri(t) => frame_types.contains(t),
+                _ => false
+            })
+        });
+
+        id_matches && type_matches
+    };
+
+    let output = js_sys::Array::new();
+
+    for (label, nodes) in &graphs {
+        let compacted = js_sys::Array::new();
+
+        for node in nodes.iter().filter(|n| selected(n)) {
+            compacted.push(&node.compact(&ctx));
+        }
+
+        match label {
+            None => {
+                for n in compacted.iter() {
+                    output.push(&n);
+                }
+            },
+            Some(l) => {
+                if compacted.length() == 0 {
+                    continue
+                }
+
+                let graph = js_sys::Object::new();
+                set(&graph, "@id", &JsValue::from_str(&ctx.compact_id(l)));
+                set(&graph, "@graph", &compacted);
+                output.push(&graph);
+            }
+        }
+    }
+
+    let doc = js_sys::Object::new();
+
+    if let Some(c) = context {
+        set(&doc, "@context", &c);
+    }
+
+    set(&doc, "@graph", &output);
+
+    Ok(doc.into())
+}
+
+
+#[derive(Clone, Default)]
+struct Definition {
+    id: String,
+    // "@id", "@vocab" or a datatype IRI
+    datatype: Option<String>,
+    language: Option<String>,
+    list: bool
+}
+
+
+#[derive(Clone, Default)]
+struct Context {
+    base: Option<String>,
+    vocab: Option<String>,
+    language: Option<String>,
+    terms: HashMap<String, Definition>
+}
+
+impl Context {
+    // the context after applying a local context, which can be an object, an array of contexts or null
+    fn parse(&self, local: &JsValue) -> Result<Context, Error> {
+        if local.is_null() {
+            return Ok(Context::default())
+        }
+
+        if js_sys::Array::is_array(local) {
+            let mut ctx = self.clone();
+            for c in js_sys::Array::from(local).iter() {
+                ctx = ctx.parse(&c)?;
+            }
+            return Ok(ctx)
+        }
+
+        if local.is_string() {
+            return Err(Error::InvalidInput("remote JSON-LD contexts are not supported".to_string()))
+        }
+
+        if !local.is_object() {
+            return Err(Error::InvalidInput("a JSON-LD context must be an object".to_string()))
+        }
+
+        let mut ctx = self.clone();
+
+        for key in keys(local) {
+            let value = get(local, &key).unwrap_or(JsValue::null());
+
+            match key.as_str() {
+                "@base" => ctx.base = value.as_string(),
+                "@vocab" => ctx.vocab = value.as_string().map(|v| ctx.expand_iri(&v, true)),
+                "@language" => ctx.language = value.as_string(),
+                "@version" | "@protected" => {},
+                _ => {
+                    if value.is_null() {
+                        ctx.terms.remove(&key);
+                    } else if let Some(id) = value.as_string() {
+                        let id = ctx.expand_iri(&id, true);
+                        ctx.terms.insert(key, Definition { id, ..Definition::default() });
+                    } else if value.is_object() {
+                        let id = match get(&value, "@id").and_then(|v| v.as_string()) {
+                            Some(id) => ctx.expand_iri(&id, true),
+                            None => ctx.expand_iri(&key, true)
+                        };
+
+                        let datatype = get(&value, "@type").and_then(|v| v.as_string()).map(|t| {
+                            if t == "@id" || t == "@vocab" { t } else { ctx.expand_iri(&t, true) }
+                        });
+
+                        let language = get(&value, "@language").and_then(|v| v.as_string());
+
+                        let list = get(&value, "@container").and_then(|v| v.as_string()).map(|c| c == "@list").unwrap_or(false);
+
+                        ctx.terms.insert(key, Definition { id, datatype, language, list });
+                    } else {
+                        return Err(Error::InvalidInput(format!("invalid JSON-LD term definition for '{}'", key)))
+                    }
+                }
+            }
+        }
+
+        Ok(ctx)
+    }
+
+    // vocab is true for properties and types, which can be terms or relative to @vocab,
+    // and false for node ids, which are relative to @base
+    fn expand_iri(&self, s: &str, vocab: bool) -> String {
+        if s.starts_with('@') {
+            return s.to_string()
+        }
+
+        if vocab {
+            if let Some(d) = self.terms.get(s) {
+                return d.id.clone()
+            }
+        }
+
+        if let Some(i) = s.find(':') {
+            let (prefix, suffix) = (&s[..i], &s[i + 1..]);
+
+            if prefix == "_" || suffix.starts_with("//") {
+                return s.to_string()
+            }
+
+            if let Some(d) = self.terms.get(prefix) {
+                return format!("{}{}", d.id, suffix)
+            }
+
+            return s.to_string()
+        }
+
+        if vocab {
+            if let Some(v) = &self.vocab {
+                return format!("{}{}", v, s)
+            }
+        } else if let Some(base) = &self.base {
+            let end = base.rfind('/').map(|i| i + 1).unwrap_or(base.len());
+            return format!("{}{}", &base[..end], s)
+        }
+
+        s.to_string()
+    }
+
+    // the shortest term, vocabulary relative or compact IRI for a property or type
+    fn compact_iri(&self, iri: &str) -> String {
+        let mut best: Option<String> = None;
+
+        let mut consider = |candidate: String| {
+            let shorter = match &best {
+                Some(b) => candidate.len() < b.len() || (candidate.len() == b.len() && candidate < *b),
+                None => true
+            };
+            if shorter {
+                best = Some(candidate);
+            }
+        };
+
+        for (term, d) in &self.terms {
+            if d.id == iri && d.datatype.is_none() && d.language.is_none() && !d.list {
+                consider(term.clone());
+            } else if iri.len() > d.id.len() && iri.starts_with(&d.id) && (d.id.ends_with('/') || d.id.ends_with('#')) {
+                let suffix = &iri[d.id.len()..];
+                if !suffix.starts_with("//") {
+                    consider(format!("{}:{}", term, suffix));
+                }
+            }
+        }
+
+        if let Some(v) = &self.vocab {
+            if iri.len() > v.len() && iri.starts_with(v.as_str()) && !iri[v.len()..].contains(':') {
+                consider(iri[v.len()..].to_string());
+            }
+        }
+
+        best.unwrap_or_else(|| iri.to_string())
+    }
+
+    fn compact_id(&self, term: &Term) -> String {
+        match term {
+            Term::Iri(iri) => {
+                let compact = self.compact_iri(iri);
+                // a term can only be used as a property or type
+                if self.terms.contains_key(&compact) { iri.clone() } else { compact }
+            },
+            Term::BlankNode(b) => format!("_:{}", b),
+            Term::Literal { value, .. } => value.clone()
+        }
+    }
+
+    // the term used as the key for a property, and its definition
+    fn compact_property(&self, iri: &str) -> (String, Option<&Definition>) {
+        for (term, d) in &self.terms {
+            if d.id == iri && !d.list {
+                return (term.clone(), Some(d))
+            }
+        }
+
+        (self.compact_iri(iri), None)
+    }
+}
+
+
+struct Importer {
+    quads: Vec<Quad>,
+    blank_nodes: BlankNodes
+}
+
+impl Importer {
+    fn new_blank_node(&mut self) -> Term {
+        self.blank_nodes.fresh()
+    }
+
+    // an expanded IRI, or a blank node of this document if it starts with _:
+    fn iri_term(&self, iri: String) -> Term {
+        if iri.starts_with("_:") {
+            self.blank_nodes.labelled(&iri[2..])
+        } else {
+            Term::Iri(iri)
+        }
+    }
+
+    fn emit(&mut self, subject: &Term, predicate: &str, object: &Term, graph: &Option<Term>) {
+        self.quads.push(Quad {
+            subject: subject.to_value(),
+            predicate: Term::Iri(predicate.to_string()).to_value(),
+            object: object.to_value(),
+            label: graph.as_ref().map(|g| g.to_value()).unwrap_or(Value::None)
+        });
+    }
+
+    // a node object, returns its id
+    fn node(&mut self, obj: &JsValue, ctx: &Context, graph: &Option<Term>) -> Result<Term, Error> {
+        if !obj.is_object() || js_sys::Array::is_array(obj) {
+            return Err(Error::InvalidInput("expected a JSON-LD node object".to_string()))
+        }
+
+        let local;
+        let ctx = match get(obj, "@context") {
+            Some(c) => {
+                local = ctx.parse(&c)?;
+                &local
+            },
+            None => ctx
+        };
+
+        let subject = match get(obj, "@id").and_then(|id| id.as_string()) {
+            Some(id) => self.iri_term(ctx.expand_iri(&id, false)),
+            None => self.new_blank_node()
+        };
+
+        for key in keys(obj) {
+            let value = get(obj, &key).unwrap();
+
+            match key.as_str() {
+                "@context" | "@id" | "@index" => {},
+                "@type" => {
+                    for t in values(&value) {
+                        if let Some(t) = t.as_string() {
+                            let object = self.iri_term(ctx.expand_iri(&t, true));
+                            self.emit(&subject, &format!("{}type", RDF), &object, graph);
+                        }
+                    }
+                },
+                "@graph" => {
+                    let named = Some(subject.clone());
+                    for node in values(&value) {
+                        self.node(&node, ctx, &named)?;
+                    }
+                },
+                k if k.starts_with('@') => {
+                    return Err(Error::InvalidInput(format!("the JSON-LD keyword {} is not supported", k)))
+                },
+                _ => {
+                    let predicate = ctx.expand_iri(&key, true);
+
+                    // properties that do not expand to an IRI are dropped
+                    if !predicate.contains(':') {
+                        continue
+                    }
+
+                    let definition = ctx.terms.get(&key).cloned();
+
+                    if definition.as_ref().map(|d| d.list).unwrap_or(false) {
+                        let list = self.list(&value, ctx, definition.as_ref(), graph)?;
+                        self.emit(&subject, &predicate, &list, graph);
+                        continue
+                    }
+
+                    for v in values(&value) {
+                        if let Some(object) = self.value(&v, ctx, definition.as_ref(), graph)? {
+                            self.emit(&subject, &predicate, &object, graph);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(subject)
+    }
+
+    fn value(&mut self, v: &JsValue, ctx: &Context, definition: Option<&Definition>, graph: &Option<Term>) -> Result<Option<Term>, Error> {
+        let datatype = definition.and_then(|d| d.datatype.clone());
+
+        if v.is_null() || v.is_undefined() {
+            return Ok(None)
+        }
+
+        if let Some(s) = v.as_string() {
+            return Ok(Some(match datatype.as_ref().map(|d| d.as_str()) {
+                Some("@id") => self.iri_term(ctx.expand_iri(&s, false)),
+                Some("@vocab") => self.iri_term(ctx.expand_iri(&s, true)),
+                Some(d) => Term::Literal { value: s, datatype: Some(d.to_string()), lang: None },
+                None => Term::Literal {
+                    value: s,
+                    datatype: None,
+                    lang: definition.and_then(|d| d.language.clone()).or_else(|| ctx.language.clone())
+                }
+            }))
+        }
+
+        if v.as_bool().is_some() || v.as_f64().is_some() {
+            return Ok(Some(native_literal(v, datatype)))
+        }
+
+        if !v.is_object() {
+            return Err(Error::InvalidInput("unsupported JSON-LD value".to_string()))
+        }
+
+        if let Some(value) = get(v, "@value") {
+            let datatype = get(v, "@type").and_then(|t| t.as_string()).map(|t| ctx.expand_iri(&t, true));
+            let lang = get(v, "@language").and_then(|l| l.as_string());
+
+            if let Some(s) = value.as_string() {
+                return Ok(Some(Term::Literal { value: s, datatype, lang }))
+            }
+
+            return Ok(Some(native_literal(&value, datatype)))
+        }
+
+        if let Some(items) = get(v, "@list") {
+            return Ok(Some(self.list(&items, ctx, definition, graph)?))
+        }
+
+        Ok(Some(self.node(v, ctx, graph)?))
+    }
+
+    // an rdf:List of the values, rdf:nil if there are none
+    fn list(&mut self, items: &JsValue, ctx: &Context, definition: Option<&Definition>, graph: &Option<Term>) -> Result<Term, Error> {
+        let mut objects = Vec::new();
+
+        for v in values(items) {
+            if let Some(o) = self.value(&v, ctx, definition, graph)? {
+                objects.push(o);
+            }
+        }
+
+        let mut list = Term::Iri(format!("{}nil", RDF));
+
+        for o in objects.into_iter().rev() {
+            let node = self.new_blank_node();
+            self.emit(&node, &format!("{}first", RDF), &o, graph);
+            self.emit(&node, &format!("{}rest", RDF), &list, graph);
+            list = node;
+        }
+
+        Ok(list)
+    }
+}
+
+
+struct Node {
+    id: Term,
+    properties: Vec<(String, Vec<Term>)>
+}
+
+impl Node {
+    fn add(&mut self, predicate: String, object: Term) {
+        match self.properties.iter_mut().find(|(p, _)| *p == predicate) {
+            Some((_, objects)) => objects.push(object),
+            None => self.properties.push((predicate, vec![object]))
+        }
+    }
+
+    fn compact(&self, ctx: &Context) -> JsValue {
+        let obj = js_sys::Object::new();
+
+        set(&obj, "@id", &JsValue::from_str(&ctx.compact_id(&self.id)));
+
+        let type_iri = format!("{}type", RDF);
+
+        for (predicate, objects) in &self.properties {
+            if *predicate == type_iri && objects.iter().all(|o| match o { Term::Iri(_) => true, _ => false }) {
+                let types: Vec<JsValue> = objects.iter().map(|o| match o {
+                    Term::Iri(t) => JsValue::from_str(&ctx.compact_iri(t)),
+                    _ => JsValue::undefined()
+                }).collect();
+
+                set(&obj, "@type", &single_or_array(types));
+                continue
+            }
+
+            let (key, definition) = ctx.compact_property(predicate);
+
+            let values: Vec<JsValue> = objects.iter().map(|o| compact_value(o, ctx, definition)).collect();
+
+            set(&obj, &key, &single_or_array(values));
+        }
+
+        obj.into()
+    }
+}
+
+
+fn compact_value(term: &Term, ctx: &Context, definition: Option<&Definition>) -> JsValue {
+    let datatype = definition.and_then(|d| d.datatype.as_ref()).map(|d| d.as_str());
+
+    match term {
+        Term::Iri(_) | Term::BlankNode(_) => {
+            let id = ctx.compact_id(term);
+
+            if datatype == Some("@id") {
+                return JsValue::from_str(&id)
+            }
+
+            let obj = js_sys::Object::new();
+            set(&obj, "@id", &JsValue::from_str(&id));
+            obj.into()
+        },
+        Term::Literal { value, datatype: literal_type, lang } => {
+            if let Some(lang) = lang {
+                let obj = js_sys::Object::new();
+                set(&obj, "@value", &JsValue::from_str(value));
+                set(&obj, "@language", &JsValue::from_str(lang));
+                return obj.into()
+            }
+
+            let literal_type = match literal_type {
+                Some(t) => t,
+                None => return JsValue::from_str(value)
+            };
+
+            if datatype == Some(literal_type.as_str()) {
+                return JsValue::from_str(value)
+            }
+
+            // numbers and booleans are written as native JSON values
+            if literal_type.starts_with(XSD) {
+                match &literal_type[XSD.len()..] {
+                    "boolean" => return JsValue::from_bool(value == "true" || value == "1"),
+                    // integers JSON numbers can not hold exactly stay typed strings
+                    "integer" => {
+                        if let Ok(n) = value.parse::<i64>() {
+                            if (-term::MAX_SAFE_INTEGER..=term::MAX_SAFE_INTEGER).contains(&n) {
+                                return JsValue::from_f64(n as f64)
+                            }
+                        }
+                    },
+                    "double" => {
+                        if let Ok(n) = value.parse::<f64>() {
+                            return JsValue::from_f64(n)
+                        }
+                    },
+                    "string" => return JsValue::from_str(value),
+                    _ => {}
+                }
+            }
+
+            let obj = js_sys::Object::new();
+            set(&obj, "@value", &JsValue::from_str(value));
+            set(&obj, "@type", &JsValue::from_str(&ctx.compact_iri(literal_type)));
+            obj.into()
+        }
+    }
+}
+
+
+// a JSON number or boolean, typed with datatype if the term definition has one
+fn native_literal(v: &JsValue, datatype: Option<String>) -> Term {
+    if let Some(b) = v.as_bool() {
+        return Term::Literal {
+            value: b.to_string(),
+            datatype: Some(datatype.unwrap_or_else(|| format!("{}boolean", XSD))),
+            lang: None
+        }
+    }
+
+    let n = v.as_f64().unwrap_or(0.0);
+
+    // beyond 2^53 the number may not be the integer it was written as
+    let (value, xsd_type) = if n.fract() == 0.0 && n.abs() <= term::MAX_SAFE_INTEGER as f64 {
+        (format!("{}", n as i64), "integer")
+    } else {
+        (format!("{:E}", n), "double")
+    };
+
+    Term::Literal {
+        value,
+        datatype: Some(datatype.unwrap_or_else(|| format!("{}{}", XSD, xsd_type))),
+        lang: None
+    }
+}
+
+
+fn single_or_array(values: Vec<JsValue>) -> JsValue {
+    if values.len() == 1 {
+        return values[0].clone()
+    }
+
+    values.into_iter().collect::<js_sys::Array>().into()
+}
+
+
+// the strings of a frame property, which can be a string or an array of strings
+fn frame_values(frame: &JsValue, key: &str) -> Vec<String> {
+    if !frame.is_object() {
+        return Vec::new()
+    }
+
+    match get(frame, key) {
+        Some(v) => values(&v).iter().filter_map(|v| v.as_string()).collect(),
+        None => Vec::new()
+    }
+}
+
+
+// a value or the items of an array or of a {"@set": [...]}
+fn values(v: &JsValue) -> Vec<JsValue> {
+    if js_sys::Array::is_array(v) {
+        return js_sys::Array::from(v).iter().collect()
+    }
+
+    if v.is_object() {
+        if let Some(set) = get(v, "@set") {
+            return values(&set)
+        }
+    }
+
+    vec![v.clone()]
+}
+
+
+// the keys of the properties that have a value, like JSON.stringify a property set to undefined is left out
+fn keys(obj: &JsValue) -> Vec<String> {
+    match obj.dyn_ref::<js_sys::Object>() {
+        Some(o) => js_sys::Object::keys(o).iter()
+            .filter_map(|k| k.as_string())
+            .filter(|k| get(obj, k).is_some())
+            .collect(),
+        None => Vec::new()
+    }
+}
+
+
+fn get(obj: &JsValue, key: &str) -> Option<JsValue> {
+    match js_sys::Reflect::get(obj, &JsValue::from_str(key)) {
+        Ok(v) if !v.is_undefined() => Some(v),
+        _ => None
+    }
+}
+
+
+fn set(obj: &js_sys::Object, key: &str, value: &JsValue) {
+    let _ = js_sys::Reflect::set(obj, &JsValue::from_str(key), value);
+}
@@ -0,0 +1,11 @@
+// Conversions between the graph and other formats: RDF serializations, tables, graph files and snapshots
+
+pub mod term;
+pub mod nquads;
+pub mod turtle;
+pub mod jsonld;
+pub mod snapshot;
+pub mod csv;
+pub mod subgraph;
+pub mod graphml;
+pub mod gexf;
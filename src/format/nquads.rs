@@ -0,0 +1,370 @@
+use gizmo_db::graph::quad::Quad;
+use gizmo_db::graph::value::Value;
+
+use super::term::{Term, XSD};
+use crate::error::Error;
+
+
+// Parses an N-Quads (or N-Triples) document.
+// Like Cayley, any term is accepted in any position, so quads written by gizmo round-trip.
+pub fn parse(text: &str) -> Result<Vec<Quad>, Error> {
+    parse_lines(text, 1)
+}
+
+
+// parses a part of a larger document, whose first line is line first_line of the document
+pub fn parse_lines(text: &str, first_line: usize) -> Result<Vec<Quad>, Error> {
+    let mut quads = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let mut parser = Parser::new(line, first_line + i);
+
+        if let Some(quad) = parser.statement()? {
+            quads.push(quad);
+        }
+    }
+
+    Ok(quads)
+}
+
+
+// a single term in N-Triples form, None if s is anything else
+pub fn parse_term(s: &str) -> Option<Term> {
+    let mut parser = Parser::new(s, 1);
+
+    let term = parser.term().ok()?;
+
+    if parser.at_end() {
+        Some(term)
+    } else {
+        None
+    }
+}
+
+
+pub fn serialize(quads: impl Iterator<Item = Quad>) -> String {
+    let mut out = String::new();
+
+    for quad in quads {
+        write_quad(&quad, &mut out);
+    }
+
+    out
+}
+
+
+// writes one line, quads without a subject, predicate or object are skipped
+pub fn write_quad(quad: &Quad, out: &mut String) -> bool {
+    let terms = (
+        Term::from_value(&quad.subject),
+        Term::from_value(&quad.predicate),
+        Term::from_value(&quad.object),
+    );
+
+    if let (Some(s), Some(p), Some(o)) = terms {
+        write_term(&s, out);
+        out.push(' ');
+        write_term(&p, out);
+        out.push(' ');
+        write_term(&o, out);
+
+        if let Some(l) = Term::from_value(&quad.label) {
+            out.push(' ');
+            write_term(&l, out);
+        }
+
+        out.push_str(" .\n");
+        return true
+    }
+
+    false
+}
+
+
+pub fn write_term(term: &Term, out: &mut String) {
+    match term {
+        Term::Iri(iri) => write_iri(iri, out),
+        Term::BlankNode(label) => {
+            out.push_str("_:");
+            out.push_str(label);
+        },
+        Term::Literal { value, datatype, lang } => {
+            out.push('"');
+            for c in value.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c => out.push(c)
+                }
+            }
+            out.push('"');
+
+            if let Some(lang) = lang {
+                out.push('@');
+                out.push_str(lang);
+            } else if let Some(datatype) = datatype {
+                // xsd:string is the datatype of plain literals
+                if *datatype != format!("{}string", XSD) {
+                    out.push_str("^^");
+                    write_iri(datatype, out);
+                }
+            }
+        }
+    }
+}
+
+
+fn write_iri(iri: &str, out: &mut String) {
+    out.push('<');
+    for c in iri.chars() {
+        match c {
+            '\u{0}'..=' ' | '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' => {
+                out.push_str(&format!("\\u{:04X}", c as u32));
+            },
+            c => out.push(c)
+        }
+    }
+    out.push('>');
+}
+
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize
+}
+
+impl Parser {
+    fn new(text: &str, line: usize) -> Parser {
+        Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+            line
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidInput(format!("line {}, column {}: {}", self.line, self.pos + 1, message))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    // only whitespace or a comment is left
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        match self.peek() {
+            None | Some('#') => true,
+            _ => false
+        }
+    }
+
+    fn statement(&mut self) -> Result<Option<Quad>, Error> {
+        if self.at_end() {
+            return Ok(None)
+        }
+
+        let subject = self.term()?;
+        let predicate = self.term()?;
+        let object = self.term()?;
+
+        self.skip_whitespace();
+
+        let label = if self.peek() == Some('.') {
+            None
+        } else {
+            Some(self.term()?)
+        };
+
+        self.skip_whitespace();
+
+        if self.peek() != Some('.') {
+            return Err(self.error("expected '.'"))
+        }
+        self.pos += 1;
+
+        if !self.at_end() {
+            return Err(self.error("unexpected text after '.'"))
+        }
+
+        Ok(Some(Quad {
+            subject: subject.to_value(),
+            predicate: predicate.to_value(),
+            object: object.to_value(),
+            label: label.map(|l| l.to_value()).unwrap_or(Value::None)
+        }))
+    }
+
+    fn term(&mut self) -> Result<Term, Error> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('<') => Ok(Term::Iri(self.iri()?)),
+            Some('_') => self.blank_node(),
+            Some('"') => self.literal(),
+            Some(c) => Err(self.error(&format!("unexpected '{}'", c))),
+            None => Err(self.error("unexpected end of line"))
+        }
+    }
+
+    fn iri(&mut self) -> Result<String, Error> {
+        self.pos += 1;
+
+        let mut iri = String::new();
+
+        loop {
+            match self.peek() {
+                Some('>') => {
+                    self.pos += 1;
+                    return Ok(iri)
+                },
+                Some('\\') => {
+                    self.pos += 1;
+                    let c = match self.next() {
+                        Some('u') => self.hex(4)?,
+                        Some('U') => self.hex(8)?,
+                        _ => return Err(self.error("invalid escape sequence in IRI"))
+                    };
+                    iri.push(c);
+                },
+                Some(c) if c <= ' ' || c == '<' || c == '"' => {
+                    return Err(self.error(&format!("invalid character {:?} in IRI", c)))
+                },
+                Some(c) => {
+                    self.pos += 1;
+                    iri.push(c);
+                },
+                None => return Err(self.error("unterminated IRI"))
+            }
+        }
+    }
+
+    fn blank_node(&mut self) -> Result<Term, Error> {
+        self.pos += 1;
+
+        if self.peek() != Some(':') {
+            return Err(self.error("expected ':' after '_'"))
+        }
+        self.pos += 1;
+
+        let start = self.pos;
+
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                self.pos += 1;
+            } else {
+                break
+            }
+        }
+
+        // a label can not end with '.', which is the end of the statement instead
+        while self.pos > start && self.chars[self.pos - 1] == '.' {
+            self.pos -= 1;
+        }
+
+        if self.pos == start {
+            return Err(self.error("empty blank node label"))
+        }
+
+        Ok(Term::BlankNode(self.chars[start..self.pos].iter().collect()))
+    }
+
+    fn literal(&mut self) -> Result<Term, Error> {
+        self.pos += 1;
+
+        let mut value = String::new();
+
+        loop {
+            match self.next() {
+                Some('"') => break,
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('f') => '\u{c}',
+                        Some('"') => '"',
+                        Some('\'') => '\'',
+                        Some('\\') => '\\',
+                        Some('u') => self.hex(4)?,
+                        Some('U') => self.hex(8)?,
+                        _ => return Err(self.error("invalid escape sequence in string"))
+                    };
+                    value.push(c);
+                },
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated string"))
+            }
+        }
+
+        let mut datatype = None;
+        let mut lang = None;
+
+        if self.peek() == Some('@') {
+            self.pos += 1;
+
+            let start = self.pos;
+
+            while let Some(c) = self.peek() {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    self.pos += 1;
+                } else {
+                    break
+                }
+            }
+
+            if self.pos == start {
+                return Err(self.error("empty language tag"))
+            }
+
+            lang = Some(self.chars[start..self.pos].iter().collect());
+        } else if self.peek() == Some('^') {
+            self.pos += 1;
+
+            if self.next() != Some('^') || self.peek() != Some('<') {
+                return Err(self.error("expected '^^<' before the datatype"))
+            }
+
+            datatype = Some(self.iri()?);
+        }
+
+        Ok(Term::Literal { value, datatype, lang })
+    }
+
+    fn hex(&mut self, len: usize) -> Result<char, Error> {
+        if self.pos + len > self.chars.len() {
+            return Err(self.error("incomplete unicode escape"))
+        }
+
+        let digits: String = self.chars[self.pos..self.pos + len].iter().collect();
+
+        let c = u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32);
+
+        match c {
+            Some(c) => {
+                self.pos += len;
+                Ok(c)
+            },
+            None => Err(self.error("invalid unicode escape"))
+        }
+    }
+}
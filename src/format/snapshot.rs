@@ -0,0 +1,294 @@
+use gizmo_db::graph::quad::Quad;
+use gizmo_db::graph::value::Value;
+use gizmo_db::graph::number::Number;
+
+use crate::error::Error;
+
+use std::collections::HashMap;
+
+
+// A snapshot is a compact binary copy of every quad in a graph:
+//
+//   magic     "GZSN"
+//   version   1 byte
+//   values    varint count, then each value as a tag byte and its payload
+//   quads     varint count, then the subject, predicate, object and label of each quad
+//             as varint value ids, where 0 is no value and n is the nth value
+//   checksum  CRC-32 of everything before it, 4 bytes little endian
+//
+// varints are LEB128, signed integers are zigzag encoded and strings are a varint length followed by UTF-8
+const MAGIC: &[u8] = b"GZSN";
+const VERSION: u8 = 1;
+
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_UINT: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_IRI: u8 = 6;
+const TAG_STRING: u8 = 7;
+
+
+pub fn write(quads: impl Iterator<Item = Quad>) -> Vec<u8> {
+    // value ids by the encoded value, so equal values share an id without hashing Values
+    let mut ids: HashMap<Vec<u8>, u64> = HashMap::new();
+    let mut values = Vec::new();
+    let mut value_count = 0u64;
+
+    let mut tuples = Vec::new();
+    let mut quad_count = 0u64;
+
+    let mut id = |value: &Value| -> u64 {
+        let encoded = match encode_value(value) {
+            Some(e) => e,
+            None => return 0
+        };
+
+        if let Some(id) = ids.get(&encoded) {
+            return *id
+        }
+
+        value_count += 1;
+        values.extend_from_slice(&encoded);
+        ids.insert(encoded, value_count);
+        value_count
+    };
+
+    for quad in quads {
+        for v in &[&quad.subject, &quad.predicate, &quad.object, &quad.label] {
+            write_varint(&mut tuples, id(v));
+        }
+        quad_count += 1;
+    }
+
+    let mut out = Vec::with_capacity(values.len() + tuples.len() + 32);
+
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    write_varint(&mut out, value_count);
+    out.extend_from_slice(&values);
+    write_varint(&mut out, quad_count);
+    out.extend_from_slice(&tuples);
+
+    let checksum = crc32(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+
+    out
+}
+
+
+pub fn read(bytes: &[u8]) -> Result<Vec<Quad>, Error> {
+    if bytes.len() < MAGIC.len() + 5 || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(Error::InvalidInput("Invalid snapshot: not a gizmo snapshot".to_string()))
+    }
+
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+
+    let mut expected = [0u8; 4];
+    expected.copy_from_slice(checksum);
+
+    if crc32(body) != u32::from_le_bytes(expected) {
+        return Err(Error::InvalidInput("Invalid snapshot: checksum mismatch".to_string()))
+    }
+
+    let version = body[MAGIC.len()];
+    if version != VERSION {
+        return Err(Error::InvalidInput(format!("Invalid snapshot: unsupported version {}", version)))
+    }
+
+    let mut reader = Reader {
+        bytes: body,
+        pos: MAGIC.len() + 1
+    };
+
+    let value_count = reader.varint()?;
+
+    // the count is untrusted, so the vector grows as values are read
+    let mut values = vec![Value::None];
+    for _ in 0..value_count {
+        values.push(reader.value()?);
+    }
+
+    let quad_count = reader.varint()?;
+
+    let mut quads = Vec::new();
+    for _ in 0..quad_count {
+        let mut get = || -> Result<Value, Error> {
+            let id = reader.varint()?;
+            values.get(id as usize).cloned().ok_or_else(|| reader.error("value id out of range"))
+        };
+
+        quads.push(Quad {
+            subject: get()?,
+            predicate: get()?,
+            object: get()?,
+            label: get()?
+        });
+    }
+
+    if reader.pos != body.len() {
+        return Err(reader.error("unexpected data after the quads"))
+    }
+
+    Ok(quads)
+}
+
+
+// None for Value::None, which is written as value id 0
+fn encode_value(value: &Value) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+
+    match value {
+        Value::None => return None,
+        Value::Null => out.push(TAG_NULL),
+        Value::Bool(false) => out.push(TAG_FALSE),
+        Value::Bool(true) => out.push(TAG_TRUE),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                out.push(TAG_INT);
+                write_varint(&mut out, ((i << 1) ^ (i >> 63)) as u64);
+            } else if let Some(u) = n.as_u64() {
+                out.push(TAG_UINT);
+                write_varint(&mut out, u);
+            } else {
+                out.push(TAG_FLOAT);
+                out.extend_from_slice(&n.as_f64().unwrap_or(0.0).to_le_bytes());
+            }
+        },
+        Value::IRI(_) => {
+            let s = value.to_string();
+            let iri = if s.starts_with('<') && s.ends_with('>') && s.len() >= 2 {
+                &s[1..s.len() - 1]
+            } else {
+                &s[..]
+            };
+            out.push(TAG_IRI);
+            write_string(&mut out, iri);
+        },
+        Value::String(s) => {
+            out.push(TAG_STRING);
+            write_string(&mut out, s);
+        }
+    }
+
+    Some(out)
+}
+
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    write_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidInput(format!("Invalid snapshot: byte {}: {}", self.pos, message))
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        match self.bytes.get(self.pos) {
+            Some(b) => {
+                self.pos += 1;
+                Ok(*b)
+            },
+            None => Err(self.error("unexpected end of data"))
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.bytes.len() - self.pos {
+            return Err(self.error("unexpected end of data"))
+        }
+
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut n = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as u64) << shift;
+
+            if b & 0x80 == 0 {
+                return Ok(n)
+            }
+        }
+
+        Err(self.error("varint is too long"))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.varint()? as usize;
+        let bytes = self.take(len)?;
+
+        match std::str::from_utf8(bytes) {
+            Ok(s) => Ok(s.to_string()),
+            Err(_) => Err(self.error("invalid UTF-8"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        match self.byte()? {
+            TAG_NULL => Ok(Value::Null),
+            TAG_FALSE => Ok(Value::Bool(false)),
+            TAG_TRUE => Ok(Value::Bool(true)),
+            TAG_INT => {
+                let z = self.varint()?;
+                Ok(Value::Number(Number::from(((z >> 1) as i64) ^ -((z & 1) as i64))))
+            },
+            TAG_UINT => Ok(Value::Number(Number::from(self.varint()?))),
+            TAG_FLOAT => {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(self.take(8)?);
+                match Number::from_f64(f64::from_le_bytes(b)) {
+                    Some(n) => Ok(Value::Number(n)),
+                    None => Err(self.error("invalid number"))
+                }
+            },
+            TAG_IRI => Ok(Value::from(format!("<{}>", self.string()?))),
+            TAG_STRING => Ok(Value::String(self.string()?)),
+            tag => Err(self.error(&format!("unknown value tag {}", tag)))
+        }
+    }
+}
+
+
+// CRC-32 (IEEE 802.3), the checksum of zip and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+
+    for i in 0..256u32 {
+        let mut c = i;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        table[i as usize] = c;
+    }
+
+    let mut crc = !0u32;
+
+    for b in bytes {
+        crc = table[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
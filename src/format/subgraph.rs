@@ -0,0 +1,236 @@
+use gizmo_db::graph::quad::Quad;
+use gizmo_db::graph::value::Value;
+
+use super::term::{Term, XSD};
+
+use std::collections::HashMap;
+
+
+// The nodes and edges of a part of the graph, for the graph file formats of tools like Gephi and yEd.
+// Quads with a literal object are folded into attributes of their subject, the other quads are edges
+// labelled with their predicate.
+pub struct Subgraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    // the attribute keys, named after the predicates
+    pub keys: Vec<Key>,
+}
+
+pub struct Node {
+    pub label: String,
+    // the key index and the value of each attribute, values of the same key are joined with |
+    pub attributes: Vec<(usize, String)>,
+}
+
+pub struct Edge {
+    pub source: usize,
+    pub target: usize,
+    pub label: String,
+    // the label of the quad
+    pub graph: Option<String>,
+}
+
+pub struct Key {
+    pub name: String,
+    pub kind: KeyType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyType {
+    Long,
+    Double,
+    Boolean,
+    String,
+}
+
+impl Subgraph {
+    // nodes are the nodes of a path, which keeps the edges between them and their attributes.
+    // Without nodes every subject and object of the quads is a node.
+    pub fn new(nodes: Option<Vec<Value>>, quads: impl Iterator<Item = Quad>) -> Subgraph {
+        let mut builder = Builder {
+            graph: Subgraph {
+                nodes: Vec::new(),
+                edges: Vec::new(),
+                keys: Vec::new(),
+            },
+            node_index: HashMap::new(),
+            key_index: HashMap::new(),
+            attributes: Vec::new(),
+        };
+
+        let fixed = nodes.is_some();
+
+        for value in nodes.unwrap_or_default() {
+            if let Some(term) = Term::from_value(&value) {
+                builder.node(&term, true);
+            }
+        }
+
+        for quad in quads {
+            let terms = (Term::from_value(&quad.subject), Term::from_value(&quad.predicate), Term::from_value(&quad.object));
+
+            let (subject, predicate, object) = match terms {
+                (Some(s), Some(p), Some(o)) => (s, p, o),
+                _ => continue
+            };
+
+            let source = match builder.node(&subject, !fixed) {
+                Some(i) => i,
+                None => continue
+            };
+
+            let is_literal = match object {
+                Term::Literal { .. } => true,
+                _ => false
+            };
+
+            // a literal that is itself a node of the path is the target of an edge
+            match builder.node(&object, !fixed && !is_literal) {
+                Some(target) => {
+                    builder.graph.edges.push(Edge {
+                        source,
+                        target,
+                        label: text(&predicate),
+                        graph: Term::from_value(&quad.label).map(|l| text(&l))
+                    });
+                },
+                None if is_literal => builder.attribute(source, &predicate, &object),
+                None => {}
+            }
+        }
+
+        builder.finish()
+    }
+}
+
+
+struct Builder {
+    graph: Subgraph,
+    // nodes by their N-Triples form
+    node_index: HashMap<String, usize>,
+    key_index: HashMap<String, usize>,
+    // the values of each node by key
+    attributes: Vec<HashMap<usize, Vec<String>>>,
+}
+
+impl Builder {
+    // the index of the node, which is added if add is true and it is not there yet
+    fn node(&mut self, term: &Term, add: bool) -> Option<usize> {
+        let id = term.to_ntriples();
+
+        if let Some(i) = self.node_index.get(&id) {
+            return Some(*i)
+        }
+
+        if !add {
+            return None
+        }
+
+        self.graph.nodes.push(Node {
+            label: text(term),
+            attributes: Vec::new()
+        });
+        self.attributes.push(HashMap::new());
+        self.node_index.insert(id, self.graph.nodes.len() - 1);
+
+        Some(self.graph.nodes.len() - 1)
+    }
+
+    fn attribute(&mut self, node: usize, predicate: &Term, object: &Term) {
+        let name = text(predicate);
+        let kind = key_type(object);
+
+        let key = match self.key_index.get(&name) {
+            Some(k) => *k,
+            None => {
+                self.graph.keys.push(Key { name: name.clone(), kind });
+                self.key_index.insert(name, self.graph.keys.len() - 1);
+                self.graph.keys.len() - 1
+            }
+        };
+
+        let values = self.attributes[node].entry(key).or_insert_with(Vec::new);
+        values.push(text(object));
+
+        // several values are joined into a string
+        self.graph.keys[key].kind = if values.len() > 1 {
+            KeyType::String
+        } else {
+            merge(self.graph.keys[key].kind, kind)
+        };
+    }
+
+    fn finish(self) -> Subgraph {
+        let mut graph = self.graph;
+
+        for (node, attrs) in graph.nodes.iter_mut().zip(self.attributes) {
+            let mut attrs: Vec<(usize, String)> = attrs.into_iter().map(|(k, v)| (k, v.join("|"))).collect();
+            attrs.sort_by_key(|(k, _)| *k);
+            node.attributes = attrs;
+        }
+
+        graph
+    }
+}
+
+
+// how a term is shown: the IRI, _:label or the literal's value
+fn text(term: &Term) -> String {
+    match term {
+        Term::Iri(iri) => iri.clone(),
+        Term::BlankNode(label) => format!("_:{}", label),
+        Term::Literal { value, .. } => value.clone()
+    }
+}
+
+
+fn key_type(term: &Term) -> KeyType {
+    if let Term::Literal { datatype: Some(d), lang: None, .. } = term {
+        if d.starts_with(XSD) {
+            return match &d[XSD.len()..] {
+                "integer" => KeyType::Long,
+                "double" => KeyType::Double,
+                "boolean" => KeyType::Boolean,
+                _ => KeyType::String
+            }
+        }
+    }
+
+    KeyType::String
+}
+
+
+fn merge(a: KeyType, b: KeyType) -> KeyType {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (KeyType::Long, KeyType::Double) | (KeyType::Double, KeyType::Long) => KeyType::Double,
+        _ => KeyType::String
+    }
+}
+
+
+// the name GraphML and GEXF give the type of an attribute
+pub fn attr_type(kind: KeyType) -> &'static str {
+    match kind {
+        KeyType::Long => "long",
+        KeyType::Double => "double",
+        KeyType::Boolean => "boolean",
+        KeyType::String => "string",
+    }
+}
+
+
+pub fn escape_xml(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // characters XML 1.0 can not contain at all
+            '\u{0}'..='\u{8}' | '\u{b}' | '\u{c}' | '\u{e}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {},
+            c => out.push(c)
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use gizmo_db::graph::value::Value;
+use gizmo_db::graph::number::Number;
+
+use wasm_bindgen::JsValue;
+
+use super::nquads;
+
+
+pub const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+
+// a datatype IRI, written in full, as <iri> or with the xsd: prefix
+pub fn expand_datatype(datatype: &str) -> String {
+    if datatype.starts_with("xsd:") {
+        return format!("{}{}", XSD, &datatype[4..])
+    }
+
+    if datatype.starts_with('<') && datatype.ends_with('>') && datatype.len() >= 2 {
+        return datatype[1..datatype.len() - 1].to_string()
+    }
+
+    datatype.to_string()
+}
+
+
+// JavaScript numbers are only exact up to 2^53
+pub const MAX_SAFE_INTEGER: i64 = 9007199254740991;
+
+
+// Integral numbers up to 2^53 are stored as integers, so they have the same hash whether they
+// were written as 1, 1.0, 1n or "1"^^xsd:integer. Larger numbers stay floats, which hash differently
+// from the same number written as a BigInt or an xsd:integer literal.
+pub fn number(n: f64) -> Option<Number> {
+    if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER as f64 {
+        return Some(Number::from(n as i64))
+    }
+
+    Number::from_f64(n)
+}
+
+
+// an integer in decimal notation, None if it does not fit in 64 bits
+pub fn integer(s: &str) -> Option<Number> {
+    let s = s.trim();
+    let s = if s.starts_with('+') { &s[1..] } else { s };
+
+    if let Ok(i) = s.parse::<i64>() {
+        return Some(Number::from(i))
+    }
+
+    s.parse::<u64>().ok().map(Number::from)
+}
+
+
+// an xsd:dateTime literal. Values with a time zone are converted to the UTC form of Date.toISOString,
+// so that the stored strings compare and sort in time order
+pub fn date_time(value: &str) -> Term {
+    let mut value = value.trim().to_string();
+
+    if has_zone(&value) {
+        let date = js_sys::Date::new(&JsValue::from_str(&value));
+        if !date.get_time().is_nan() {
+            value = String::from(date.to_iso_string());
+        }
+    }
+
+    Term::Literal {
+        value,
+        datatype: Some(format!("{}dateTime", XSD)),
+        lang: None
+    }
+}
+
+
+// whether an xsd:dateTime value ends with Z or an offset such as +02:00, without one it is a local time
+pub fn has_zone(value: &str) -> bool {
+    value.ends_with('Z') || {
+        let len = value.len();
+        len > 6 && value.is_char_boundary(len - 6) && {
+            let zone = &value[len - 6..];
+            (zone.starts_with('+') || zone.starts_with('-')) && zone.as_bytes()[3] == b':'
+        }
+    }
+}
+
+
+// Dates are stored as strings in their N-Triples form, which compare with plain strings as well.
+// A comparison with a date is combined with this like pattern, which only dates match.
+pub fn date_time_pattern(value: &Value) -> Option<String> {
+    match Term::from_value(value) {
+        Some(Term::Literal { datatype: Some(d), lang: None, .. }) if d == format!("{}dateTime", XSD) => {
+            Some(format!("\"*\"^^<{}>", d))
+        },
+        _ => None
+    }
+}
+
+
+// A plain string as a value. A string starting with "_:" or a quote is stored in its N-Triples form,
+// "_:b0" as "\"_:b0\"", so that it is not read back as a blank node or a literal
+pub fn plain(s: String) -> Value {
+    if s.starts_with("_:") || s.starts_with('"') {
+        return Value::String(Term::Literal { value: s, datatype: None, lang: None }.to_ntriples())
+    }
+
+    Value::String(s)
+}
+
+
+// An RDF term. Values only distinguish IRIs, strings, numbers and booleans, so blank nodes are
+// stored as strings starting with "_:", and literals with a language tag or a datatype other than
+// xsd:string, xsd:integer, xsd:double and xsd:boolean are stored as strings in their N-Triples form,
+// e.g. "chat"@fr or "12"^^xsd:int, which keeps their datatype and lexical form
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Iri(String),
+    BlankNode(String),
+    Literal {
+        value: String,
+        datatype: Option<String>,
+        lang: Option<String>
+    }
+}
+
+impl Term {
+    pub fn to_value(&self) -> Value {
+        match self {
+            Term::Iri(iri) => Value::from(format!("<{}>", iri)),
+            Term::BlankNode(label) => Value::String(format!("_:{}", label)),
+            Term::Literal { value, datatype, lang } => {
+                if lang.is_some() {
+                    return Value::String(self.to_ntriples())
+                }
+
+                let datatype = match datatype {
+                    Some(d) => d,
+                    None => return plain(value.clone())
+                };
+
+                if !datatype.starts_with(XSD) {
+                    return Value::String(self.to_ntriples())
+                }
+
+                match &datatype[XSD.len()..] {
+                    "string" => plain(value.clone()),
+                    "dateTime" => Value::String(date_time(value).to_ntriples()),
+                    "boolean" => match value.trim() {
+                        "true" | "1" => Value::Bool(true),
+                        "false" | "0" => Value::Bool(false),
+                        _ => Value::String(self.to_ntriples())
+                    },
+                    // the datatypes numbers are written back as, any other keeps its datatype and lexical form
+                    "integer" => match integer(value) {
+                        Some(n) => Value::Number(n),
+                        None => Value::String(self.to_ntriples())
+                    },
+                    "double" => match value.trim().parse::<f64>().ok().and_then(number) {
+                        Some(n) => Value::Number(n),
+                        None => Value::String(self.to_ntriples())
+                    },
+                    _ => Value::String(self.to_ntriples())
+                }
+            }
+        }
+    }
+
+    pub fn from_value(value: &Value) -> Option<Term> {
+        match value {
+            Value::None | Value::Null => None,
+            Value::Bool(b) => Some(Term::typed(b.to_string(), "boolean")),
+            Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    return Some(Term::typed(i.to_string(), "integer"))
+                }
+                if let Some(u) = n.as_u64() {
+                    return Some(Term::typed(u.to_string(), "integer"))
+                }
+
+                let f = n.as_f64()?;
+                if f.fract() == 0.0 && f.abs() < 1e15 {
+                    Some(Term::typed((f as i64).to_string(), "integer"))
+                } else {
+                    Some(Term::typed(f.to_string(), "double"))
+                }
+            },
+            Value::IRI(_) => {
+                let s = value.to_string();
+                if s.starts_with('<') && s.ends_with('>') && s.len() >= 2 {
+                    Some(Term::Iri(s[1..s.len() - 1].to_string()))
+                } else {
+                    Some(Term::Iri(s))
+                }
+            },
+            Value::String(s) => {
+                if s.starts_with("_:") && s.len() > 2 {
+                    return Some(Term::BlankNode(s[2..].to_string()))
+                }
+
+                // a literal that was stored in its N-Triples form
+                if s.starts_with('"') {
+                    if let Some(t @ Term::Literal { .. }) = nquads::parse_term(s) {
+                        return Some(t)
+                    }
+                }
+
+                Some(Term::Literal { value: s.clone(), datatype: None, lang: None })
+            }
+        }
+    }
+
+    fn typed(value: String, xsd_type: &str) -> Term {
+        Term::Literal {
+            value,
+            datatype: Some(format!("{}{}", XSD, xsd_type)),
+            lang: None
+        }
+    }
+
+    pub fn to_ntriples(&self) -> String {
+        let mut s = String::new();
+        nquads::write_term(self, &mut s);
+        s
+    }
+}
+
+
+// The blank nodes of one imported document. Blank node labels only identify a node within a document,
+// so each import puts them under its own random scope: _:b0 becomes _:<scope>_b0, and the nodes the
+// import creates for [ ] or lists are _:<scope>-1, _:<scope>-2, ... which no label can collide with.
+pub struct BlankNodes {
+    scope: String,
+    count: usize,
+}
+
+impl BlankNodes {
+    pub fn new() -> BlankNodes {
+        let random = (js_sys::Math::random() * 2f64.powi(52)) as u64;
+
+        BlankNodes {
+            scope: format!("b{:013x}", random),
+            count: 0,
+        }
+    }
+
+    // the node the document labels _:label
+    pub fn labelled(&self, label: &str) -> Term {
+        Term::BlankNode(format!("{}_{}", self.scope, label))
+    }
+
+    pub fn fresh(&mut self) -> Term {
+        self.count += 1;
+        Term::BlankNode(format!("{}-{}", self.scope, self.count))
+    }
+}
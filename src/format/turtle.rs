@@ -0,0 +1,778 @@
+use gizmo_db::graph::quad::Quad;
+use gizmo_db::graph::value::Value;
+
+use super::term::{BlankNodes, Term, XSD};
+use crate::error::Error;
+
+use std::collections::HashMap;
+
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+
+// Parses a Turtle or TriG document, passing every quad to emit as soon as it is read.
+// Triples in a named graph get the graph name as their label. Returns the number of quads.
+pub fn parse<F>(text: &str, base: Option<&str>, emit: F) -> Result<usize, Error>
+    where F: FnMut(Quad) -> Result<(), Error> {
+
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+        base: base.map(|b| b.to_string()),
+        prefixes: HashMap::new(),
+        graph: None,
+        blank_nodes: BlankNodes::new(),
+        count: 0,
+        sink: emit
+    };
+
+    parser.document()?;
+
+    Ok(parser.count)
+}
+
+
+struct Parser<F> {
+    chars: Vec<char>,
+    pos: usize,
+    base: Option<String>,
+    prefixes: HashMap<String, String>,
+    // the label of the quads inside a TriG graph block
+    graph: Option<Term>,
+    blank_nodes: BlankNodes,
+    count: usize,
+    sink: F
+}
+
+impl<F> Parser<F> where F: FnMut(Quad) -> Result<(), Error> {
+
+    fn error(&self, message: &str) -> Error {
+        let mut line = 1;
+        let mut column = 1;
+
+        for c in &self.chars[..self.pos.min(self.chars.len())] {
+            if *c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+
+        Error::InvalidInput(format!("line {}, column {}: {}", line, column, message))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        self.skip_whitespace();
+
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c)))
+        }
+
+        self.pos += 1;
+        Ok(())
+    }
+
+    // whitespace, new lines and comments
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while let Some(c) = self.next() {
+                    if c == '\n' {
+                        break
+                    }
+                }
+            } else if c.is_whitespace() {
+                self.pos += 1;
+            } else {
+                break
+            }
+        }
+    }
+
+    // a case insensitive keyword such as PREFIX, which has to be followed by a character that can not be part of a name
+    fn keyword(&mut self, word: &str) -> bool {
+        let len = word.chars().count();
+
+        if self.pos + len > self.chars.len() {
+            return false
+        }
+
+        let found: String = self.chars[self.pos..self.pos + len].iter().collect();
+
+        if !found.eq_ignore_ascii_case(word) {
+            return false
+        }
+
+        if !ends_word(self.peek_at(len)) {
+            return false
+        }
+
+        self.pos += len;
+        true
+    }
+
+    fn document(&mut self) -> Result<(), Error> {
+        loop {
+            self.skip_whitespace();
+
+            if self.peek().is_none() {
+                return Ok(())
+            }
+
+            self.statement()?;
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), Error> {
+        if self.peek() == Some('@') {
+            self.pos += 1;
+
+            if self.keyword("prefix") {
+                self.prefix()?;
+            } else if self.keyword("base") {
+                self.base()?;
+            } else {
+                return Err(self.error("expected @prefix or @base"))
+            }
+
+            return self.expect('.')
+        }
+
+        if self.keyword("PREFIX") {
+            return self.prefix()
+        }
+
+        if self.keyword("BASE") {
+            return self.base()
+        }
+
+        if self.keyword("GRAPH") {
+            let label = self.term(false)?;
+            return self.graph_block(label)
+        }
+
+        if self.peek() == Some('{') {
+            return self.block()
+        }
+
+        let subject_is_property_list = self.peek() == Some('[');
+
+        let subject = self.term(false)?;
+
+        self.skip_whitespace();
+
+        // TriG: a graph name followed by its block
+        if self.peek() == Some('{') {
+            return self.graph_block(subject)
+        }
+
+        self.triples(subject, subject_is_property_list)?;
+
+        self.expect('.')
+    }
+
+    fn prefix(&mut self) -> Result<(), Error> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+
+        while let Some(c) = self.peek() {
+            if is_name_char(c) {
+                self.pos += 1;
+            } else {
+                break
+            }
+        }
+
+        let name: String = self.chars[start..self.pos].iter().collect();
+
+        self.expect(':')?;
+        self.skip_whitespace();
+
+        if self.peek() != Some('<') {
+            return Err(self.error("expected an IRI"))
+        }
+
+        let iri = self.iri()?;
+
+        self.prefixes.insert(name, iri);
+
+        Ok(())
+    }
+
+    fn base(&mut self) -> Result<(), Error> {
+        self.skip_whitespace();
+
+        if self.peek() != Some('<') {
+            return Err(self.error("expected an IRI"))
+        }
+
+        self.base = Some(self.iri()?);
+
+        Ok(())
+    }
+
+    fn graph_block(&mut self, label: Term) -> Result<(), Error> {
+        if let Term::Literal { .. } = label {
+            return Err(self.error("a graph name can not be a literal"))
+        }
+
+        let outer = self.graph.replace(label);
+        let res = self.block();
+        self.graph = outer;
+        res
+    }
+
+    // the triples between { and }, the last '.' is optional
+    fn block(&mut self) -> Result<(), Error> {
+        self.expect('{')?;
+
+        loop {
+            self.skip_whitespace();
+
+            if self.peek() == Some('}') {
+                self.pos += 1;
+                return Ok(())
+            }
+
+            let subject_is_property_list = self.peek() == Some('[');
+            let subject = self.term(false)?;
+
+            self.triples(subject, subject_is_property_list)?;
+
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some('.') => self.pos += 1,
+                Some('}') => {},
+                _ => return Err(self.error("expected '.' or '}'"))
+            }
+        }
+    }
+
+    fn triples(&mut self, subject: Term, subject_is_property_list: bool) -> Result<(), Error> {
+        self.skip_whitespace();
+
+        // [ :p :o ] . is a complete statement
+        if subject_is_property_list {
+            if let Some('.') | Some('}') = self.peek() {
+                return Ok(())
+            }
+        }
+
+        self.predicate_object_list(&subject)
+    }
+
+    fn predicate_object_list(&mut self, subject: &Term) -> Result<(), Error> {
+        loop {
+            let predicate = self.predicate()?;
+
+            self.object_list(subject, &predicate)?;
+
+            self.skip_whitespace();
+
+            if self.peek() != Some(';') {
+                return Ok(())
+            }
+
+            while self.peek() == Some(';') {
+                self.pos += 1;
+                self.skip_whitespace();
+            }
+
+            // a trailing ';'
+            if let None | Some('.') | Some(']') | Some('}') = self.peek() {
+                return Ok(())
+            }
+        }
+    }
+
+    fn object_list(&mut self, subject: &Term, predicate: &Term) -> Result<(), Error> {
+        loop {
+            let object = self.term(true)?;
+
+            self.emit(subject, predicate, &object)?;
+
+            self.skip_whitespace();
+
+            if self.peek() != Some(',') {
+                return Ok(())
+            }
+
+            self.pos += 1;
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Term, Error> {
+        self.skip_whitespace();
+
+        if self.peek() == Some('a') && ends_word(self.peek_at(1)) {
+            self.pos += 1;
+            return Ok(Term::Iri(format!("{}type", RDF)))
+        }
+
+        match self.term(false)? {
+            Term::Iri(iri) => Ok(Term::Iri(iri)),
+            _ => Err(self.error("a predicate must be an IRI"))
+        }
+    }
+
+    fn emit(&mut self, subject: &Term, predicate: &Term, object: &Term) -> Result<(), Error> {
+        let quad = Quad {
+            subject: subject.to_value(),
+            predicate: predicate.to_value(),
+            object: object.to_value(),
+            label: self.graph.as_ref().map(|g| g.to_value()).unwrap_or(Value::None)
+        };
+
+        self.count += 1;
+
+        (self.sink)(quad)
+    }
+
+    fn new_blank_node(&mut self) -> Term {
+        self.blank_nodes.fresh()
+    }
+
+    // a subject, object or graph name, literals are only allowed as objects
+    fn term(&mut self, literal: bool) -> Result<Term, Error> {
+        self.skip_whitespace();
+
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error("unexpected end of input"))
+        };
+
+        let is_literal = c == '"' || c == '\'' || c.is_ascii_digit() || c == '+' || c == '-' ||
+            (c == '.' && self.peek_at(1).map(|c| c.is_ascii_digit()).unwrap_or(false)) ||
+            self.at_word("true") || self.at_word("false");
+
+        if is_literal && !literal {
+            return Err(self.error("a literal is only allowed as an object"))
+        }
+
+        match c {
+            '<' => Ok(Term::Iri(self.iri()?)),
+            '_' if self.peek_at(1) == Some(':') => self.blank_node(),
+            '[' => self.blank_node_property_list(),
+            '(' => self.collection(),
+            '"' | '\'' => self.string_literal(),
+            _ if is_literal => {
+                if self.at_word("true") || self.at_word("false") {
+                    let value = if self.at_word("true") { "true" } else { "false" };
+                    self.pos += value.len();
+                    Ok(Term::Literal { value: value.to_string(), datatype: Some(format!("{}boolean", XSD)), lang: None })
+                } else {
+                    self.number()
+                }
+            },
+            _ => Ok(Term::Iri(self.prefixed_name()?))
+        }
+    }
+
+    fn at_word(&self, word: &str) -> bool {
+        let len = word.chars().count();
+
+        if self.pos + len > self.chars.len() {
+            return false
+        }
+
+        let found: String = self.chars[self.pos..self.pos + len].iter().collect();
+
+        found == word && ends_word(self.peek_at(len))
+    }
+
+    fn iri(&mut self) -> Result<String, Error> {
+        self.pos += 1;
+
+        let mut iri = String::new();
+
+        loop {
+            match self.next() {
+                Some('>') => break,
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('u') => self.hex(4)?,
+                        Some('U') => self.hex(8)?,
+                        _ => return Err(self.error("invalid escape sequence in IRI"))
+                    };
+                    iri.push(c);
+                },
+                Some(c) if c <= ' ' || c == '<' || c == '"' => {
+                    self.pos -= 1;
+                    return Err(self.error(&format!("invalid character {:?} in IRI", c)))
+                },
+                Some(c) => iri.push(c),
+                None => return Err(self.error("unterminated IRI"))
+            }
+        }
+
+        Ok(self.resolve(iri))
+    }
+
+    // resolves a relative IRI against the base, relative IRIs are kept as they are without a base
+    fn resolve(&self, iri: String) -> String {
+        let base = match &self.base {
+            Some(b) => b,
+            None => return iri
+        };
+
+        if has_scheme(&iri) {
+            return iri
+        }
+
+        if iri.is_empty() {
+            return base.clone()
+        }
+
+        if iri.starts_with('#') {
+            let end = base.find('#').unwrap_or(base.len());
+            return format!("{}{}", &base[..end], iri)
+        }
+
+        if iri.starts_with("//") {
+            let end = base.find(':').map(|i| i + 1).unwrap_or(0);
+            return format!("{}{}", &base[..end], iri)
+        }
+
+        if iri.starts_with('/') {
+            // scheme://authority
+            let authority = base.find("//").map(|i| i + 2).unwrap_or(0);
+            let end = base[authority..].find('/').map(|i| i + authority).unwrap_or(base.len());
+            return format!("{}{}", &base[..end], iri)
+        }
+
+        let end = base.rfind('/').map(|i| i + 1).unwrap_or(base.len());
+        format!("{}{}", &base[..end], iri)
+    }
+
+    fn prefixed_name(&mut self) -> Result<String, Error> {
+        let start = self.pos;
+
+        while let Some(c) = self.peek() {
+            if is_name_char(c) {
+                self.pos += 1;
+            } else {
+                break
+            }
+        }
+
+        let prefix: String = self.chars[start..self.pos].iter().collect();
+
+        if self.peek() != Some(':') {
+            self.pos = start;
+            return Err(self.error("expected an IRI, a prefixed name, a blank node or a literal"))
+        }
+
+        self.pos += 1;
+
+        let namespace = match self.prefixes.get(&prefix) {
+            Some(n) => n.clone(),
+            None => {
+                self.pos = start;
+                return Err(self.error(&format!("undefined prefix '{}:'", prefix)))
+            }
+        };
+
+        let mut local = String::new();
+
+        loop {
+            match self.peek() {
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.next() {
+                        Some(c) if "_~.-!$&'()*+,;=/?#@%".contains(c) => local.push(c),
+                        _ => return Err(self.error("invalid escape sequence in prefixed name"))
+                    }
+                },
+                Some('%') => {
+                    let hex: String = self.chars[self.pos..(self.pos + 3).min(self.chars.len())].iter().collect();
+                    if hex.len() != 3 || !hex[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(self.error("invalid percent encoding in prefixed name"))
+                    }
+                    local.push_str(&hex);
+                    self.pos += 3;
+                },
+                Some(c) if is_name_char(c) || c == ':' => {
+                    local.push(c);
+                    self.pos += 1;
+                },
+                _ => break
+            }
+        }
+
+        // a name can not end with '.', which is the end of the statement instead
+        while local.ends_with('.') {
+            local.pop();
+            self.pos -= 1;
+        }
+
+        Ok(format!("{}{}", namespace, local))
+    }
+
+    fn blank_node(&mut self) -> Result<Term, Error> {
+        self.pos += 2;
+
+        let start = self.pos;
+
+        while let Some(c) = self.peek() {
+            if is_name_char(c) {
+                self.pos += 1;
+            } else {
+                break
+            }
+        }
+
+        while self.pos > start && self.chars[self.pos - 1] == '.' {
+            self.pos -= 1;
+        }
+
+        if self.pos == start {
+            return Err(self.error("empty blank node label"))
+        }
+
+        let label: String = self.chars[start..self.pos].iter().collect();
+        Ok(self.blank_nodes.labelled(&label))
+    }
+
+    // [ :p :o ; ... ] is a new blank node with the properties inside the brackets
+    fn blank_node_property_list(&mut self) -> Result<Term, Error> {
+        self.pos += 1;
+
+        let node = self.new_blank_node();
+
+        self.skip_whitespace();
+
+        if self.peek() != Some(']') {
+            self.predicate_object_list(&node)?;
+        }
+
+        self.expect(']')?;
+
+        Ok(node)
+    }
+
+    // ( a b c ) is an rdf:List
+    fn collection(&mut self) -> Result<Term, Error> {
+        self.pos += 1;
+
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            if self.peek() == Some(')') {
+                self.pos += 1;
+                break
+            }
+
+            items.push(self.term(true)?);
+        }
+
+        let first = Term::Iri(format!("{}first", RDF));
+        let rest = Term::Iri(format!("{}rest", RDF));
+
+        let mut list = Term::Iri(format!("{}nil", RDF));
+
+        let nodes: Vec<Term> = items.iter().map(|_| self.new_blank_node()).collect();
+
+        for (node, item) in nodes.iter().zip(items.iter()).rev() {
+            self.emit(node, &first, item)?;
+            self.emit(node, &rest, &list)?;
+            list = node.clone();
+        }
+
+        Ok(list)
+    }
+
+    fn string_literal(&mut self) -> Result<Term, Error> {
+        let quote = self.next().unwrap();
+
+        let long = self.peek() == Some(quote) && self.peek_at(1) == Some(quote);
+
+        if long {
+            self.pos += 2;
+        }
+
+        let mut value = String::new();
+
+        loop {
+            match self.next() {
+                Some(c) if c == quote => {
+                    if !long {
+                        break
+                    }
+                    if self.peek() == Some(quote) && self.peek_at(1) == Some(quote) {
+                        self.pos += 2;
+                        break
+                    }
+                    value.push(c);
+                },
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('f') => '\u{c}',
+                        Some('"') => '"',
+                        Some('\'') => '\'',
+                        Some('\\') => '\\',
+                        Some('u') => self.hex(4)?,
+                        Some('U') => self.hex(8)?,
+                        _ => return Err(self.error("invalid escape sequence in string"))
+                    };
+                    value.push(c);
+                },
+                Some('\n') | Some('\r') if !long => {
+                    return Err(self.error("new line in string, use \"\"\" for strings that span lines"))
+                },
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated string"))
+            }
+        }
+
+        let mut datatype = None;
+        let mut lang = None;
+
+        if self.peek() == Some('@') {
+            self.pos += 1;
+
+            let start = self.pos;
+
+            while let Some(c) = self.peek() {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    self.pos += 1;
+                } else {
+                    break
+                }
+            }
+
+            if self.pos == start {
+                return Err(self.error("empty language tag"))
+            }
+
+            lang = Some(self.chars[start..self.pos].iter().collect());
+        } else if self.peek() == Some('^') && self.peek_at(1) == Some('^') {
+            self.pos += 2;
+
+            datatype = match self.term(false)? {
+                Term::Iri(iri) => Some(iri),
+                _ => return Err(self.error("a datatype must be an IRI"))
+            };
+        }
+
+        Ok(Term::Literal { value, datatype, lang })
+    }
+
+    // integers, decimals and doubles
+    fn number(&mut self) -> Result<Term, Error> {
+        let start = self.pos;
+
+        if let Some('+') | Some('-') = self.peek() {
+            self.pos += 1;
+        }
+
+        let digits = |p: &mut Self| {
+            let from = p.pos;
+            while p.peek().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+                p.pos += 1;
+            }
+            p.pos - from
+        };
+
+        let mut count = digits(self);
+        let mut datatype = "integer";
+
+        // the '.' ending a statement is not part of the number
+        if self.peek() == Some('.') && self.peek_at(1).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            self.pos += 1;
+            count += digits(self);
+            datatype = "decimal";
+        }
+
+        if count == 0 {
+            return Err(self.error("invalid number"))
+        }
+
+        if let Some('e') | Some('E') = self.peek() {
+            self.pos += 1;
+
+            if let Some('+') | Some('-') = self.peek() {
+                self.pos += 1;
+            }
+
+            if digits(self) == 0 {
+                return Err(self.error("invalid exponent"))
+            }
+
+            datatype = "double";
+        }
+
+        Ok(Term::Literal {
+            value: self.chars[start..self.pos].iter().collect(),
+            datatype: Some(format!("{}{}", XSD, datatype)),
+            lang: None
+        })
+    }
+
+    fn hex(&mut self, len: usize) -> Result<char, Error> {
+        if self.pos + len > self.chars.len() {
+            return Err(self.error("incomplete unicode escape"))
+        }
+
+        let digits: String = self.chars[self.pos..self.pos + len].iter().collect();
+
+        match u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32) {
+            Some(c) => {
+                self.pos += len;
+                Ok(c)
+            },
+            None => Err(self.error("invalid unicode escape"))
+        }
+    }
+}
+
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '\u{b7}'
+}
+
+
+// a keyword can be followed by the '.' ending a statement, but not by a character of a prefixed name
+fn ends_word(c: Option<char>) -> bool {
+    match c {
+        Some(c) => !(c.is_alphanumeric() || c == '_' || c == '-' || c == ':'),
+        None => true
+    }
+}
+
+
+fn has_scheme(iri: &str) -> bool {
+    match iri.find(':') {
+        Some(i) => {
+            let scheme = &iri[..i];
+            !scheme.is_empty() &&
+                scheme.chars().next().unwrap().is_ascii_alphabetic() &&
+                scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        },
+        None => false
+    }
+}
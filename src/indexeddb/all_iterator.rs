@@ -3,9 +3,9 @@ use gizmo_db::graph::iterator::{Base, Scanner, Index, Shape, Costs, ShapeType};
 
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
-use super::quadstore::InternalIndexedDb;
+use super::quadstore::{InternalIndexedDb, Primitive, PAGE_SIZE};
 
 use std::sync::Arc;
 
@@ -37,12 +37,13 @@ impl Shape for IndexedDbAllIterator {
 
     fn stats(&mut self) -> Result<Costs, String> {
         let count = self.db.get_count()?;
+        let size = if self.nodes { count.values } else { count.quads };
 
         Ok(Costs {
             contains_cost: 1,
             next_cost: 1,
             size: Size {
-                value: count.total() as i64,
+                value: size as i64,
                 exact: true
             }
         })
@@ -64,10 +65,17 @@ impl Shape for IndexedDbAllIterator {
 
 
 
+// Walks the nodes (or quads) a page at a time in the order of their ids, the way InternalIndexedDb::scan
+// walks the is_quad index with a cursor. Scanner::next and Index::contains are synchronous and can not wait
+// for a cursor request, so unlike scan both iterators read the cache of InternalIndexedDb. It holds every
+// primitive, is updated by each committed batch and read in again after another connection wrote, see
+// InternalIndexedDb::flush. Until then the iterators do not see the writes of other tabs.
 pub struct IndexedDbAllIteratorNext {
     db: Arc<InternalIndexedDb>,
     nodes: bool,
     done: bool,
+    page: VecDeque<Primitive>,
+    last: u64,
     cur: Option<Ref>
 }
 
@@ -78,6 +86,8 @@ impl IndexedDbAllIteratorNext {
             db,
             nodes,
             done: false,
+            page: VecDeque::new(),
+            last: 0,
             cur: None
         }))
     }
@@ -108,66 +118,28 @@ impl Base for IndexedDbAllIteratorNext {
 
 impl Scanner for IndexedDbAllIteratorNext {
     fn next(&mut self) -> bool {
-        
-    //     if self.done {
-    //         return false
-    //     }
-
-    //     // TODO: node and quad primitives should have a different prefix, this would require changing Ref to know if the key is for a value or quad 
-
-    //     let lam = |(_, v):(Box<[u8]>, Box<[u8]>)| {
-    //         match Primitive::decode(&v) {
-    //             Ok(p) => {
-    //                 let is_node = p.is_node();
-
-    //                 if self.nodes && is_node {
-    //                     return Some(p)
-    //                 } else if !self.nodes && !is_node {
-    //                     return Some(p)
-    //                 } 
-    
-    //                 return None
-    //             },
-    //             Err(_) => {
-    //                 // TODO: result() should return Result<Option<>>
-    //                 return None
-    //             }
-    //         }
-    //     };
-
-    //     self.cur = if !self.done && self.cur.is_none() {
-
-    //         self.db.db.iterator(
-    //             IteratorMode::Start
-    //         ).take_while(|(k,_)| {
-    //             !k.is_empty() && k[0] == PRIMITIVE_KEY_PREFIX
-    //         }).filter_map(
-    //             lam
-    //         ).map(|p| {
-    //             p.to_ref(self.nodes).unwrap()
-    //         }).next()
-
-    //     } else {
-
-    //         self.db.db.iterator(
-    //             IteratorMode::From(&primitive_key(self.cur.as_ref().unwrap().k.unwrap() + 1), rocksdb::Direction::Forward)
-    //         ).take_while(|(k,_)| {
-    //             !k.is_empty() && k[0] == PRIMITIVE_KEY_PREFIX
-    //         }).filter_map(
-    //             lam
-    //         ).map(|p| {
-    //             p.to_ref(self.nodes).unwrap()
-    //         }).next()
-
-    //     };
-
-    //     if !self.cur.is_some() {
-    //         self.done = true;
-    //         return false
-    //     }
-
-    //     return true
-        return false
+        if self.done {
+            return false
+        }
+
+        if self.page.is_empty() {
+            self.page = self.db.scan_cached(self.nodes, self.last, PAGE_SIZE).into();
+        }
+
+        self.cur = match self.page.pop_front() {
+            Some(p) => {
+                self.last = p.id;
+                p.to_ref(self.nodes)
+            },
+            None => None
+        };
+
+        if self.cur.is_none() {
+            self.done = true;
+            return false
+        }
+
+        return true
     }
 }
 
@@ -225,20 +197,9 @@ impl Index for IndexedDbAllIteratorContains {
         
         match id {
             Some(i) => {
-                match self.db.get_primitive(i) {
-                    Ok(prim) => {
-                        if let Some(p) = prim {
-                           self.cur = p.to_ref(self.nodes);
-                           return true
-                        }
-                        self.cur = None;
-                        return false  
-                    },
-                    Err(_) => {
-                        // TODO: change impl to return result
-                        return false
-                    }
-                }
+                // a node id is not contained in the quads iterator and vice versa
+                self.cur = self.db.get_primitive(i).and_then(|p| p.to_ref(self.nodes));
+                return self.cur.is_some()
             },
             None => return false
         }
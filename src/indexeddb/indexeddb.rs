@@ -1,8 +1,12 @@
-use crate::{value_to_js, js_to_value_ignore};
+use crate::js_to_value_ignore;
+use crate::format::nquads;
+use crate::format::term::{self, Term, XSD};
 use crate::indexeddb::quadstore;
 use super::{WINDOW, MultiSender, poll_receiver, spawn};
 
 use std::collections::BTreeSet;
+use std::rc::Rc;
+use std::cell::Cell;
 use std::pin::Pin;
 use std::future::Future;
 use std::task::{Poll, Context};
@@ -15,15 +19,19 @@ use web_sys::{
     DomException, 
     IdbTransaction, 
     IdbTransactionMode, 
+    IdbCursor, 
+    IdbCursorDirection, 
     IdbCursorWithValue, 
     IdbObjectStore, 
-    IdbObjectStoreParameters
+    IdbObjectStoreParameters, 
+    BroadcastChannel
 };
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
 
 use gizmo_db::graph::value::Value;
+use gizmo_db::graph::number::Number;
 
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
@@ -104,10 +112,19 @@ impl TransactionFuture {
             })
         };
 
-        let on_abort = Closure::once(move |_event: &JsValue| {
-            // TODO better error handling
-            sender.send(Err(js_sys::Error::new("Transaction aborted").into()));
-        });
+        let on_abort = {
+            let tx = tx.clone();
+
+            Closure::once(move |_event: &JsValue| {
+                // the browser sets the error when it aborted the transaction itself, e.g. with a QuotaExceededError
+                let error = match tx.error() {
+                    Some(error) => error.into(),
+                    None => js_sys::Error::new("Transaction aborted").into()
+                };
+
+                sender.send(Err(error));
+            })
+        };
 
         // TODO use addEventListener ?
         tx.set_oncomplete(Some(on_complete.as_ref().unchecked_ref()));
@@ -213,6 +230,97 @@ struct Fold<A> {
     receiver: oneshot::Receiver<Result<A, JsValue>>,
 }
 
+// where a fold goes after a record
+enum Step<A> {
+    // the next record
+    Next(A),
+    // the first record with a primary key of at least the given one, under the same index key
+    Seek(A, JsValue),
+    // the fold ends with this accumulator
+    Stop(A)
+}
+
+impl<A> Fold<A> where A: 'static {
+    // calls f with each record the cursor request visits and resolves with the final accumulator.
+    // When f or the cursor fails the transaction is aborted, so none of its requests are committed
+    fn new<F>(request: &IdbRequest, initial: A, mut f: F) -> Self
+        where F: FnMut(A, &IdbCursorWithValue) -> Result<A, JsValue> + 'static {
+
+        Fold::steps(request, initial, move |acc, cursor| Ok(Step::Next(f(acc, cursor)?)))
+    }
+
+    // like new, but f decides where the cursor goes next
+    fn steps<F>(request: &IdbRequest, initial: A, mut f: F) -> Self
+        where F: FnMut(A, &IdbCursorWithValue) -> Result<Step<A>, JsValue> + 'static {
+
+        let (sender, receiver) = oneshot::channel();
+
+        let sender = MultiSender::new(sender);
+
+        let mut state = Some(initial);
+
+        let on_success = {
+            let request = request.clone();
+            let sender = sender.clone();
+
+            Closure::wrap(Box::new(move |_event: &JsValue| {
+                let acc = match state.take() {
+                    Some(acc) => acc,
+                    None => return
+                };
+
+                let step = request.result().and_then(|result| {
+                    if result.is_null() {
+                        return Ok(Step::Stop(acc))
+                    }
+
+                    let cursor: IdbCursorWithValue = result.unchecked_into();
+                    let step = f(acc, &cursor)?;
+                    match &step {
+                        Step::Next(_) => cursor.continue_()?,
+                        Step::Seek(_, primary_key) => cursor.continue_primary_key(&cursor.key()?, primary_key)?,
+                        Step::Stop(_) => {}
+                    }
+                    Ok(step)
+                });
+
+                match step {
+                    Ok(Step::Next(acc)) | Ok(Step::Seek(acc, _)) => state = Some(acc),
+                    Ok(Step::Stop(acc)) => sender.send(Ok(acc)),
+                    Err(e) => {
+                        if let Some(tx) = request.transaction() {
+                            let _ = tx.abort();
+                        }
+                        sender.send(Err(e));
+                    }
+                }
+            }) as Box<dyn FnMut(&JsValue)>)
+        };
+
+        let on_error = {
+            let request = request.clone();
+
+            Closure::once(move |_event: &JsValue| {
+                let error = match request.error() {
+                    Ok(Some(e)) => e.into(),
+                    _ => JsValue::from_str("Cursor request failed")
+                };
+                sender.send(Err(error));
+            })
+        };
+
+        request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
+
+        request.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+        Self {
+            _on_success: on_success,
+            _on_error: on_error,
+            receiver,
+        }
+    }
+}
+
 impl<A> Future for Fold<A> {
     type Output = Result<A, JsValue>;
 
@@ -346,36 +454,36 @@ extern "C" {
 
     #[wasm_bindgen(method, getter)]
     pub fn content(this: &Primitive) -> JsValue;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn kind(this: &Primitive) -> JsValue;
 }
 
 impl Primitive {
 
-    fn encode_insert(p:& quadstore::Primitive) -> Primitive {
+    fn encode(p: &quadstore::Primitive) -> Primitive {
         let x = js_sys::Object::new();
 
+        js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("id")), &JsValue::from_f64(p.id as f64)).unwrap();
         js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("hash")), &JsValue::from_str(&p.hash.to_string())).unwrap();
-        js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("refs")), &JsValue::from_f64(p.id as f64)).unwrap();
+        js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("refs")), &JsValue::from_f64(p.refs as f64)).unwrap();
 
         match &p.content {
             quadstore::PrimitiveContent::InternalQuad(q) => {
-                js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("is_quad")), &JsValue::from_bool(true)).unwrap();
+                js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("is_quad")), &JsValue::from_f64(1f64)).unwrap();
                 js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("content")), &InternalQuad::encode(&q)).unwrap();
             },
             quadstore::PrimitiveContent::Value(v) => {
-                js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("is_quad")), &JsValue::from_bool(false)).unwrap();
-                js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("content")), &value_to_js(&v)).unwrap();
+                let (kind, content) = encode_value(v);
+                js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("is_quad")), &JsValue::from_f64(0f64)).unwrap();
+                js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("kind")), &JsValue::from(wasm_bindgen::intern(kind))).unwrap();
+                js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("content")), &JsValue::from_str(&content)).unwrap();
             }
         }
     
         x.unchecked_into()
     }
 
-    fn encode_update(p:& quadstore::Primitive) -> Primitive {
-        let prim = Primitive::encode_insert(p);
-        js_sys::Reflect::set(&prim, &JsValue::from(wasm_bindgen::intern("id")), &JsValue::from_f64(p.id as f64)).unwrap();
-        return prim
-    }
-
     fn decode(p: &Primitive) -> quadstore::Primitive {
         quadstore::Primitive {
             id: p.id().as_f64().unwrap_or(0f64) as u64,
@@ -383,7 +491,11 @@ impl Primitive {
             refs: p.refs().as_f64().unwrap_or(0f64) as u64,
             content: if p.is_quad().is_truthy() {
                 quadstore::PrimitiveContent::InternalQuad(InternalQuad::decode(&p.content().unchecked_into()))
+            } else if let Some(kind) = p.kind().as_string() {
+                let content = p.content().as_string().unwrap_or_default();
+                quadstore::PrimitiveContent::Value(decode_value(&kind, &content).unwrap_or(Value::None))
             } else {
+                // written before version 5, as the value was given to JavaScript
                 quadstore::PrimitiveContent::Value(js_to_value_ignore(&p.content()))
             }
         }
@@ -391,6 +503,79 @@ impl Primitive {
 }
 
 
+// Values are stored as the kind of value and its N-Triples form, which reads back exactly the value
+// that was written. The JavaScript form of a value is for presenting it and does not tell every value apart.
+fn encode_value(value: &Value) -> (&'static str, String) {
+    let literal = |value: String, datatype: Option<&str>| Term::Literal {
+        value,
+        datatype: datatype.map(|d| format!("{}{}", XSD, d)),
+        lang: None
+    }.to_ntriples();
+
+    match value {
+        Value::None => ("none", String::new()),
+        Value::Null => ("null", String::new()),
+        Value::Bool(b) => ("bool", literal(b.to_string(), Some("boolean"))),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                ("integer", literal(i.to_string(), Some("integer")))
+            } else if let Some(u) = n.as_u64() {
+                ("integer", literal(u.to_string(), Some("integer")))
+            } else {
+                // the shortest form that parses back to the same float
+                ("double", literal(format!("{:?}", n.as_f64().unwrap_or(0f64)), Some("double")))
+            }
+        },
+        Value::IRI(_) => ("iri", Term::from_value(value).map(|t| t.to_ntriples()).unwrap_or_default()),
+        Value::String(s) => ("string", literal(s.clone(), None))
+    }
+}
+
+fn decode_value(kind: &str, content: &str) -> Option<Value> {
+    match (kind, nquads::parse_term(content)) {
+        ("none", _) => Some(Value::None),
+        ("null", _) => Some(Value::Null),
+        ("iri", Some(t @ Term::Iri(_))) => Some(t.to_value()),
+        ("bool", Some(Term::Literal { value, .. })) => Some(Value::Bool(value == "true")),
+        ("integer", Some(Term::Literal { value, .. })) => term::integer(&value).map(Value::Number),
+        ("double", Some(Term::Literal { value, .. })) => value.parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number),
+        ("string", Some(Term::Literal { value, .. })) => Some(Value::String(value)),
+        _ => None
+    }
+}
+
+
+#[wasm_bindgen]
+extern "C" {
+    pub type PrimitiveCount;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn values(this: &PrimitiveCount) -> JsValue;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn quads(this: &PrimitiveCount) -> JsValue;
+}
+
+impl PrimitiveCount {
+    fn encode(count: &quadstore::PrimitiveCount) -> PrimitiveCount {
+        let x = js_sys::Object::new();
+
+        js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("key")), &JsValue::from(wasm_bindgen::intern("count"))).unwrap();
+        js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("values")), &JsValue::from_f64(count.values as f64)).unwrap();
+        js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("quads")), &JsValue::from_f64(count.quads as f64)).unwrap();
+
+        x.unchecked_into()
+    }
+
+    fn decode(count: &PrimitiveCount) -> quadstore::PrimitiveCount {
+        quadstore::PrimitiveCount {
+            values: count.values().as_f64().unwrap_or(0f64) as u64,
+            quads: count.quads().as_f64().unwrap_or(0f64) as u64,
+        }
+    }
+}
+
+
 #[wasm_bindgen]
 extern "C" {
     pub type QuadDirection;
@@ -440,13 +625,13 @@ pub struct Read {
 }
 
 impl Read {
-    fn store(&self, name: &str) -> IdbObjectStore {
+    pub fn store(&self, name: &str) -> IdbObjectStore {
         self.tx.object_store(wasm_bindgen::intern(name)).unwrap()
     }
 
     pub fn get_primitive(&self, id: u64) -> impl Future<Output = Result<Option<quadstore::Primitive>, JsValue>> {
-        let store = self.store("primitive");
-        let req = store.get(&JsValue::from_str(&id.to_string())).unwrap();
+        let store = self.store("primitives");
+        let req = store.get(&JsValue::from_f64(id as f64)).unwrap();
         RequestFuture::new(&req, move |value| {
             if value.is_undefined() {
                 return None
@@ -457,7 +642,7 @@ impl Read {
     }
 
     pub fn get_primitive_from_hash(&self, hash: u64) -> impl Future<Output = Result<Option<quadstore::Primitive>, JsValue>> {
-        let store = self.store("primitive");
+        let store = self.store("primitives");
         let index = store.index(wasm_bindgen::intern("hash")).unwrap();
 
         let req = index.get(&JsValue::from_str(&hash.to_string())).unwrap();
@@ -471,6 +656,74 @@ impl Read {
         })
     }
 
+    // reads up to limit nodes (or quads) with an id greater than after, in the order of their ids.
+    // is_quad is stored as 0 or 1 so the cursor can walk the key range of one kind in the is_quad index,
+    // skipping ahead to after by primary key
+    pub fn scan_primitives(&self, nodes: bool, after: u64, limit: usize) -> impl Future<Output = Result<Vec<quadstore::Primitive>, JsValue>> {
+        let req = self.store("primitives").index(wasm_bindgen::intern("is_quad")).and_then(|index| {
+            let range = IdbKeyRange::only(&JsValue::from_f64(if nodes { 0f64 } else { 1f64 }))?;
+            index.open_cursor_with_range(&range)
+        });
+
+        let fold = req.map(|req| Fold::steps(&req, Vec::new(), move |mut acc: Vec<quadstore::Primitive>, cursor| {
+            let id = cursor.primary_key()?.as_f64().unwrap_or(0f64) as u64;
+
+            if id <= after {
+                return Ok(Step::Seek(acc, JsValue::from_f64((after + 1) as f64)))
+            }
+
+            acc.push(Primitive::decode(&cursor.value()?.unchecked_into()));
+
+            if acc.len() >= limit {
+                return Ok(Step::Stop(acc))
+            }
+
+            Ok(Step::Next(acc))
+        }));
+
+        async move { fold?.await }
+    }
+
+    // the highest id of any primitive, 0 when there are none
+    pub fn last_primitive_id(&self) -> impl Future<Output = Result<u64, JsValue>> {
+        let req = self.store("primitives").open_key_cursor_with_range_and_direction(&JsValue::NULL, IdbCursorDirection::Prev);
+
+        let future = req.map(|req| RequestFuture::new(&req, move |value| {
+            if value.is_null() {
+                return 0
+            }
+            let cursor: IdbCursor = value.unchecked_into();
+            cursor.key().ok().and_then(|key| key.as_f64()).unwrap_or(0f64) as u64
+        }));
+
+        async move { future?.await }
+    }
+
+    // counts the records in the is_quad index instead of reading them
+    pub fn count_primitives(&self, nodes: bool) -> impl Future<Output = Result<u64, JsValue>> {
+        let index = self.store("primitives").index(wasm_bindgen::intern("is_quad")).unwrap();
+        let range = IdbKeyRange::only(&JsValue::from_f64(if nodes { 0f64 } else { 1f64 })).unwrap();
+
+        let req = index.count_with_key(&range).unwrap();
+
+        RequestFuture::new(&req, move |value| {
+            value.as_f64().unwrap_or(0f64) as u64
+        })
+    }
+
+    pub fn get_count(&self) -> impl Future<Output = Result<Option<quadstore::PrimitiveCount>, JsValue>> {
+        let store = self.store("meta");
+        let req = store.get(&JsValue::from(wasm_bindgen::intern("count"))).unwrap();
+
+        RequestFuture::new(&req, move |value| {
+            if value.is_undefined() {
+                return None
+            } else {
+                return Some(PrimitiveCount::decode(&value.unchecked_into()));
+            }
+        })
+    }
+
     pub fn get_quad_direction(&self, direction: &Direction, value_id: &u64) -> impl Future<Output = Result<BTreeSet<u64>, JsValue>> {
 
         let from = format!("{}{:0>19}", direction.to_byte(), value_id);
@@ -483,12 +736,22 @@ impl Read {
 
         RequestFuture::new(&req, move |values| {
             let values: js_sys::Array = values.unchecked_into();
-            values.iter().filter_map(|value| {
+            values.iter().map(|value| {
                 let quad_direction: QuadDirection = value.unchecked_into();
-                quad_direction.quad_id().as_string().unwrap_or("0".to_string()).parse::<u64>().ok()
+                quad_direction.quad_id().as_f64().unwrap_or(0f64) as u64
             }).collect()
         })
     }
+
+    pub fn get_all_quad_directions(&self) -> impl Future<Output = Result<Vec<(u64, Direction, u64)>, JsValue>> {
+        let store = self.store("quad_direction");
+        let req = store.get_all().unwrap();
+
+        RequestFuture::new(&req, move |values| {
+            let values: js_sys::Array = values.unchecked_into();
+            values.iter().map(|value| QuadDirection::decode(&value.unchecked_into())).collect()
+        })
+    }
 }
 
 
@@ -498,25 +761,23 @@ pub struct Write {
 }
 
 impl Write {
-    pub fn insert_primitive(&self, record: &quadstore::Primitive) -> Result<u64, String> {
-        match self.store("primitive").add(&Primitive::encode_insert(record)) {
-            Ok(request) => match request.result() {
-                Ok(result) => Ok(result.as_f64().unwrap_or(0f64) as u64),
-                Err(_) => Err("Unable to insert primitive".to_string())
-            }
+    // the id of the record is allocated by the quad store, not by the auto increment key generator
+    pub fn insert_primitive(&self, record: &quadstore::Primitive) -> Result<(), String> {
+        match self.store("primitives").add(&Primitive::encode(record)) {
+            Ok(_) => Ok(()),
             Err(_) => Err("Unable to insert primitive".to_string())
         }
     }
 
     pub fn update_primitive(&self, record: &quadstore::Primitive) -> Result<(), String> {
-        match self.store("primitive").put(&Primitive::encode_update(record)) {
+        match self.store("primitives").put(&Primitive::encode(record)) {
             Ok(_) => Ok(()),
             Err(_) => Err("Unable to update primitive".to_string())
         }
     }
 
     pub fn remove_primitive(&self, key: u64) -> Result<(), String> {
-        match self.store("primitive").delete(&JsValue::from_str(&key.to_string())) {
+        match self.store("primitives").delete(&JsValue::from_f64(key as f64)) {
             Ok(_) => Ok(()),
             Err(_) => Err("Unable to remove primitive".to_string())
         }
@@ -536,6 +797,40 @@ impl Write {
             Err(_) => Err("Unable to remove quad direction".to_string())
         }
     }
+
+    // the layout version the records were written with, stored next to the counts
+    pub fn put_format_version(&self, version: u32) -> Result<(), String> {
+        let x = js_sys::Object::new();
+
+        js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("key")), &JsValue::from(wasm_bindgen::intern("format"))).unwrap();
+        js_sys::Reflect::set(&x, &JsValue::from(wasm_bindgen::intern("version")), &JsValue::from_f64(version as f64)).unwrap();
+
+        match self.store("meta").put(&x) {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Unable to update format version".to_string())
+        }
+    }
+
+    pub fn put_count(&self, count: &quadstore::PrimitiveCount) -> Result<(), String> {
+        match self.store("meta").put(&PrimitiveCount::encode(count)) {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Unable to update count".to_string())
+        }
+    }
+
+    // rewrites every primitive in the current encoding, with the hash of what it decodes to
+    pub fn reencode_primitives(&self) -> impl Future<Output = Result<(), JsValue>> {
+        let req = self.store("primitives").open_cursor();
+
+        let fold = req.map(|req| Fold::new(&req, (), move |_, cursor| {
+            let mut p = Primitive::decode(&cursor.value()?.unchecked_into());
+            p.hash = p.calc_hash();
+            cursor.update(&Primitive::encode(&p))?;
+            Ok(())
+        }));
+
+        async move { fold?.await }
+    }
 }
 
 impl std::ops::Deref for Write {
@@ -575,6 +870,10 @@ impl Upgrade {
         // TODO intern this ?
         self.db.delete_object_store(name).unwrap();
     }
+
+    pub fn has_table(&self, name: &str) -> bool {
+        self.db.object_store_names().contains(name)
+    }
 }
 
 impl std::ops::Deref for Upgrade {
@@ -587,15 +886,114 @@ impl std::ops::Deref for Upgrade {
 }
 
 
+// Callbacks for the events another tab can cause on a connection
+#[derive(Default)]
+pub struct OpenEvents {
+    // another tab still has the database open with an older version, opening waits until it closes.
+    // Without a callback the open fails with a BlockedError instead.
+    pub on_blocked: Option<Box<dyn FnMut(&JsValue)>>,
+    // another tab wants to upgrade the database, the connection is closed before this is called
+    pub on_version_change: Option<Box<dyn FnMut(&JsValue)>>,
+}
+
+
+// Tells the other connections to a database, e.g. in other tabs, that it was written to.
+// A connection that was told so is stale until it takes the flag with take_stale
+#[derive(Debug)]
+pub struct Changes {
+    channel: BroadcastChannel,
+    stale: Rc<Cell<bool>>,
+    _on_message: Closure<dyn FnMut(&JsValue)>,
+}
+
+impl Changes {
+    pub fn open(name: &str) -> Result<Self, JsValue> {
+        let channel = BroadcastChannel::new(&format!("gizmo-db:{}", name))?;
+
+        let stale = Rc::new(Cell::new(false));
+
+        let on_message = {
+            let stale = stale.clone();
+
+            Closure::wrap(Box::new(move |_event: &JsValue| {
+                stale.set(true);
+            }) as Box<dyn FnMut(&JsValue)>)
+        };
+
+        channel.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            channel,
+            stale,
+            _on_message: on_message,
+        })
+    }
+
+    pub fn notify(&self) {
+        let _ = self.channel.post_message(&JsValue::from(wasm_bindgen::intern("write")));
+    }
+
+    pub fn take_stale(&self) -> bool {
+        self.stale.replace(false)
+    }
+
+    pub fn mark_stale(&self) {
+        self.stale.set(true);
+    }
+}
+
+impl Drop for Changes {
+    #[inline]
+    fn drop(&mut self) {
+        self.channel.close();
+    }
+}
+
+
+fn named_error(name: &str, message: &str) -> JsValue {
+    let error = js_sys::Error::new(message);
+    error.set_name(name);
+    error.into()
+}
+
+
 #[derive(Debug)]
 pub struct Db {
     db: IdbDatabase,
+    closed: Rc<Cell<bool>>,
+    _on_version_change: Closure<dyn FnMut(&IdbVersionChangeEvent)>,
 }
 
 impl Db {
+    fn new(db: IdbDatabase, mut on_version_change: Option<Box<dyn FnMut(&JsValue)>>) -> Self {
+        let closed = Rc::new(Cell::new(false));
+
+        let on_version_change = {
+            let db = db.clone();
+            let closed = closed.clone();
+
+            Closure::wrap(Box::new(move |event: &IdbVersionChangeEvent| {
+                // the upgrade in the other tab is blocked until every connection is closed
+                db.close();
+                closed.set(true);
+
+                if let Some(f) = on_version_change.as_mut() {
+                    f(event);
+                }
+            }) as Box<dyn FnMut(&IdbVersionChangeEvent)>)
+        };
+
+        db.set_onversionchange(Some(on_version_change.as_ref().unchecked_ref()));
+
+        Self {
+            db,
+            closed,
+            _on_version_change: on_version_change,
+        }
+    }
+
     // TODO this should actually be u64
-    // TODO handle versionchange event
-    pub fn open<A, F>(name: &str, version: u32, on_upgrade: F) -> impl Future<Output = Result<Self, JsValue>>
+    pub fn open<A, F>(name: &str, version: u32, events: OpenEvents, on_upgrade: F) -> impl Future<Output = Result<Self, JsValue>>
         // TODO remove the 'static from A ?
         where A: Future<Output = Result<(), JsValue>> + 'static,
               F: FnOnce(Upgrade, Option<u32>, u32) -> A + 'static {
@@ -604,6 +1002,8 @@ impl Db {
 
         let sender = MultiSender::new(sender);
 
+        let OpenEvents { on_blocked, on_version_change } = events;
+
         let request = WINDOW.with(|x| x.indexed_db()
             .unwrap()
             .unwrap()
@@ -626,6 +1026,8 @@ impl Db {
                 // TODO test that this always works correctly
                 let complete = TransactionFuture::new(&tx);
 
+                let abort = tx.clone();
+
                 // TODO test this with oldVersion and newVersion
                 let fut = on_upgrade(
                     Upgrade { db, write: Write { read: Read { tx } } },
@@ -640,7 +1042,11 @@ impl Db {
                 );
 
                 spawn(async move {
-                    fut.await?;
+                    // a failed migration must not leave the database at the new version
+                    if let Err(e) = fut.await {
+                        let _ = abort.abort();
+                        return Err(e)
+                    }
                     complete.await?;
                     Ok(())
                 });
@@ -650,9 +1056,12 @@ impl Db {
         let onblocked = {
             let sender = sender.clone();
 
-            Closure::once(move |_event: &JsValue| {
-                // TODO better error handling
-                sender.send(Err(js_sys::Error::new("Database is blocked").into()));
+            Closure::once(move |event: &JsValue| {
+                match on_blocked {
+                    // the request stays pending and succeeds once the other tab closes its connection
+                    Some(mut f) => f(event),
+                    None => sender.send(Err(named_error("BlockedError", "Database is open in another tab with an older version"))),
+                }
             })
         };
 
@@ -662,36 +1071,39 @@ impl Db {
 
         DbOpen {
             future: RequestFuture::new_raw(&request, sender, receiver, move |result| {
-                Self {
-                    db: result.dyn_into().unwrap(),
-                }
+                Self::new(result.dyn_into().unwrap(), on_version_change)
             }),
             _onupgradeneeded: onupgradeneeded,
             _onblocked: onblocked,
         }
     }
 
-    fn transaction(&self, names: &[&str], mode: IdbTransactionMode) -> IdbTransaction {
+    fn transaction(&self, names: &[&str], mode: IdbTransactionMode) -> Result<IdbTransaction, JsValue> {
+        if self.closed.get() {
+            return Err(named_error("VersionChangeError", "Database connection was closed because another tab upgraded the database"))
+        }
+
         // TODO can the names be converted more efficiently ?
         // TODO verify that the names are interned properly when calling JsValue::from
         let names = names.into_iter().map(|x| JsValue::from(wasm_bindgen::intern(*x))).collect::<js_sys::Array>();
 
-        self.db.transaction_with_str_sequence_and_mode(&names, mode).unwrap()
+        self.db.transaction_with_str_sequence_and_mode(&names, mode)
     }
 
     pub fn read<A, B, F>(&self, names: &[&str], f: F) -> impl Future<Output = Result<A, JsValue>>
         where B: Future<Output = Result<A, JsValue>>,
               F: FnOnce(Read) -> B {
 
-        let tx = self.transaction(names, IdbTransactionMode::Readonly);
+        let started = self.transaction(names, IdbTransactionMode::Readonly).map(|tx| {
+            // TODO test that this always works correctly
+            let complete = TransactionFuture::new(&tx);
 
-        // TODO test that this always works correctly
-        let complete = TransactionFuture::new(&tx);
-
-        // TODO should this be inside the async ?
-        let fut = f(Read { tx });
+            // TODO should this be inside the async ?
+            (complete, f(Read { tx }))
+        });
 
         async move {
+            let (complete, fut) = started?;
             let value = fut.await?;
             complete.await?;
             Ok(value)
@@ -702,16 +1114,26 @@ impl Db {
         where B: Future<Output = Result<A, JsValue>>,
               F: FnOnce(Write) -> B {
 
-        let tx = self.transaction(names, IdbTransactionMode::Readwrite);
+        let started = self.transaction(names, IdbTransactionMode::Readwrite).map(|tx| {
+            // TODO test that this always works correctly
+            let complete = TransactionFuture::new(&tx);
 
-        // TODO test that this always works correctly
-        let complete = TransactionFuture::new(&tx);
+            let abort = tx.clone();
 
-        // TODO should this be inside the async ?
-        let fut = f(Write { read: Read { tx } });
+            // TODO should this be inside the async ?
+            (complete, abort, f(Write { read: Read { tx } }))
+        });
 
         async move {
-            let value = fut.await?;
+            let (complete, abort, fut) = started?;
+            let value = match fut.await {
+                Ok(value) => value,
+                Err(e) => {
+                    // roll back the requests that were already made in this transaction
+                    let _ = abort.abort();
+                    return Err(e)
+                }
+            };
             complete.await?;
             Ok(value)
         }
@@ -0,0 +1,231 @@
+use super::indexeddb::{Upgrade, TableOptions};
+use super::quadstore::PrimitiveCount;
+
+use wasm_bindgen::prelude::*;
+
+
+// The version of the database layout, which is also the IndexedDB version the database is opened with.
+// Every layout change adds a step to migrate and increments VERSION.
+pub const VERSION: u32 = 2;
+
+
+// upgrades the layout one version at a time, from old (0 for a new database) to new
+pub async fn migrate(tx: &Upgrade, old: u32, new: u32) -> Result<(), JsValue> {
+    if new > VERSION {
+        return Err(JsValue::from_str(&format!("Unknown database version {}", new)))
+    }
+
+    for version in old..new {
+        match version + 1 {
+            1 => create_tables(tx)?,
+            2 => upgrade_primitives(tx).await?,
+            v => return Err(JsValue::from_str(&format!("No migration to database version {}", v)))
+        }
+    }
+
+    if tx.has_table("meta") {
+        tx.put_format_version(new)?;
+    }
+
+    Ok(())
+}
+
+
+// version 1: primitives with a hash index and the quad direction index
+fn create_tables(tx: &Upgrade) -> Result<(), JsValue> {
+    let object_store = tx.create_table("primitives", &TableOptions {
+        auto_increment: true,
+        key_path: "id".to_string(),
+    }).map_err(|_| "Unable to open database table".to_string())?;
+
+    object_store.create_index_with_str("hash", "hash")
+        .map_err(|_| "Unable to open database table".to_string())?;
+
+    tx.create_table("quad_direction", &TableOptions {
+        auto_increment: false,
+        key_path: "key".to_string()
+    }).map_err(|_| "Unable to open database table")?;
+
+    Ok(())
+}
+
+
+// version 2:
+// - is_quad is stored as 0 or 1 instead of a boolean, which can not be indexed, and indexed
+// - values are stored as their kind and N-Triples form instead of their JavaScript form, and hashed
+//   the way they are decoded, integral numbers as integers
+// - the meta table holds the node and quad counts
+async fn upgrade_primitives(tx: &Upgrade) -> Result<(), JsValue> {
+    tx.store("primitives").create_index_with_str("is_quad", "is_quad")
+        .map_err(|_| "Unable to upgrade database table".to_string())?;
+
+    tx.reencode_primitives().await?;
+
+    tx.create_table("meta", &TableOptions {
+        auto_increment: false,
+        key_path: "key".to_string()
+    }).map_err(|_| "Unable to open database table")?;
+
+    let count = PrimitiveCount {
+        values: tx.count_primitives(true).await?,
+        quads: tx.count_primitives(false).await?
+    };
+
+    tx.put_count(&count)?;
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::indexeddb::{Db, OpenEvents};
+    use super::super::quadstore::{InternalIndexedDb, Primitive, PrimitiveContent};
+    use gizmo_db::graph::value::Value;
+    use gizmo_db::graph::number::Number;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn set(obj: &js_sys::Object, key: &str, value: JsValue) {
+        js_sys::Reflect::set(obj, &JsValue::from_str(key), &value).unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn upgrade_v1_database() {
+        let name = format!("gizmo_migration_test_{}", js_sys::Date::now());
+
+        {
+            let db = Db::open(&name, 1, OpenEvents::default(), |tx, old, new| {
+                async move {
+                    migrate(&tx, old.unwrap_or(0), new).await
+                }
+            }).await.unwrap();
+
+            // nodes and a quad the way version 1 encoded them, 42 was hashed as a float
+            db.write(&["primitives"], |tx| {
+                async move {
+                    let node = js_sys::Object::new();
+                    set(&node, "id", JsValue::from_f64(1f64));
+                    set(&node, "hash", JsValue::from_str("1"));
+                    set(&node, "refs", JsValue::from_f64(1f64));
+                    set(&node, "is_quad", JsValue::from_bool(false));
+                    set(&node, "content", JsValue::from_str("<alice>"));
+                    tx.store("primitives").put(&node)?;
+
+                    let hash = Value::Number(Number::from_f64(42f64).unwrap()).calc_hash();
+
+                    let node = js_sys::Object::new();
+                    set(&node, "id", JsValue::from_f64(2f64));
+                    set(&node, "hash", JsValue::from_str(&hash.to_string()));
+                    set(&node, "refs", JsValue::from_f64(1f64));
+                    set(&node, "is_quad", JsValue::from_bool(false));
+                    set(&node, "content", JsValue::from_f64(42f64));
+                    tx.store("primitives").put(&node)?;
+
+                    let content = js_sys::Object::new();
+                    set(&content, "s", JsValue::from_f64(1f64));
+                    set(&content, "p", JsValue::from_f64(1f64));
+                    set(&content, "o", JsValue::from_f64(2f64));
+                    set(&content, "l", JsValue::from_f64(0f64));
+
+                    let quad = js_sys::Object::new();
+                    set(&quad, "id", JsValue::from_f64(3f64));
+                    set(&quad, "hash", JsValue::from_str("3"));
+                    set(&quad, "refs", JsValue::from_f64(1f64));
+                    set(&quad, "is_quad", JsValue::from_bool(true));
+                    set(&quad, "content", content.into());
+                    tx.store("primitives").put(&quad)?;
+
+                    Ok(())
+                }
+            }).await.unwrap();
+        }
+
+        let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+        assert_eq!(store.get_count().unwrap(), PrimitiveCount { values: 2, quads: 1 });
+        assert_eq!(store.scan(true, 0, 10).await.unwrap().iter().map(|p| p.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(store.scan(false, 0, 10).await.unwrap().iter().map(|p| p.id).collect::<Vec<_>>(), vec![3]);
+
+        let alice = Value::from("<alice>".to_string());
+        assert_eq!(store.get_primitive(1).map(|p| p.content), Some(PrimitiveContent::Value(alice.clone())));
+
+        // every primitive is found by the hash of what it decodes to
+        let quad = store.get_primitive(3).unwrap();
+        assert_eq!(store.get_primitive_from_hash(alice.calc_hash()).map(|p| p.id), Some(1));
+        assert_eq!(store.get_primitive_from_hash(Value::Number(Number::from(42i64)).calc_hash()).map(|p| p.id), Some(2));
+        assert_eq!(store.get_primitive_from_hash(quad.calc_hash()).map(|p| p.id), Some(3));
+    }
+
+    #[wasm_bindgen_test]
+    async fn values_round_trip() {
+        let name = format!("gizmo_values_test_{}", js_sys::Date::now());
+
+        let values = vec![
+            Value::from("<alice>".to_string()),
+            Value::String("<alice>".to_string()),
+            Value::String("\"chat\"@fr".to_string()),
+            Value::String("_:b0".to_string()),
+            Value::Number(Number::from(1234567890123456789i64)),
+            Value::Number(Number::from_f64(0.1).unwrap()),
+            Value::Bool(true),
+        ];
+
+        {
+            let db = Db::open(&name, VERSION, OpenEvents::default(), |tx, old, new| {
+                async move {
+                    migrate(&tx, old.unwrap_or(0), new).await
+                }
+            }).await.unwrap();
+
+            let written = values.clone();
+            db.write(&["primitives"], move |tx| {
+                async move {
+                    for (i, value) in written.into_iter().enumerate() {
+                        let mut p = Primitive::new_value(value);
+                        p.id = i as u64 + 1;
+                        tx.insert_primitive(&p)?;
+                    }
+                    Ok(())
+                }
+            }).await.unwrap();
+        }
+
+        let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+        for (i, value) in values.into_iter().enumerate() {
+            assert_eq!(store.get_primitive(i as u64 + 1).map(|p| p.content), Some(PrimitiveContent::Value(value)));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn upgrade_closes_older_connection() {
+        let name = format!("gizmo_version_change_test_{}", js_sys::Date::now());
+
+        let notified = Rc::new(Cell::new(false));
+
+        let events = OpenEvents {
+            on_blocked: None,
+            on_version_change: Some(Box::new({
+                let notified = notified.clone();
+                move |_| notified.set(true)
+            })),
+        };
+
+        let old = Db::open(&name, 1, events, |tx, old, new| {
+            async move {
+                migrate(&tx, old.unwrap_or(0), new).await
+            }
+        }).await.unwrap();
+
+        // the older connection closes itself, so this upgrade is not blocked
+        let _new = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+        assert!(notified.get());
+        assert!(old.read(&["primitives"], |_| async { Ok(()) }).await.is_err());
+    }
+}
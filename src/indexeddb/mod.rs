@@ -1,6 +1,10 @@
 mod indexeddb;
 mod all_iterator;
 mod quadstore;
+mod migrations;
+
+pub use quadstore::IndexedDb;
+pub use self::indexeddb::OpenEvents;
 
 use wasm_bindgen_futures::spawn_local;
 use std::rc::Rc;
@@ -33,11 +37,11 @@ impl<A> MultiSender<A> {
         }
     }
 
+    // only the first value is sent, a request can still succeed after it was reported as blocked
     pub fn send(&self, value: A) {
-        let _ = self.sender.borrow_mut()
-            .take()
-            .unwrap()
-            .send(value);
+        if let Some(sender) = self.sender.borrow_mut().take() {
+            let _ = sender.send(value);
+        }
     }
 }
 
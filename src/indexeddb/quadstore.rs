@@ -9,196 +9,223 @@ use std::cell::RefCell;
 use std::hash::Hash;
 // use std::io::Cursor;
 // use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt, ByteOrder};
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, BTreeMap, HashMap};
 use std::sync::Arc;
+use std::pin::Pin;
+use std::future::Future;
 
 use super::all_iterator::IndexedDbAllIterator;
-use super::indexeddb::{Db, TableOptions, QuadDirection};
+use super::indexeddb::{Db, Write, Changes, QuadDirection, OpenEvents};
+use super::migrations;
+use crate::AsyncQuadStore;
+use crate::error::Error;
 
-use futures::executor::block_on;
+use futures::lock::Mutex;
 use wasm_bindgen::prelude::*;
 
-pub struct InternalIndexedDb {
-    pub db: Db
-}
-
-const VERSION:i32 = 1;
-
-
-impl InternalIndexedDb {
 
-    fn open(name: &str) -> Result<Self, String> {
-        let db = block_on(
-            Db::open(name, 1, |tx, _old, _new| {
-                async move {
-                    let object_store = tx.create_table("primitives", &TableOptions {
-                        auto_increment: true,
-                        key_path: "id".to_string(),
-                    }).map_err(|_| "Unable to open database table".to_string())?;
+// IndexedDB can only be read asynchronously, but QuadStore and the iterators are synchronous.
+// The cache mirrors the primitives and quad_direction tables so reads can be answered
+// synchronously, while every write is first committed to IndexedDB and then applied to the cache.
+// When another connection writes to the database the cache is read again on the next flush.
+#[derive(Default)]
+struct Cache {
+    primitives: BTreeMap<u64, Primitive>,
+    node_ids: BTreeSet<u64>,
+    quad_ids: BTreeSet<u64>,
+    hashes: HashMap<u64, u64>,
+    quad_direction: HashMap<(u8, u64), BTreeSet<u64>>,
+    count: PrimitiveCount
+}
 
-                    object_store.create_index_with_str("hash", "hash")
-                        .map_err(|_| "Unable to open database table".to_string())?;
+impl Cache {
+    fn apply(&mut self, op: Op) {
+        match op {
+            Op::InsertPrimitive(p) => self.insert_primitive(p),
+            Op::UpdatePrimitive(p) => self.insert_primitive(p),
+            Op::RemovePrimitive(id) => self.remove_primitive(id),
+            Op::InsertQuadDirection(value_id, d, quad_id) => self.insert_quad_direction(value_id, &d, quad_id),
+            Op::RemoveQuadDirection(value_id, d, quad_id) => self.remove_quad_direction(value_id, &d, quad_id),
+        }
+    }
 
-                    tx.create_table("quad_direction", &TableOptions {
-                        auto_increment: false,
-                        key_path: "key".to_string()
-                    }).map_err(|_| "Unable to open database table")?;
+    fn insert_primitive(&mut self, p: Primitive) {
+        if p.is_node() {
+            self.node_ids.insert(p.id);
+        } else {
+            self.quad_ids.insert(p.id);
+        }
+        self.hashes.insert(p.hash, p.id);
+        self.primitives.insert(p.id, p);
+    }
 
-                    Ok(())
-                }
-            })
-        ).map_err(|_| "Unable to open database".to_string())?;
+    fn remove_primitive(&mut self, id: u64) {
+        if let Some(p) = self.primitives.remove(&id) {
+            self.node_ids.remove(&id);
+            self.quad_ids.remove(&id);
+            self.hashes.remove(&p.hash);
+        }
+    }
 
-        Ok(InternalIndexedDb {
-            db
-        })
+    fn recount(&self) -> PrimitiveCount {
+        PrimitiveCount {
+            values: self.node_ids.len() as u64,
+            quads: self.quad_ids.len() as u64
+        }
     }
 
-    // Primitives
+    fn insert_quad_direction(&mut self, value_id: u64, direction: &Direction, quad_id: u64) {
+        self.quad_direction.entry((direction.to_byte(), value_id)).or_insert_with(BTreeSet::new).insert(quad_id);
+    }
 
-    pub fn get_count(&self) -> Result<PrimitiveCount, String> {
-        // TODO: Implement
-        Ok(PrimitiveCount {
-            values: 10,
-            quads: 10
-        })
+    fn remove_quad_direction(&mut self, value_id: u64, direction: &Direction, quad_id: u64) {
+        let key = (direction.to_byte(), value_id);
+        let empty = if let Some(quad_ids) = self.quad_direction.get_mut(&key) {
+            quad_ids.remove(&quad_id);
+            quad_ids.is_empty()
+        } else {
+            false
+        };
+        if empty {
+            self.quad_direction.remove(&key);
+        }
     }
+}
 
 
-    pub fn get_primitive(&self, id: u64) -> Result<Option<Primitive>, String> {
-        let primitive = block_on(
-            self.db.read(&["primitives"], move |tx| {
-                async move {
-                    let primitive = tx.get_primitive(id).await?;
-                    Ok(primitive)
-                }
-            })
-        ).map_err(|_| "Unable to get primitive".to_string())?;
+#[derive(Clone)]
+enum Op {
+    InsertPrimitive(Primitive),
+    UpdatePrimitive(Primitive),
+    RemovePrimitive(u64),
+    InsertQuadDirection(u64, Direction, u64),
+    RemoveQuadDirection(u64, Direction, u64)
+}
 
-        Ok(primitive)
-    }
 
-    pub fn get_primitive_from_hash(&self, hash: u64) -> Result<Option<Primitive>, String> {
-        let primitive = block_on(
-            self.db.read(&["primitives"], move |tx| {
-                async move {
-                    let primitive = tx.get_primitive_from_hash(hash).await?;
-                    Ok(primitive)
-                }
-            })
-        ).map_err(|_| "Unable to get primitive".to_string())?;
+// The changes made by one apply_deltas call. They are resolved against the records read inside the
+// readwrite transaction they are written in, not against the cache, which does not see the writes of
+// other tabs, and applied to the cache once the transaction commits.
+struct Batch {
+    // the records that were read, None for a record that does not exist (anymore)
+    primitives: HashMap<u64, Option<Primitive>>,
+    hashes: HashMap<u64, Option<u64>>,
+    count: PrimitiveCount,
+    last_id: u64,
+    ops: Vec<Op>
+}
 
-        Ok(primitive)
-    }
+impl Batch {
+    // reads the count, the last id and every primitive the deltas can touch: their values and, when all of
+    // those exist, their quads. Deleting a quad only touches the values it was found with
+    async fn read(tx: &Write, deltas: &[Delta]) -> Result<Batch, JsValue> {
+        let mut batch = Batch {
+            primitives: HashMap::new(),
+            hashes: HashMap::new(),
+            count: tx.get_count().await?.unwrap_or_default(),
+            last_id: tx.last_primitive_id().await?,
+            ops: Vec::new()
+        };
 
+        for d in deltas {
+            let mut quad = Some(InternalQuad{s: 0, p: 0, o: 0, l: 0});
 
-    // Only call this method after you have checked that the primitive does not yet exist
-    fn update_primitive(&self, p: &Primitive) -> Result<(), String> {
-        block_on(
-            async move {
-                self.db.write(&["primitives"], move |tx| {
-                    async move {
-                        tx.update_primitive(p);
-                        Ok(())
-                    }
-                }).await.map_err(|_| "Unable to add primitive".to_string())
+            for dir in Direction::iterator() {
+                let v = d.quad.get(dir);
+                if let Value::None = v {
+                    continue
+                }
+                match batch.read_hash(tx, v.calc_hash()).await? {
+                    Some(p) => if let Some(q) = quad.as_mut() { q.set_dir(dir, p.id) },
+                    None => quad = None
+                }
             }
-        ).map_err(|_| "Unable to add primitive".to_string())?;
 
-        Ok(())
-    }
+            if let Some(q) = quad {
+                batch.read_hash(tx, q.calc_hash()).await?;
+            }
+        }
 
+        Ok(batch)
+    }
 
-    // Only call this method after you have checked that the primitive does not yet exist
-    fn add_primitive(&self, p: &mut Primitive) -> Result<(), String> {
-        block_on(
-            async move {
-                self.db.write(&["primitives"], move |tx| {
-                    async move {
-                        let id = tx.insert_primitive(p)?;
-                        p.id = id;
-                        Ok(())
-                    }
-                }).await
+    async fn read_hash(&mut self, tx: &Write, hash: u64) -> Result<Option<Primitive>, JsValue> {
+        if !self.hashes.contains_key(&hash) {
+            match tx.get_primitive_from_hash(hash).await? {
+                Some(p) => {
+                    self.hashes.insert(hash, Some(p.id));
+                    self.primitives.insert(p.id, Some(p));
+                },
+                None => {
+                    self.hashes.insert(hash, None);
+                }
             }
-        ).map_err(|_| "Unable to add primitive".to_string())?;
+        }
 
-        Ok(())
+        Ok(self.get_primitive_from_hash(hash))
     }
 
-    
-    fn remove_primitive(&self, p: &Primitive) -> Result<(), String> {
-        block_on(
-            async move {
-                self.db.write(&["primitives"], move |tx| {
-                    async move {
-                        tx.remove_primitive(p.id)?;
-                        Ok(())
-                    }
-                }).await
-            }
-        ).map_err(|_| "Unable to add primitive".to_string())?;
+    fn get_primitive(&self, id: u64) -> Option<Primitive> {
+        self.primitives.get(&id).cloned().and_then(|p| p)
+    }
 
-        Ok(())
+    fn get_primitive_from_hash(&self, hash: u64) -> Option<Primitive> {
+        self.hashes.get(&hash).cloned().and_then(|id| id).and_then(|id| self.get_primitive(id))
     }
 
-    // Quad Direction Index
+    // Only call this method after you have checked that the primitive does not yet exist
+    fn add_primitive(&mut self, p: &mut Primitive) {
+        self.last_id += 1;
+        p.id = self.last_id;
 
-    fn get_quad_direction(&self, direction: &Direction, value_id: &u64) -> Result<BTreeSet<u64>, String> {
-        let quad_directions = block_on(
-            self.db.read(&["quad_direction"], move |tx| {
-                async move {
-                    let quad_directions = tx.get_quad_direction(direction, value_id).await?;
-                    Ok(quad_directions)
-                }
-            })
-        ).map_err(|_| "Unable to get primitive".to_string())?;
+        if p.is_node() {
+            self.count.increment_values(1);
+        } else {
+            self.count.increment_quads(1);
+        }
 
-        Ok(quad_directions)
+        self.hashes.insert(p.hash, Some(p.id));
+        self.primitives.insert(p.id, Some(p.clone()));
+        self.ops.push(Op::InsertPrimitive(p.clone()));
     }
 
-    fn add_quad_direction(&self, value_id: u64, direction: &Direction, quad_id: u64) -> Result<(), String> {
-        block_on(
-            async move {
-                self.db.write(&["quad_direction"], move |tx| {
-                    async move {
-                        tx.insert_quad_direction(value_id, direction, quad_id)?;
-                        Ok(())
-                    }
-                }).await
-            }
-        ).map_err(|_| "Unable to add primitive".to_string())?;
+    fn update_primitive(&mut self, p: &Primitive) {
+        self.primitives.insert(p.id, Some(p.clone()));
+        self.ops.push(Op::UpdatePrimitive(p.clone()));
+    }
 
-        Ok(())
+    fn remove_primitive(&mut self, p: &Primitive) {
+        if p.is_node() {
+            self.count.increment_values(-1);
+        } else {
+            self.count.increment_quads(-1);
+        }
+
+        self.hashes.insert(p.hash, None);
+        self.primitives.insert(p.id, None);
+        self.ops.push(Op::RemovePrimitive(p.id));
     }
 
-    fn remove_quad_direction(&self, value_id: u64, direction: &Direction, quad_id: u64) -> Result<(), String> {
-        block_on(
-            async move {
-                self.db.write(&["quad_direction"], move |tx| {
-                    async move {
-                        let id = tx.remove_quad_direction(value_id, direction, quad_id);
-                        Ok(id)
-                    }
-                }).await.map_err(|_| "Unable to add quad_direction".to_string())?
-            }
-        ).map_err(|_| "Unable to add quad_direction".to_string())?;
+    fn add_quad_direction(&mut self, value_id: u64, direction: &Direction, quad_id: u64) {
+        self.ops.push(Op::InsertQuadDirection(value_id, direction.clone(), quad_id));
+    }
 
-        Ok(())
+    fn remove_quad_direction(&mut self, value_id: u64, direction: &Direction, quad_id: u64) {
+        self.ops.push(Op::RemoveQuadDirection(value_id, direction.clone(), quad_id));
     }
 
 
     ///////////////////////
 
 
-    fn resolve_val(&self, v: &Value, add: bool) -> Result<Option<u64>, String> {
+    fn resolve_val(&mut self, v: &Value, add: bool) -> Option<u64> {
         if let Value::None = v {
-            return Ok(None)
+            return None
         }
 
         let hash = v.calc_hash();
         
-        let prim = self.get_primitive_from_hash(hash)?;
+        let prim = self.get_primitive_from_hash(hash);
         
         if prim.is_some() || !add {
             // if the value exsists and we are adding it, increment refs
@@ -208,22 +235,22 @@ impl InternalIndexedDb {
                 let mut p = prim.unwrap();
                 p.refs += 1;
 
-                self.update_primitive(&p)?; // update p.refs
+                self.update_primitive(&p); // update p.refs
 
             }
             
-            return Ok(res)
+            return res
         }
 
         let mut prim = Primitive::new_value(v.clone());
-        self.add_primitive(&mut prim)?;
+        self.add_primitive(&mut prim);
 
-        Ok(Some(prim.id))
+        Some(prim.id)
     }
 
     
 
-    fn resolve_quad(&self, q: &Quad, add: bool) -> Result<Option<InternalQuad>, String> {
+    fn resolve_quad(&mut self, q: &Quad, add: bool) -> Option<InternalQuad> {
         let mut p = InternalQuad{s: 0, p: 0, o: 0, l: 0};
 
         // find all value ids for each direction of quad
@@ -232,53 +259,47 @@ impl InternalIndexedDb {
             if let Value::None = v {
                 continue
             }
-            let vid = self.resolve_val(v, add)?;
+            let vid = self.resolve_val(v, add);
             if  let Some(i) = vid {
                 p.set_dir(dir, i);
             } else {
                 // if any value is not found or undefined return zero value internal quad
-                return Ok(None)
+                return None
             }
         }
 
-        return Ok(Some(p))
+        return Some(p)
     }
 
 
-    fn find_quad(&self, q: &Quad) -> Result<Option<Primitive>, String> {
-        let quad = self.resolve_quad(q, false)?;
+    fn find_quad(&mut self, q: &Quad) -> Option<Primitive> {
+        let quad = self.resolve_quad(q, false);
         if let Some(q) = quad {
-            return Ok(self.get_primitive_from_hash(q.calc_hash())?)
+            return self.get_primitive_from_hash(q.calc_hash())
         }
-        Ok(None)
+        None
     }
 
 
-    fn delete_quad_nodes(&self, q: &InternalQuad) -> Result<(), String> {
+    fn delete_quad_nodes(&mut self, q: &InternalQuad) -> Result<(), Error> {
         for dir in Direction::iterator() {
             let id = q.dir(dir);
             if id == 0 {
                 continue
             }
 
-            if let Some(mut p) = self.get_primitive(id)? { // value
+            if let Some(mut p) = self.get_primitive(id) { // value
 
                 if p.refs == 0 {
-                    return Err("remove of delete node".to_string())
+                    return Err(Error::Storage("remove of delete node".to_string()))
                 } 
 
                 p.refs -= 1;
                 
                 if p.refs == 0 {
-
-                    self.remove_primitive(&p)?;
-
+                    self.remove_primitive(&p);
                 } else {
-
-                    if let Err(_) = self.update_primitive(&p) { // value
-                        return Err("read/write error".to_string())
-                    }
-
+                    self.update_primitive(&p);
                 }
             }
         }
@@ -287,30 +308,30 @@ impl InternalIndexedDb {
     }
 
 
-    fn resolve_quad_default(&self, q: &Quad, add: bool) -> Result<InternalQuad, String> {
-        match self.resolve_quad(q, add)? {
-            Some(q) => Ok(q),
-            None => Ok(InternalQuad{s: 0, p: 0, o: 0, l: 0})
+    fn resolve_quad_default(&mut self, q: &Quad, add: bool) -> InternalQuad {
+        match self.resolve_quad(q, add) {
+            Some(q) => q,
+            None => InternalQuad{s: 0, p: 0, o: 0, l: 0}
         }
     }
 
 
-    fn delete(&self, id: u64) -> Result<bool, String> {
+    fn delete(&mut self, id: u64) -> Result<bool, Error> {
         let mut quad:Option<InternalQuad> = None;
  
-        if let Some(p) = self.get_primitive(id)? {
+        if let Some(p) = self.get_primitive(id) {
             if let PrimitiveContent::InternalQuad(q) = &p.content {
                 quad = Some(q.clone());
             }
 
-            self.remove_primitive(&p)?;
+            self.remove_primitive(&p);
         } else {
             return Ok(false)
         }
         
         if let Some(q) = quad {
             for d in Direction::iterator() {
-                self.remove_quad_direction(q.dir(d), d, id)?;
+                self.remove_quad_direction(q.dir(d), d, id);
             }
 
             self.delete_quad_nodes(&q)?;
@@ -320,14 +341,14 @@ impl InternalIndexedDb {
     }
 
 
-    fn add_quad(&self, q: Quad) -> Result<u64, String> {
+    fn add_quad(&mut self, q: Quad) -> Result<u64, Error> {
         // get value_ids for each direction
-        let p = self.resolve_quad_default(&q, false)?;
+        let p = self.resolve_quad_default(&q, false);
 
         // get quad id
         let hash = p.calc_hash();
 
-        let prim = self.get_primitive_from_hash(hash)?;
+        let prim = self.get_primitive_from_hash(hash);
 
         // if prim already exsits
         if let Some(p) = prim {
@@ -335,37 +356,289 @@ impl InternalIndexedDb {
         }
 
         // get value_ids for each direction, this time inserting the values as neccecery
-        let p = self.resolve_quad_default(&q, true)?;
+        let p = self.resolve_quad_default(&q, true);
 
         // add value primitive
         let mut pr = Primitive::new_quad(p.clone());
-        let id = self.add_primitive(&mut pr)?;
+        self.add_primitive(&mut pr);
 
         // add to index
         for d in Direction::iterator() {
-            self.add_quad_direction(p.dir(d), d, pr.id)?;
+            self.add_quad_direction(p.dir(d), d, pr.id);
         }
 
         return Ok(pr.id);
     }
 
+    fn apply(&mut self, deltas: &[Delta], ignore_opts: &IgnoreOptions) -> Result<(), Error> {
+        if !ignore_opts.ignore_dup || !ignore_opts.ignore_missing {
+            for d in deltas {
+                match d.action {
+                    Procedure::Add => {
+                        if !ignore_opts.ignore_dup {
+                            if let Some(_) = self.find_quad(&d.quad) {
+                                return Err(Error::DuplicateQuad("Quad already exists".to_string()))
+                            }
+                        }
+                    },
+                    Procedure::Delete => {
+                        if !ignore_opts.ignore_missing {
+                            if let Some(_) = self.find_quad(&d.quad) {
+                            } else {
+                                return Err(Error::NotFound("Quad does not exist".to_string()))
+                            }
+                        }
+                    },
+                }
+            }
+        }
+
+        for d in deltas {
+            match &d.action {
+                Procedure::Add => {
+                    self.add_quad(d.quad.clone())?;
+                },
+                Procedure::Delete => {
+                   if let Some(prim) = self.find_quad(&d.quad) {
+                    self.delete(prim.id)?;
+                   }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+
+// the number of primitives read by one step of a scan
+pub const PAGE_SIZE: usize = 500;
+
+
+pub struct InternalIndexedDb {
+    pub db: Db,
+    cache: RefCell<Cache>,
+    // the writes of other connections, None where BroadcastChannel is not supported
+    changes: Option<Changes>,
+    // the cache is updated after each batch commits, so batches must not interleave
+    write_lock: Mutex<()>
+}
+
+impl InternalIndexedDb {
+
+    pub async fn open(name: &str, events: OpenEvents) -> Result<Self, Error> {
+        let db = Db::open(name, migrations::VERSION, events, |tx, old, new| {
+            async move {
+                migrations::migrate(&tx, old.unwrap_or(0), new).await
+            }
+        }).await.map_err(|e| Error::from_storage(&e, "Unable to open database"))?;
+
+        let store = InternalIndexedDb {
+            db,
+            cache: RefCell::new(Cache::default()),
+            changes: Changes::open(name).ok(),
+            write_lock: Mutex::new(())
+        };
+
+        let cache = store.load().await?;
+        *store.cache.borrow_mut() = cache;
+
+        Ok(store)
+    }
+
+    // reads every primitive and quad direction record into a new cache. The primitives are walked a page
+    // at a time, so no single transaction has to hold the whole table
+    async fn load(&self) -> Result<Cache, Error> {
+        let mut cache = Cache::default();
+
+        for &nodes in &[true, false] {
+            let mut after = 0;
+            loop {
+                let page = self.scan(nodes, after, PAGE_SIZE).await?;
+
+                let last = match page.last() {
+                    Some(p) => p.id,
+                    None => break
+                };
+
+                for p in page {
+                    cache.insert_primitive(p);
+                }
+
+                after = last;
+            }
+        }
+
+        let (quad_directions, count) = self.db.read(&["quad_direction", "meta"], move |tx| {
+            async move {
+                let quad_directions = tx.get_all_quad_directions().await?;
+                let count = tx.get_count().await?;
+                Ok((quad_directions, count))
+            }
+        }).await.map_err(|e| Error::from_storage(&e, "Unable to load database"))?;
+
+        for (value_id, direction, quad_id) in quad_directions {
+            cache.insert_quad_direction(value_id, &direction, quad_id);
+        }
+
+        cache.count = match count {
+            Some(c) => c,
+            None => cache.recount()
+        };
+
+        Ok(cache)
+    }
+
+    // reads up to limit nodes (or quads) with an id greater than after from IndexedDB, in the order of their ids
+    pub async fn scan(&self, nodes: bool, after: u64, limit: usize) -> Result<Vec<Primitive>, Error> {
+        self.db.read(&["primitives"], move |tx| {
+            async move {
+                tx.scan_primitives(nodes, after, limit).await
+            }
+        }).await.map_err(|e| Error::from_storage(&e, "Unable to read database"))
+    }
+
+    // resolves once every write queued before it has been committed, and the writes of other
+    // connections have been read into the cache. If they can not be read the cache stays stale
+    pub async fn flush(&self) {
+        let _guard = self.write_lock.lock().await;
+
+        if let Some(changes) = &self.changes {
+            if changes.take_stale() {
+                match self.load().await {
+                    Ok(cache) => *self.cache.borrow_mut() = cache,
+                    Err(_) => changes.mark_stale()
+                }
+            }
+        }
+    }
+
+    // Primitives
+
+    pub fn get_count(&self) -> Result<PrimitiveCount, String> {
+        Ok(self.cache.borrow().count.clone())
+    }
 
-    fn lookup_val(&self, id: u64) -> Result<Option<Value>, String> {
-        match self.get_primitive(id)? {
+    // counts the primitives stored in IndexedDB instead of relying on the maintained counters,
+    // and replaces the counters with the result
+    pub async fn recount(&self) -> Result<PrimitiveCount, Error> {
+        let _guard = self.write_lock.lock().await;
+
+        let count = self.db.write(&["primitives", "meta"], move |tx| {
+            async move {
+                let count = PrimitiveCount {
+                    values: tx.count_primitives(true).await?,
+                    quads: tx.count_primitives(false).await?
+                };
+                tx.put_count(&count)?;
+                Ok(count)
+            }
+        }).await.map_err(|e| Error::from_storage(&e, "Unable to count primitives"))?;
+
+        self.cache.borrow_mut().count = count.clone();
+
+        Ok(count)
+    }
+
+
+    pub fn get_primitive(&self, id: u64) -> Option<Primitive> {
+        self.cache.borrow().primitives.get(&id).cloned()
+    }
+
+    pub fn get_primitive_from_hash(&self, hash: u64) -> Option<Primitive> {
+        let cache = self.cache.borrow();
+        cache.hashes.get(&hash).and_then(|id| cache.primitives.get(id)).cloned()
+    }
+
+    // the page of scan for the iterators, which can not wait for IndexedDB
+    pub fn scan_cached(&self, nodes: bool, after: u64, limit: usize) -> Vec<Primitive> {
+        let cache = self.cache.borrow();
+        let ids = if nodes { &cache.node_ids } else { &cache.quad_ids };
+        ids.range(after + 1..).take(limit).filter_map(|id| cache.primitives.get(id)).cloned().collect()
+    }
+
+    // Quad Direction Index
+
+    fn get_quad_direction(&self, direction: &Direction, value_id: &u64) -> BTreeSet<u64> {
+        match self.cache.borrow().quad_direction.get(&(direction.to_byte(), *value_id)) {
+            Some(quad_ids) => quad_ids.clone(),
+            None => BTreeSet::new()
+        }
+    }
+
+    ///////////////////////
+
+
+    pub async fn apply_deltas(&self, deltas: Vec<Delta>, ignore_opts: &IgnoreOptions) -> Result<(), Error> {
+        let _guard = self.write_lock.lock().await;
+
+        let ignore_opts = IgnoreOptions { ignore_dup: ignore_opts.ignore_dup, ignore_missing: ignore_opts.ignore_missing };
+
+        // if any request fails the whole transaction is aborted and none of the deltas are stored
+        let batch = self.db.write(&["primitives", "quad_direction", "meta"], move |tx| {
+            async move {
+                let mut batch = Batch::read(&tx, &deltas).await?;
+
+                // nothing was written yet, the transaction only commits the reads
+                if let Err(e) = batch.apply(&deltas, &ignore_opts) {
+                    return Ok(Err(e))
+                }
+
+                for op in &batch.ops {
+                    match op {
+                        Op::InsertPrimitive(p) => tx.insert_primitive(p)?,
+                        Op::UpdatePrimitive(p) => tx.update_primitive(p)?,
+                        Op::RemovePrimitive(id) => tx.remove_primitive(*id)?,
+                        Op::InsertQuadDirection(value_id, d, quad_id) => tx.insert_quad_direction(*value_id, d, *quad_id)?,
+                        Op::RemoveQuadDirection(value_id, d, quad_id) => tx.remove_quad_direction(*value_id, d, *quad_id)?,
+                    }
+                }
+
+                if !batch.ops.is_empty() {
+                    tx.put_count(&batch.count)?;
+                }
+
+                Ok(Ok(batch))
+            }
+        }).await.map_err(|e| Error::from_storage(&e, "Unable to apply deltas"))??;
+
+        if batch.ops.is_empty() {
+            return Ok(())
+        }
+
+        if let Some(changes) = &self.changes {
+            changes.notify();
+        }
+
+        let mut cache = self.cache.borrow_mut();
+
+        for op in batch.ops {
+            cache.apply(op);
+        }
+
+        cache.count = batch.count;
+
+        Ok(())
+    }
+
+
+    fn lookup_val(&self, id: u64) -> Option<Value> {
+        match self.get_primitive(id) {
             Some(p) => {
                 match p.content {
-                    PrimitiveContent::Value(v) => Ok(Some(v)),
-                    _ => Ok(None)
+                    PrimitiveContent::Value(v) => Some(v),
+                    _ => None
                 }
             },
-            None => Ok(None)
+            None => None
         }
     }
 
 
-    fn internal_quad(&self, r: &Ref) -> Result<Option<InternalQuad>, String> {
+    fn internal_quad(&self, r: &Ref) -> Option<InternalQuad> {
         let key = if let Some(k) = r.key() { 
-            self.get_primitive(k)?
+            self.get_primitive(k)
         } else { 
             None 
         };
@@ -373,42 +646,42 @@ impl InternalIndexedDb {
         match key {
             Some(p) => {
                 match p.content {
-                    PrimitiveContent::InternalQuad(q) => Ok(Some(q)),
-                    _ => Ok(None)
+                    PrimitiveContent::InternalQuad(q) => Some(q),
+                    _ => None
                 }
             },
-            None => Ok(None)
+            None => None
         }
     }
 
     
-    fn lookup_quad_dirs(&self, p: InternalQuad) -> Result<Quad, String> {
+    fn lookup_quad_dirs(&self, p: InternalQuad) -> Quad {
         let mut q = Quad::new_undefined_vals();
         for dir in Direction::iterator() {
             let vid = p.dir(dir);
             if vid == 0 {
                 continue
             }
-            let val = self.lookup_val(vid)?;
-            if let Some(v) = val {
+            if let Some(v) = self.lookup_val(vid) {
                 q.set_val(dir, v);
             }
         }
-        return Ok(q)
+        return q
     }
 
 }
 
 
 
+#[derive(Clone)]
 pub struct IndexedDb {
     store: Arc<InternalIndexedDb>
 }
 
 impl IndexedDb {
-    pub fn open(path: &str) -> Result<IndexedDb, String> {
+    pub async fn open(name: &str, events: OpenEvents) -> Result<IndexedDb, Error> {
         Ok(IndexedDb {
-            store: Arc::new(InternalIndexedDb::open(path)?)
+            store: Arc::new(InternalIndexedDb::open(name, events).await?)
         })
     }
 }
@@ -421,7 +694,7 @@ impl Namer for IndexedDb {
 
         let hash = v.calc_hash();
 
-        if let Ok(Some(prim)) = self.store.get_primitive_from_hash(hash) { // TODO: this method should return Result<Option>
+        if let Some(prim) = self.store.get_primitive_from_hash(hash) {
             Some(Ref {
                 k: Some(prim.id),
                 content: Content::Value(v.clone())
@@ -437,12 +710,7 @@ impl Namer for IndexedDb {
         }
 
         if let Some(i) = key.key() {
-            if let Ok(v) = self.store.lookup_val(i) {
-                return v
-            } else {    
-                // TODO: return Err
-                return None
-            }
+            return self.store.lookup_val(i)
         } else {
             return None
         }
@@ -461,37 +729,21 @@ impl QuadStore for IndexedDb {
                 Some(iq.clone())
             }
             _ => {
-                match self.store.internal_quad(r) {
-                    Ok(iq) => {
-                        iq
-                    },
-                    Err(_) => {
-                        // TODO: return Err
-                        return None
-                    } 
-                }
+                self.store.internal_quad(r)
             }
         };
 
         match internal_quad {
-            Some(q) => {
-                if let Ok(dirs) = self.store.lookup_quad_dirs(q) {
-                    return Some(dirs)
-                } else {
-                    // TODO: return Err
-                    return None
-                }
-            }
+            Some(q) => Some(self.store.lookup_quad_dirs(q)),
             None => None
         }
     }
 
     fn quad_iterator(&self, d: &Direction, r: &Ref) -> Rc<RefCell<dyn Shape>> {
         if let Some(i) = r.key() {
-            if let Ok(quad_ids) = self.store.get_quad_direction(d, &i) {
-                if !quad_ids.is_empty() {
-                    return QuadIds::new(Rc::new(quad_ids), d.clone())
-                }
+            let quad_ids = self.store.get_quad_direction(d, &i);
+            if !quad_ids.is_empty() {
+                return QuadIds::new(Rc::new(quad_ids), d.clone())
             }
         } 
             
@@ -501,7 +753,7 @@ impl QuadStore for IndexedDb {
     fn quad_iterator_size(&self, d: &Direction, r: &Ref) -> Result<Size, String> {
         if let Some(i) = r.key() {
 
-            let quad_ids = self.store.get_quad_direction(d, &i)?;
+            let quad_ids = self.store.get_quad_direction(d, &i);
 
             if !quad_ids.is_empty() {
                 return Ok(Size{value: quad_ids.len() as i64, exact: true})
@@ -512,15 +764,7 @@ impl QuadStore for IndexedDb {
     }
 
     fn quad_direction(&self, r: &Ref, d: &Direction) -> Option<Ref> {
-        let quad = match self.store.internal_quad(r) {
-            Ok(q) => q,
-            Err(_) => {
-                return None
-                // TODO: return Result<Option>>
-            }
-        };
-
-        match quad {
+        match self.store.internal_quad(r) {
             Some(q) => {
                 let id = q.dir(d);
                 if id == 0 {
@@ -537,6 +781,8 @@ impl QuadStore for IndexedDb {
         }
     }
 
+    // the maintained counters, which are exact unless the database was changed without them.
+    // Counting the stored records can not be done synchronously, InternalIndexedDb::recount corrects them
     fn stats(&self, _exact: bool) -> Result<Stats, String> {
         let count = self.store.get_count()?;
 
@@ -552,43 +798,10 @@ impl QuadStore for IndexedDb {
         })
     }
     
-    fn apply_deltas(&mut self, deltas: Vec<Delta>, ignore_opts: &IgnoreOptions) -> Result<(), String> {
-        if !ignore_opts.ignore_dup || !ignore_opts.ignore_missing {
-            for d in &deltas {
-                match d.action {
-                    Procedure::Add => {
-                        if !ignore_opts.ignore_dup {
-                            if let Some(_) = self.store.find_quad(&d.quad)? {
-                                return Err("ErrQuadExists".into())
-                            }
-                        }
-                    },
-                    Procedure::Delete => {
-                        if !ignore_opts.ignore_missing {
-                            if let Some(_) = self.store.find_quad(&d.quad)? {
-                            } else {
-                                return Err("ErrQuadNotExist".into())
-                            }
-                        }
-                    },
-                }
-            }
-        }
-
-        for d in &deltas {
-            match &d.action {
-                Procedure::Add => {
-                    self.store.add_quad(d.quad.clone())?;
-                },
-                Procedure::Delete => {
-                   if let Some(prim) = self.store.find_quad(&d.quad)? {
-                    self.store.delete(prim.id)?;
-                   }
-                }
-            }
-        }
-
-        Ok(())
+    fn apply_deltas(&mut self, _deltas: Vec<Delta>, _ignore_opts: &IgnoreOptions) -> Result<(), String> {
+        // committing to IndexedDB can not be done synchronously, Session rejects synchronous writes
+        // to a graph with an AsyncQuadStore before they get here
+        Err("IndexedDb writes are asynchronous, use writeAsync or deleteAsync".to_string())
     }
 
     fn nodes_all_iterator(&self) -> Rc<RefCell<dyn Shape>> {
@@ -606,13 +819,31 @@ impl QuadStore for IndexedDb {
     }
 }
 
+
+impl AsyncQuadStore for IndexedDb {
+    fn write_deltas(&self, deltas: Vec<Delta>, ignore_opts: IgnoreOptions) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
+        let store = self.store.clone();
+        Box::pin(async move {
+            store.apply_deltas(deltas, &ignore_opts).await
+        })
+    }
+
+    fn flush(&self) -> Pin<Box<dyn Future<Output = ()>>> {
+        let store = self.store.clone();
+        Box::pin(async move {
+            store.flush().await
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
 pub struct PrimitiveCount {
-    values: u64,
-    quads: u64
+    pub values: u64,
+    pub quads: u64
 }
 
 impl PrimitiveCount {
-    fn zero() -> PrimitiveCount {
+    pub fn zero() -> PrimitiveCount {
         PrimitiveCount {
             values: 0,
             quads: 0
@@ -623,7 +854,7 @@ impl PrimitiveCount {
         return self.values + self.quads
     }
 
-    fn increment_quads(&mut self, n: i64) {
+    pub fn increment_quads(&mut self, n: i64) {
         if n < 0 {
             let m = n.abs() as u64;
             if m > self.quads {
@@ -640,7 +871,7 @@ impl PrimitiveCount {
         }
     }
 
-    fn increment_values(&mut self, n: i64) {
+    pub fn increment_values(&mut self, n: i64) {
         if n < 0 {
             let m = n.abs() as u64;
             if m > self.values {
@@ -671,7 +902,7 @@ pub struct Primitive {
 
 impl Primitive {
 
-    fn calc_hash(&self) -> u64 {
+    pub fn calc_hash(&self) -> u64 {
         match &self.content {
             PrimitiveContent::Value(v) => {
                 return v.calc_hash()
@@ -771,4 +1002,187 @@ pub enum PrimitiveContent {
 #[test]
 fn testing() {
    
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn deltas(quads: &[(&str, &str, &str)], add: bool) -> Vec<Delta> {
+        quads.iter().map(|(s, p, o)| Delta {
+            quad: Quad {
+                subject: Value::from(s.to_string()),
+                predicate: Value::from(p.to_string()),
+                object: Value::from(o.to_string()),
+                label: Value::None
+            },
+            action: if add { Procedure::Add } else { Procedure::Delete }
+        }).collect()
+    }
+
+    const STRICT: IgnoreOptions = IgnoreOptions { ignore_dup: false, ignore_missing: false };
+
+    #[wasm_bindgen_test]
+    async fn connections_build_on_each_others_writes() {
+        let name = format!("gizmo_connections_test_{}", js_sys::Date::now());
+
+        // two tabs with the database open
+        let first = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+        let second = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+        first.apply_deltas(deltas(&[("<alice>", "<knows>", "<bob>")], true), &STRICT).await.unwrap();
+
+        // the ids and refs of the first write are read in the transaction, not taken from the cache
+        second.apply_deltas(deltas(&[("<alice>", "<knows>", "<carol>")], true), &STRICT).await.unwrap();
+        second.apply_deltas(deltas(&[("<alice>", "<knows>", "<bob>")], false), &STRICT).await.unwrap();
+
+        let alice = Value::from("<alice>".to_string()).calc_hash();
+        assert_eq!(second.get_primitive_from_hash(alice).map(|p| p.refs), Some(1));
+        assert_eq!(second.get_count().unwrap(), PrimitiveCount { values: 3, quads: 1 });
+
+        let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+        assert_eq!(store.get_primitive_from_hash(alice).map(|p| p.refs), Some(1));
+        assert_eq!(store.get_count().unwrap(), PrimitiveCount { values: 3, quads: 1 });
+        assert_eq!(store.recount().await.unwrap(), PrimitiveCount { values: 3, quads: 1 });
+    }
+
+    #[wasm_bindgen_test]
+    async fn scan_walks_pages() {
+        let name = format!("gizmo_scan_test_{}", js_sys::Date::now());
+
+        {
+            let db = Db::open(&name, migrations::VERSION, OpenEvents::default(), |tx, old, new| {
+                async move {
+                    migrations::migrate(&tx, old.unwrap_or(0), new).await
+                }
+            }).await.unwrap();
+
+            db.write(&["primitives"], move |tx| {
+                async move {
+                    for i in 1..=5 {
+                        let mut p = Primitive::new_value(Value::from(format!("<n{}>", i)));
+                        p.id = i;
+                        tx.insert_primitive(&p)?;
+                    }
+                    Ok(())
+                }
+            }).await.unwrap();
+        }
+
+        let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+        let ids = |page: Vec<Primitive>| page.iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids(store.scan(true, 0, 2).await.unwrap()), vec![1, 2]);
+        assert_eq!(ids(store.scan(true, 2, 2).await.unwrap()), vec![3, 4]);
+        assert_eq!(ids(store.scan(true, 4, 2).await.unwrap()), vec![5]);
+        assert_eq!(ids(store.scan(false, 0, 2).await.unwrap()), Vec::<u64>::new());
+    }
+
+    #[wasm_bindgen_test]
+    async fn counts_are_persisted() {
+        let name = format!("gizmo_count_test_{}", js_sys::Date::now());
+
+        {
+            let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+            let quads = [("<alice>", "<knows>", "<bob>"), ("<bob>", "<knows>", "<carol>")];
+            store.apply_deltas(deltas(&quads, true), &STRICT).await.unwrap();
+            assert_eq!(store.get_count().unwrap(), PrimitiveCount { values: 4, quads: 2 });
+
+            // <carol> is no longer used by any quad, <bob> still is
+            store.apply_deltas(deltas(&quads[1..], false), &STRICT).await.unwrap();
+            assert_eq!(store.get_count().unwrap(), PrimitiveCount { values: 3, quads: 1 });
+        }
+
+        let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+        assert_eq!(store.get_count().unwrap(), PrimitiveCount { values: 3, quads: 1 });
+        assert_eq!(store.recount().await.unwrap(), PrimitiveCount { values: 3, quads: 1 });
+    }
+
+    #[wasm_bindgen_test]
+    async fn recount_corrects_counters() {
+        let name = format!("gizmo_recount_test_{}", js_sys::Date::now());
+
+        {
+            let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+            store.apply_deltas(deltas(&[("<alice>", "<knows>", "<bob>")], true), &STRICT).await.unwrap();
+
+            // counters that no longer match the stored primitives
+            store.db.write(&["meta"], |tx| {
+                async move {
+                    tx.put_count(&PrimitiveCount { values: 10, quads: 5 })?;
+                    Ok(())
+                }
+            }).await.unwrap();
+        }
+
+        {
+            let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+            assert_eq!(store.get_count().unwrap(), PrimitiveCount { values: 10, quads: 5 });
+            assert_eq!(store.recount().await.unwrap(), PrimitiveCount { values: 3, quads: 1 });
+            assert_eq!(store.get_count().unwrap(), PrimitiveCount { values: 3, quads: 1 });
+        }
+
+        let store = IndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+        let stats = store.stats(false).unwrap();
+        assert_eq!((stats.nodes.value, stats.quads.value), (3, 1));
+    }
+
+    #[wasm_bindgen_test]
+    async fn failed_batch_changes_nothing() {
+        let name = format!("gizmo_failed_batch_test_{}", js_sys::Date::now());
+
+        let alice = Value::from("<alice>".to_string());
+        let x = Value::from("<x>".to_string());
+
+        {
+            let store = InternalIndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+            store.apply_deltas(deltas(&[("<alice>", "<knows>", "<bob>")], true), &STRICT).await.unwrap();
+            let count = store.get_count().unwrap();
+            let refs = store.get_primitive_from_hash(alice.calc_hash()).map(|p| p.refs);
+
+            // the batch below adds <likes> and <x> as 5 and 6 and their quad as 7, the quad direction
+            // planted for <alice> as its subject makes the batch fail after the primitives were inserted
+            store.db.write(&["quad_direction"], |tx| {
+                async move {
+                    tx.insert_quad_direction(1, &Direction::Subject, 7)?;
+                    Ok(())
+                }
+            }).await.unwrap();
+
+            let quads = [("<alice>", "<likes>", "<x>")];
+            assert!(store.apply_deltas(deltas(&quads, true), &STRICT).await.is_err());
+
+            assert_eq!(store.get_count().unwrap(), count);
+            assert_eq!(store.recount().await.unwrap(), count);
+            assert_eq!(store.get_primitive_from_hash(alice.calc_hash()).map(|p| p.refs), refs);
+            assert_eq!(store.get_primitive_from_hash(x.calc_hash()), None);
+            assert_eq!(store.get_primitive(5), None);
+
+            store.db.write(&["quad_direction"], |tx| {
+                async move {
+                    tx.remove_quad_direction(1, &Direction::Subject, 7)?;
+                    Ok(())
+                }
+            }).await.unwrap();
+        }
+
+        let store = IndexedDb::open(&name, OpenEvents::default()).await.unwrap();
+
+        let stats = store.stats(true).unwrap();
+        assert_eq!((stats.nodes.value, stats.quads.value), (3, 1));
+        assert!(store.value_of(&x).is_none());
+
+        // no quad direction entries were left for the quad that was not added
+        let alice = store.value_of(&alice).unwrap();
+        assert_eq!(store.quad_iterator_size(&Direction::Subject, &alice).unwrap().value, 1);
+    }
+}
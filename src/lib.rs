@@ -1,14 +1,21 @@
 mod indexeddb;
+mod error;
+mod format;
+mod bulk;
+mod query;
+mod tree;
+mod explain;
 
 
 use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
 
 use web_sys::console;
 use js_sys;
 
 use gizmo_db::query::path;
 
-use gizmo_db::graph::quad::{QuadStore, QuadWriter, IgnoreOptions, Quad};
+use gizmo_db::graph::quad::{QuadStore, QuadWriter, IgnoreOptions, Quad, Delta, Procedure};
 use gizmo_db::graph::memstore;
 use gizmo_db::graph::iterator;
 use gizmo_db::query::shape::Shape;
@@ -19,9 +26,16 @@ use gizmo_db::graph::refs::Ref;
 
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::pin::Pin;
+use std::future::Future;
 
 use std::collections::HashMap;
 
+use error::Error;
+use format::term::{self, Term};
+
+use wasm_bindgen_futures::future_to_promise;
+
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
 //
@@ -52,9 +66,72 @@ pub fn new_memory_graph() -> GraphWrapper {
     let qs = Rc::new(RefCell::new(memstore::quadstore::MemStore::new()));
     //let qs = Rc::new(RefCell::new(graphmock::Store::new()));
 
+    new_graph(qs, None)
+}
+
+
+// a memory graph holding the quads of a snapshot made with exportSnapshot
+#[wasm_bindgen(js_name = NewMemoryGraphFromSnapshot)]
+pub fn new_memory_graph_from_snapshot(bytes: &[u8]) -> Result<GraphWrapper, JsValue> {
+    let deltas: Vec<Delta> = format::snapshot::read(bytes)?.into_iter().map(|quad| Delta {
+        quad,
+        action: Procedure::Add
+    }).collect();
+
+    let mut qs = memstore::quadstore::MemStore::new();
+    qs.apply_deltas(deltas, &IgnoreOptions{ignore_dup: true, ignore_missing: true}).map_err(Error::from_quad_writer)?;
+
+    Ok(new_graph(Rc::new(RefCell::new(qs)), None))
+}
+
+
+// opens the named IndexedDB database, creating it if it does not exist yet.
+// options can contain onBlocked and onVersionChange callbacks for when the database is used from several tabs.
+// The graph is written with writeAsync, deleteAsync, the imports or bulkLoad, write and delete throw an UnsupportedError.
+// Every node and quad of the database is read into memory when it is opened, so that queries can run synchronously,
+// and the database has to fit in memory. Writes from other tabs are read in again by the next async final or readAsync.
+// Only those wait for the writes that are still pending: read, query, sparql, graphql, the exports and the other
+// finals answer from what has been written so far, so await every write before calling them.
+#[wasm_bindgen(js_name = NewIndexedDbGraph)]
+pub async fn new_indexeddb_graph(name: String, options: JsValue) -> Result<GraphWrapper, JsValue> {
+    let events = js_to_open_events(&options);
+
+    let qs = indexeddb::IndexedDb::open(&name, events).await?;
+    let persist: Rc<dyn AsyncQuadStore> = Rc::new(qs.clone());
+
+    Ok(new_graph(Rc::new(RefCell::new(qs)), Some(persist)))
+}
+
+
+fn js_to_open_events(options: &JsValue) -> indexeddb::OpenEvents {
+    let callback = |key: &str| -> Option<Box<dyn FnMut(&JsValue)>> {
+        if !options.is_object() {
+            return None
+        }
+
+        match js_sys::Reflect::get(options, &JsValue::from_str(key)) {
+            Ok(f) if f.is_function() => {
+                let f: js_sys::Function = f.into();
+                Some(Box::new(move |event: &JsValue| {
+                    let _ = f.call1(&JsValue::NULL, event);
+                }))
+            },
+            _ => None
+        }
+    };
+
+    indexeddb::OpenEvents {
+        on_blocked: callback("onBlocked"),
+        on_version_change: callback("onVersionChange"),
+    }
+}
+
+
+fn new_graph(qs: Rc<RefCell<dyn QuadStore>>, persist: Option<Rc<dyn AsyncQuadStore>>) -> GraphWrapper {
     let s = Rc::new(RefCell::new(Session {
         qs: qs.clone(),
-        qw: QuadWriter::new(qs.clone(), IgnoreOptions{ignore_dup: true, ignore_missing: true})
+        qw: QuadWriter::new(qs.clone(), IgnoreOptions{ignore_dup: true, ignore_missing: true}),
+        persist
     }));
 
     let g = Graph::new(s.clone());
@@ -84,34 +161,246 @@ impl GraphWrapper {
         return self.graph.clone();
     }
 
-    pub fn write(&self, quads: &JsValue) -> Result<(), JsValue> {
-        self.session.borrow().write(quads)
+    // runs a query written like the method chain, e.g. g.V("<alice>").out("<follows>").all(),
+    // and returns what its final returns, see query::gizmo
+    pub fn query(&self, text: &str) -> Result<JsValue, JsValue> {
+        query::gizmo::run(&self.graph, text)
+    }
+
+    // runs a SPARQL SELECT query, each row maps the selected variables to their values, see query::sparql
+    pub fn sparql(&self, query: &str) -> Result<TagIterator, JsValue> {
+        Ok(query::sparql::run(&self.graph, query)?)
+    }
+
+    // runs a GraphQL-like query and returns the nested objects it selects, see query::graphql
+    pub fn graphql(&self, query: &str) -> Result<JsValue, JsValue> {
+        Ok(query::graphql::run(&self.graph, query)?)
+    }
+
+    pub fn write(&self, quads: &JsValue, options: &JsValue) -> Result<(), JsValue> {
+        self.session.borrow().write(quads, options)
+    }
+
+    pub fn read(&self, filter: &JsValue, options: &JsValue) -> Result<QuadIterator, JsValue> {
+        self.session.borrow().read(filter, options)
+    }
+
+    pub fn delete(&self, quads: &JsValue, options: &JsValue) -> Result<(), JsValue> {
+        self.session.borrow().delete(quads, options)
+    }
+
+    #[wasm_bindgen(js_name = writeAsync)]
+    pub fn write_async(&self, quads: &JsValue, options: &JsValue) -> js_sys::Promise {
+        let quads = js_to_quads(quads, options);
+        let session = self.session.clone();
+
+        future_to_promise(async move {
+            Session::apply_async(session, quads?, true).await?;
+            Ok(JsValue::undefined())
+        })
+    }
+
+    #[wasm_bindgen(js_name = readAsync)]
+    pub fn read_async(&self, filter: &JsValue, options: &JsValue) -> js_sys::Promise {
+        let filter = filter.clone();
+        let options = options.clone();
+        let session = self.session.clone();
+
+        future_to_promise(async move {
+            Session::flush(&session).await;
+            let it = session.borrow().read(&filter, &options)?;
+            Ok(it.into())
+        })
+    }
+
+    #[wasm_bindgen(js_name = deleteAsync)]
+    pub fn delete_async(&self, quads: &JsValue, options: &JsValue) -> js_sys::Promise {
+        let quads = js_to_quads(quads, options);
+        let session = self.session.clone();
+
+        future_to_promise(async move {
+            Session::apply_async(session, quads?, false).await?;
+            Ok(JsValue::undefined())
+        })
+    }
+
+    // parses N-Quads text and writes the quads in one batch. Resolves with the number of quads.
+    #[wasm_bindgen(js_name = importNQuads)]
+    pub fn import_nquads(&self, text: &str) -> js_sys::Promise {
+        let quads = format::nquads::parse(text);
+        self.import_quads(quads)
+    }
+
+    // parses Turtle or TriG text and writes the quads in one batch, nothing is written when the text
+    // has a syntax error. Relative IRIs are resolved against base if it is given.
+    // Resolves with the number of quads.
+    #[wasm_bindgen(js_name = importTurtle)]
+    pub fn import_turtle(&self, text: &str, base: Option<String>) -> js_sys::Promise {
+        let mut quads = Vec::new();
+        let parsed = format::turtle::parse(text, base.as_deref(), |quad| {
+            quads.push(quad);
+            Ok(())
+        });
+        self.import_quads(parsed.map(|_| quads))
+    }
+
+    // expands and flattens a JSON-LD document (an object, an array or JSON text) and writes the quads
+    // in one batch, named graphs become labels. context is applied before the document's own context.
+    // Resolves with the number of quads.
+    #[wasm_bindgen(js_name = importJsonLd)]
+    pub fn import_jsonld(&self, doc: &JsValue, context: &JsValue) -> js_sys::Promise {
+        let quads = format::jsonld::import(doc, context);
+        self.import_quads(quads)
+    }
+
+    // converts the rows of CSV or TSV text to quads as the mapping says, see format::csv, and writes
+    // them in one batch. When a row can not be converted nothing is written and the error lists the bad
+    // rows, unless mapping.strict is false, in which case the other rows are written.
+    // Resolves with {quads, errors}, the number of quads written and the {line, reason} of the skipped rows.
+    #[wasm_bindgen(js_name = importCsv)]
+    pub fn import_csv(&self, text: &str, mapping: &JsValue) -> js_sys::Promise {
+        let imported = format::csv::import(text, mapping);
+        let strict = js_option_bool(mapping, "strict").unwrap_or(true);
+        let session = self.session.clone();
+
+        future_to_promise(async move {
+            let (quads, errors) = imported?;
+
+            if !errors.is_empty() && strict {
+                return Err(Error::InvalidRows(errors).into())
+            }
+
+            let count = quads.len() as u32;
+            Session::apply_async(session, quads, true).await?;
+
+            let result = js_sys::Object::new();
+            let _ = js_sys::Reflect::set(&result, &JsValue::from_str("quads"), &JsValue::from(count));
+            let _ = js_sys::Reflect::set(&result, &JsValue::from_str("errors"), &error::reasons_to_js("line", &errors));
+            Ok(result.into())
+        })
+    }
+
+    // all quads as a JSON-LD document compacted with the @context of the frame,
+    // the @id and @type of the frame select the nodes
+    #[wasm_bindgen(js_name = exportJsonLd)]
+    pub fn export_jsonld(&self, frame: &JsValue) -> Result<JsValue, JsValue> {
+        let quads = self.session.borrow().quads(&JsValue::undefined());
+        Ok(format::jsonld::export(quads, frame)?)
+    }
+
+    // loads a large number of quads in batches without blocking the page, see bulk::Loader.
+    // options: { batchSize, onProgress, signal, strict }. Resolves with the number of quads.
+    #[wasm_bindgen(js_name = bulkLoad)]
+    pub fn bulk_load(&self, source: JsValue, options: &JsValue) -> js_sys::Promise {
+        let loader = bulk::Loader::new(self.session.clone(), options);
+
+        future_to_promise(async move {
+            let count = loader.load(source).await?;
+            Ok(JsValue::from(count))
+        })
+    }
+
+    // all quads as a versioned, checksummed binary snapshot, see format::snapshot
+    #[wasm_bindgen(js_name = exportSnapshot)]
+    pub fn export_snapshot(&self) -> Vec<u8> {
+        let quads = self.session.borrow().quads(&JsValue::undefined());
+        format::snapshot::write(quads)
+    }
+
+    // adds the quads of a snapshot in one batch, which also seeds an IndexedDB graph in a single transaction.
+    // Resolves with the number of quads.
+    #[wasm_bindgen(js_name = importSnapshot)]
+    pub fn import_snapshot(&self, bytes: &[u8]) -> js_sys::Promise {
+        let quads = format::snapshot::read(bytes);
+        self.import_quads(quads)
+    }
+
+    // adds the quads of an import in one batch, which is a single transaction for an IndexedDB graph.
+    // Resolves with the number of quads.
+    fn import_quads(&self, quads: Result<Vec<Quad>, Error>) -> js_sys::Promise {
+        let session = self.session.clone();
+
+        future_to_promise(async move {
+            let quads = quads?;
+            let count = quads.len() as u32;
+            Session::apply_async(session, quads, true).await?;
+            Ok(JsValue::from(count))
+        })
+    }
+
+    // the nodes of a path and the quads between them as GraphML ("graphml") or GEXF ("gexf"),
+    // see exportGraphML and exportGexf in gizmo.js
+    #[wasm_bindgen(js_name = _export_graph_path)]
+    pub fn export_graph_path(&self, path: &Path, format: &str) -> Result<String, JsValue> {
+        let nodes: Vec<Value> = path.iter_values(None).iterator.collect();
+
+        let quads: Box<dyn Iterator<Item = Quad>> = if nodes.is_empty() {
+            Box::new(std::iter::empty())
+        } else {
+            self.session.borrow().filter_quads(shape::filter_quads(Some(nodes.clone()), None, None, None))
+        };
+
+        write_graph(&format::subgraph::Subgraph::new(Some(nodes), quads), format)
     }
 
-    pub fn read(&self, filter: &JsValue) -> Result<QuadIterator, JsValue> {
-        self.session.borrow().read(filter)
+    // the quads matching the read filter (or all quads) and their subjects and objects as GraphML or GEXF
+    #[wasm_bindgen(js_name = _export_graph_filter)]
+    pub fn export_graph_filter(&self, filter: &JsValue, format: &str) -> Result<String, JsValue> {
+        let quads = self.session.borrow().quads(filter);
+        write_graph(&format::subgraph::Subgraph::new(None, quads), format)
     }
 
-    pub fn delete(&self, quads: &JsValue) -> Result<(), JsValue> {
-        self.session.borrow().delete(quads)
+    // the quads matching the read filter (or all quads) as N-Quads text
+    #[wasm_bindgen(js_name = exportNQuads)]
+    pub fn export_nquads(&self, filter: &JsValue) -> String {
+        let quads = self.session.borrow().quads(filter);
+        format::nquads::serialize(quads)
     }
 }
 
 
+fn write_graph(graph: &format::subgraph::Subgraph, format: &str) -> Result<String, JsValue> {
+    match format {
+        "graphml" => Ok(format::graphml::write(graph)),
+        "gexf" => Ok(format::gexf::write(graph)),
+        f => Err(Error::InvalidInput(format!("Unknown graph format {:?}", f)).into())
+    }
+}
+
+
+// A quad store whose writes can only complete asynchronously, such as IndexedDB.
+// Its QuadStore implementation serves reads, while writes go through write_deltas.
+pub trait AsyncQuadStore {
+    fn write_deltas(&self, deltas: Vec<Delta>, ignore_opts: IgnoreOptions) -> Pin<Box<dyn Future<Output = Result<(), Error>>>>;
+
+    // resolves once all previously started writes have completed
+    fn flush(&self) -> Pin<Box<dyn Future<Output = ()>>>;
+}
+
+
 #[wasm_bindgen]
 pub struct Session {
     qs: Rc<RefCell<dyn QuadStore>>,
-    qw: QuadWriter
+    qw: QuadWriter,
+    persist: Option<Rc<dyn AsyncQuadStore>>
 }
 
 
 #[wasm_bindgen]
 impl Session {
     // an array of quads, where each quad is represented as an array of 3 or 4 values
-    fn write(&self, quads: &JsValue) -> Result<(), JsValue> {
-        let quads: Vec<Quad> = js_array_to_quad_vec(quads);
-        for quad in &quads {
-            self.qw.add_quad(quad.clone()).unwrap();
+    // or as a {subject, predicate, object, label} or {s, p, o, l} object
+    // options: { strict: bool }, see js_to_quads
+    fn write(&self, quads: &JsValue, options: &JsValue) -> Result<(), JsValue> {
+        let quads: Vec<Quad> = js_to_quads(quads, options)?;
+        self.write_quads(quads)?;
+        Ok(())
+    }
+
+    fn write_quads(&self, quads: Vec<Quad>) -> Result<(), Error> {
+        self.check_sync_write()?;
+        for quad in quads {
+            self.qw.add_quad(quad).map_err(Error::from_quad_writer)?;
         }
         Ok(())
     }
@@ -125,9 +414,20 @@ impl Session {
     //     obj: String | [String]
     //     label: String | [String]
     // }
-    fn read(&self, filter: &JsValue) -> Result<QuadIterator, JsValue> {
+    // options: { objects: bool } to return the quads as objects instead of arrays
+    fn read(&self, filter: &JsValue, options: &JsValue) -> Result<QuadIterator, JsValue> {
+        return Ok(QuadIterator {
+            iterator: self.quads(filter),
+            objects: js_option_bool(options, "objects").unwrap_or(false)
+        })
+    }
+
+    // the quads matching a read filter
+    fn quads(&self, filter: &JsValue) -> Box<dyn Iterator<Item = Quad>> {
+        self.filter_quads(js_to_filter_quads(filter))
+    }
 
-        let mut quad_filter = js_to_filter_quads(filter);
+    fn filter_quads(&self, mut quad_filter: shape::Quads) -> Box<dyn Iterator<Item = Quad>> {
 
         let quad_iterator = if quad_filter.0.is_empty() {
             self.qs.borrow().quads_all_iterator()
@@ -136,18 +436,63 @@ impl Session {
         };
 
         let it = quad_iterator.borrow().iterate();
-        return Ok(QuadIterator { iterator: Box::new(iterator::iterate::QuadIterator::new(self.qs.clone(), it)) })
+        Box::new(iterator::iterate::QuadIterator::new(self.qs.clone(), it))
     }
 
     // an array of quads, where each quad is represented as an array of 3 or 4 values
-    fn delete(&self, quads: &JsValue) -> Result<(), JsValue> {
-        let quads: Vec<Quad> = js_array_to_quad_vec(quads);
+    // or as a {subject, predicate, object, label} or {s, p, o, l} object
+    // options: { strict: bool }, see js_to_quads
+    fn delete(&self, quads: &JsValue, options: &JsValue) -> Result<(), JsValue> {
+        let quads: Vec<Quad> = js_to_quads(quads, options)?;
+        self.check_sync_write()?;
         for quad in &quads {
-            self.qw.remove_quad(quad.clone()).unwrap();
+            self.qw.remove_quad(quad.clone()).map_err(Error::from_quad_writer)?;
+        }
+        Ok(())
+    }
+
+    // a graph with an async store, such as IndexedDB, can only be written with writeAsync, deleteAsync,
+    // the imports and bulkLoad, which resolve once the quads are committed
+    fn check_sync_write(&self) -> Result<(), Error> {
+        if self.persist.is_some() {
+            return Err(Error::Unsupported("This graph is written asynchronously, use writeAsync or deleteAsync".to_string()))
+        }
+        Ok(())
+    }
+
+    // adds (or deletes) the quads through the async store if there is one, otherwise through the quad writer
+    async fn apply_async(session: Rc<RefCell<Session>>, quads: Vec<Quad>, add: bool) -> Result<(), JsValue> {
+        let persist = session.borrow().persist.clone();
+
+        if let Some(p) = persist {
+            let deltas: Vec<Delta> = quads.into_iter().map(|quad| Delta {
+                quad,
+                action: if add { Procedure::Add } else { Procedure::Delete }
+            }).collect();
+
+            return Ok(p.write_deltas(deltas, IgnoreOptions{ignore_dup: true, ignore_missing: true}).await?)
+        }
+
+        let s = session.borrow();
+        for quad in quads {
+            let res = if add {
+                s.qw.add_quad(quad)
+            } else {
+                s.qw.remove_quad(quad)
+            };
+            res.map_err(Error::from_quad_writer)?;
         }
         Ok(())
     }
 
+    async fn flush(session: &Rc<RefCell<Session>>) {
+        let persist = session.borrow().persist.clone();
+
+        if let Some(p) = persist {
+            p.flush().await;
+        }
+    }
+
     fn run_tag_each_iterator(&mut self, it: Rc<RefCell<dyn iterator::Shape>>) -> iterator::iterate::TagEachIterator {
         iterator::iterate::TagEachIterator::new(it, false, true)
     }
@@ -272,6 +617,75 @@ impl Path {
         self.session.borrow_mut().run_each_iterator(it).count() as i32
     }
 
+    // the iterator tree of the path with its estimated sizes and costs, before and after it is optimized
+    pub fn explain(&self) -> JsValue {
+        explain::explain(&|| self.build_iterator_tree())
+    }
+
+    // groups the tags of the results into nested objects, see tree::Template
+    #[wasm_bindgen(js_name = toTree)]
+    pub fn to_tree(&self, template: &JsValue) -> Result<JsValue, JsValue> {
+        let template = tree::Template::new(template)?;
+        let rows: Vec<HashMap<String, Value>> = self.iter_tags(None).iterator.collect();
+        Ok(template.build(&rows))
+    }
+
+
+    ///////////////
+    // Async Finals
+    // these wait for pending writes to the store before running the query
+    ///////////////
+
+    #[wasm_bindgen(js_name = allAsync)]
+    pub fn all_async(&self) -> js_sys::Promise {
+        self.iter_tags_async(None)
+    }
+
+    #[wasm_bindgen(js_name = getLimitAsync)]
+    pub fn get_limit_async(&self, limit: Option<usize>) -> js_sys::Promise {
+        self.iter_tags_async(limit)
+    }
+
+    #[wasm_bindgen(js_name = iterTagsAsync)]
+    pub fn iter_tags_async(&self, limit: Option<usize>) -> js_sys::Promise {
+        let path = self.clone();
+
+        future_to_promise(async move {
+            Session::flush(&path.session).await;
+            Ok(path.iter_tags(limit).into())
+        })
+    }
+
+    #[wasm_bindgen(js_name = iterValuesAsync)]
+    pub fn iter_values_async(&self, limit: Option<usize>) -> js_sys::Promise {
+        let path = self.clone();
+
+        future_to_promise(async move {
+            Session::flush(&path.session).await;
+            Ok(path.iter_values(limit).into())
+        })
+    }
+
+    #[wasm_bindgen(js_name = countAsync)]
+    pub fn count_async(&self) -> js_sys::Promise {
+        let mut path = self.clone();
+
+        future_to_promise(async move {
+            Session::flush(&path.session).await;
+            Ok(JsValue::from(path.count()))
+        })
+    }
+
+    #[wasm_bindgen(js_name = toTreeAsync)]
+    pub fn to_tree_async(&self, template: JsValue) -> js_sys::Promise {
+        let path = self.clone();
+
+        future_to_promise(async move {
+            Session::flush(&path.session).await;
+            path.to_tree(&template)
+        })
+    }
+
 
     ///////////////
     // Traversals
@@ -488,10 +902,10 @@ impl Path {
     pub fn save_values(&mut self, js_value: &JsValue, tag: Option<String>, rev: bool, opt: bool) -> Result<Path, JsValue> {
         let node = js_to_value_ignore(js_value);
         if let Value::None = node {
-            return Err(JsValue::from_str("must specify a predicate"))
+            return Err(Error::InvalidInput("must specify a predicate".to_string()).into())
         } 
         if let Value::Null = node {
-            return Err(JsValue::from_str("must specify a predicate"))
+            return Err(Error::InvalidInput("must specify a predicate".to_string()).into())
         } 
 
         let tag = if let Some(t) = tag {
@@ -636,7 +1050,9 @@ impl Path {
 
 #[wasm_bindgen]
 pub struct QuadIterator {
-    iterator: Box<dyn Iterator<Item = Quad>>
+    iterator: Box<dyn Iterator<Item = Quad>>,
+    // return {subject, predicate, object, label} objects instead of arrays
+    objects: bool
 }
 
 #[wasm_bindgen]
@@ -645,7 +1061,12 @@ impl QuadIterator {
         let obj:JsValue = js_sys::Object::new().into();
 
         if let Some(next) = self.iterator.next() {
-            js_sys::Reflect::set(&obj, &"value".into(), &quad_to_js(&next))?;
+            let value = if self.objects {
+                quad_to_js_object(&next)
+            } else {
+                quad_to_js(&next)
+            };
+            js_sys::Reflect::set(&obj, &"value".into(), &value)?;
             js_sys::Reflect::set(&obj, &"done".into(), &JsValue::from_bool(false))?;
         } else {
             js_sys::Reflect::set(&obj, &"done".into(), &JsValue::from_bool(true))?;
@@ -716,6 +1137,19 @@ fn js_array_to_value_filters(v: &JsValue) -> Vec<Rc<dyn shape::ValueFilter>> {
 }
 
 
+// a comparison with a date only matches dates, see term::date_time_pattern
+fn comparison(op: iterator::value_filter::Operator, value: Value) -> Vec<Rc<dyn shape::ValueFilter>> {
+    let mut res: Vec<Rc<dyn shape::ValueFilter>> = Vec::new();
+
+    if let Some(pattern) = term::date_time_pattern(&value) {
+        res.push(Rc::new(shape::Wildcard::new(pattern.into())));
+    }
+
+    res.push(Rc::new(shape::Comparison::new(op, value)));
+    res
+}
+
+
 fn js_object_to_value_filters(obj: &JsValue) -> Vec<Rc<dyn shape::ValueFilter>> {
 
     let mut res = Vec::new();
@@ -732,32 +1166,28 @@ fn js_object_to_value_filters(obj: &JsValue) -> Vec<Rc<dyn shape::ValueFilter>>
                     if name == "lt" {
                         if let Ok(value) = js_sys::Reflect::get(obj, &k) {
                             let v = js_to_value_ignore(&value);
-                            let vf = Rc::new(shape::Comparison::new(iterator::value_filter::Operator::LT, v));
-                            res.push(vf)
+                            res.extend(comparison(iterator::value_filter::Operator::LT, v));
                         }
                     } 
                     
                     if name == "lte" {
                         if let Ok(value) = js_sys::Reflect::get(obj, &k) {
                             let v = js_to_value_ignore(&value);
-                            let vf = Rc::new(shape::Comparison::new(iterator::value_filter::Operator::LTE, v.into()));
-                            res.push(vf)
+                            res.extend(comparison(iterator::value_filter::Operator::LTE, v));
                         }
                     } 
                     
                     if name == "gt" {
                         if let Ok(value) = js_sys::Reflect::get(obj, &k) {
                             let v = js_to_value_ignore(&value);
-                            let vf = Rc::new(shape::Comparison::new(iterator::value_filter::Operator::GT, v.into()));
-                            res.push(vf)
+                            res.extend(comparison(iterator::value_filter::Operator::GT, v));
                         }
                     } 
                     
                     if name == "gte" {
                         if let Ok(value) = js_sys::Reflect::get(obj, &k) {
                             let v = js_to_value_ignore(&value);
-                            let vf = Rc::new(shape::Comparison::new(iterator::value_filter::Operator::GTE, v.into()));
-                            res.push(vf)
+                            res.extend(comparison(iterator::value_filter::Operator::GTE, v));
                         }
                     } 
                     
@@ -902,20 +1332,113 @@ fn js_to_value(js: &JsValue) -> Option<Value> {
 
     let opt_n = js.as_f64();
     if let Some(n) = opt_n {
-        if let Some(f) = Number::from_f64(n) {
+        if let Some(f) = term::number(n) {
             return Some(Value::Number(f))
         }
     } 
 
+    // a BigInt is an integer, None if it does not fit in 64 bits
+    if js.is_bigint() {
+        let digits = js.unchecked_ref::<js_sys::BigInt>().to_string(10).ok()?;
+        return term::integer(&String::from(digits)).map(Value::Number)
+    }
+
     let opt_s = js.as_string();
     if let Some(s) = opt_s {
-        return Some(Value::from(s))
+        return match Value::from(s) {
+            Value::String(s) => Some(term::plain(s)),
+            v => Some(v)
+        }
     } 
 
+    // a Date is an xsd:dateTime literal, whose fixed width form sorts and compares in time order
+    if let Some(date) = js.dyn_ref::<js_sys::Date>() {
+        if date.get_time().is_nan() {
+            return None
+        }
+        return Some(term::date_time(&String::from(date.to_iso_string())).to_value())
+    }
+
+    if js.is_object() {
+        if let Some(t) = js_object_to_term(js) {
+            return Some(t.to_value())
+        }
+    }
+
+    None
+}
+
+
+// {iri}, {bnode} or {literal, datatype, lang}
+// literals with a numeric or boolean XSD datatype become numbers and booleans, see Term::to_value
+fn js_object_to_term(js: &JsValue) -> Option<Term> {
+    let get = |key: &str| js_sys::Reflect::get(js, &JsValue::from_str(key)).ok().and_then(|v| v.as_string());
+
+    if let Some(iri) = get("iri") {
+        if iri.starts_with('<') && iri.ends_with('>') && iri.len() >= 2 {
+            return Some(Term::Iri(iri[1..iri.len() - 1].to_string()))
+        }
+        return Some(Term::Iri(iri))
+    }
+
+    if let Some(label) = get("bnode") {
+        if label.starts_with("_:") {
+            return Some(Term::BlankNode(label[2..].to_string()))
+        }
+        return Some(Term::BlankNode(label))
+    }
+
+    if let Some(value) = get("literal") {
+        return Some(Term::Literal {
+            value,
+            datatype: get("datatype").map(|d| term::expand_datatype(&d)),
+            lang: get("lang")
+        })
+    }
+
     None
 }
 
 
+// blank nodes and literals with a datatype or a language tag as {bnode} and {literal, datatype, lang} objects,
+// xsd:dateTime literals with a time zone as a Date, other strings as they are. A dateTime without
+// a zone is a local time that a Date would move to the zone of the browser, so it stays a literal.
+fn string_to_js(s: &str) -> JsValue {
+    if s.starts_with("_:") || s.starts_with('"') {
+        match Term::from_value(&Value::String(s.to_string())) {
+            Some(Term::BlankNode(label)) => {
+                let obj = js_sys::Object::new();
+                let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("bnode"), &JsValue::from_str(&label));
+                return obj.into()
+            },
+            // a plain string stored in its N-Triples form, see term::plain
+            Some(Term::Literal { value, datatype: None, lang: None }) => return JsValue::from_str(&value),
+            Some(Term::Literal { value, datatype, lang }) => {
+                if datatype == Some(format!("{}dateTime", term::XSD)) && term::has_zone(&value) {
+                    let date = js_sys::Date::new(&JsValue::from_str(&value));
+                    if !date.get_time().is_nan() {
+                        return date.into()
+                    }
+                }
+
+                let obj = js_sys::Object::new();
+                let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("literal"), &JsValue::from_str(&value));
+                if let Some(d) = datatype {
+                    let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("datatype"), &JsValue::from_str(&d));
+                }
+                if let Some(l) = lang {
+                    let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("lang"), &JsValue::from_str(&l));
+                }
+                return obj.into()
+            },
+            _ => {}
+        }
+    }
+
+    JsValue::from_str(s)
+}
+
+
 fn js_to_value_ignore(js: &JsValue) -> Value {
     if let Some(s) = js_to_value(js) {
         s
@@ -925,39 +1448,45 @@ fn js_to_value_ignore(js: &JsValue) -> Value {
 }
 
 
-fn js_to_quad(js: &JsValue) -> Option<Quad> {
+// the subject, predicate, object and label of a quad given as an array of 3 or 4 values,
+// or as an object with subject, predicate, object and label (or s, p, o and l) properties
+fn js_quad_parts(js: &JsValue) -> Result<Vec<JsValue>, String> {
+    if js_sys::Array::is_array(js) {
+        let arr = js_sys::Array::from(js);
+        let len = js_sys::Array::length(&arr);
 
-    if !js_sys::Array::is_array(js) {
-        return None
-    } 
+        if len < 3 || len > 4 {
+            return Err(format!("expected 3 or 4 values, found {}", len))
+        }
 
-    let arr = js_sys::Array::from(js);
+        return Ok((0..4).map(|i| arr.get(i)).collect())
+    }
 
-    if js_sys::Array::length(&arr) < 3 ||  js_sys::Array::length(&arr) > 4 {
-        return None
-    } 
+    if js.is_object() {
+        let parts = [("subject", "s"), ("predicate", "p"), ("object", "o"), ("label", "l")].iter().map(|(long, short)| {
+            let v = js_sys::Reflect::get(js, &JsValue::from_str(long)).unwrap_or(JsValue::undefined());
+            if v.is_undefined() {
+                js_sys::Reflect::get(js, &JsValue::from_str(short)).unwrap_or(JsValue::undefined())
+            } else {
+                v
+            }
+        }).collect();
 
-    let res_s = arr.get(0);
-    let subject = js_to_value_ignore(&res_s);
+        return Ok(parts)
+    }
 
-    let res_p = arr.get(1);
-    let predicate = js_to_value_ignore(&res_p);
+    Err("expected an array of 3 or 4 values or a quad object".to_string())
+}
 
-    let res_o = arr.get(2);
-    let object = js_to_value_ignore(&res_o);
 
-    let label = if js_sys::Array::length(&arr) == 4 {
-        let res_l = arr.get(3);
-        js_to_value_ignore(&res_l)
-    } else {
-        Value::None
-    };
+fn js_to_quad(js: &JsValue) -> Option<Quad> {
+    let parts = js_quad_parts(js).ok()?;
 
     Some(Quad {
-        subject,
-        predicate,
-        object,
-        label
+        subject: js_to_value_ignore(&parts[0]),
+        predicate: js_to_value_ignore(&parts[1]),
+        object: js_to_value_ignore(&parts[2]),
+        label: js_to_value_ignore(&parts[3])
     })
 }
 
@@ -967,19 +1496,48 @@ pub fn value_to_js(value: &Value) -> JsValue {
         Value::None => JsValue::undefined(),
         Value::Null => JsValue::null(),
         Value::Bool(b) => JsValue::from_bool(*b),
-        Value::Number(n) => {
-            if let Some(f) = n.as_f64() {
-                JsValue::from_f64(f)
-            } else {
-                JsValue::undefined()
-            }
-        },
+        Value::Number(n) => number_to_js(n),
         Value::IRI(_) => JsValue::from_str(&value.to_string()),
-        Value::String(s) => JsValue::from_str(&s),
+        Value::String(s) => string_to_js(s),
     }
 }
 
 
+// integers outside the range JavaScript numbers hold exactly become a BigInt
+fn number_to_js(n: &Number) -> JsValue {
+    let safe = -term::MAX_SAFE_INTEGER..=term::MAX_SAFE_INTEGER;
+
+    if let Some(i) = n.as_i64() {
+        if safe.contains(&i) {
+            return JsValue::from_f64(i as f64)
+        }
+        return js_sys::BigInt::from(i).into()
+    }
+
+    if let Some(u) = n.as_u64() {
+        return js_sys::BigInt::from(u).into()
+    }
+
+    match n.as_f64() {
+        Some(f) => JsValue::from_f64(f),
+        None => JsValue::undefined()
+    }
+}
+
+
+// {subject, predicate, object, label}
+pub fn quad_to_js_object(quad: &Quad) -> JsValue {
+    let obj = js_sys::Object::new();
+
+    let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("subject"), &value_to_js(&quad.subject));
+    let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("predicate"), &value_to_js(&quad.predicate));
+    let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("object"), &value_to_js(&quad.object));
+    let _ = js_sys::Reflect::set(&obj, &JsValue::from_str("label"), &value_to_js(&quad.label));
+
+    obj.into()
+}
+
+
 pub fn quad_to_js(quad: &Quad) -> JsValue {
     let arr = js_sys::Array::new();
     
@@ -1001,7 +1559,7 @@ fn quad_vec_to_js(quads: &Vec<Quad>) -> JsValue {
 }
 
 
-// TODO: instead of ignoring invalid data return result with error
+// the lenient conversion, quads that are not valid are skipped
 fn js_array_to_quad_vec(js: &JsValue) -> Vec<Quad> {
     if !js_sys::Array::is_array(js) {
         return Vec::new()
@@ -1014,6 +1572,79 @@ fn js_array_to_quad_vec(js: &JsValue) -> Vec<Quad> {
     values
 }
 
+// a quad as an array or an object, see js_quad_parts, with the reason if it is not valid
+fn js_to_quad_checked(js: &JsValue) -> Result<Quad, String> {
+    let parts = js_quad_parts(js)?;
+
+    let mut values = Vec::new();
+
+    for (i, name) in ["subject", "predicate", "object", "label"].iter().enumerate() {
+        match js_to_value(&parts[i]) {
+            // only the label is optional
+            Some(Value::None) | Some(Value::Null) if i < 3 => return Err(format!("{} is missing", name)),
+            Some(v) => values.push(v),
+            None if parts[i].is_bigint() => return Err(format!("{} does not fit in 64 bits", name)),
+            None => return Err(format!("{} is not a string, number, boolean or value object", name))
+        }
+    }
+
+    let label = values.pop().unwrap();
+    let object = values.pop().unwrap();
+    let predicate = values.pop().unwrap();
+    let subject = values.pop().unwrap();
+
+    Ok(Quad {
+        subject,
+        predicate,
+        object,
+        label
+    })
+}
+
+
+// The quads of a write or delete. In strict mode, the default, one malformed quad rejects the
+// whole batch with the index and reason of every malformed quad. With { strict: false } they are skipped.
+fn js_to_quads(js: &JsValue, options: &JsValue) -> Result<Vec<Quad>, Error> {
+    if !js_option_bool(options, "strict").unwrap_or(true) {
+        return Ok(js_array_to_quad_vec(js))
+    }
+
+    if !js_sys::Array::is_array(js) {
+        return Err(Error::InvalidInput("expected an array of quads".to_string()))
+    }
+
+    let arr = js_sys::Array::from(js);
+
+    let mut quads = Vec::new();
+    let mut errors = Vec::new();
+
+    for i in 0..js_sys::Array::length(&arr) {
+        match js_to_quad_checked(&arr.get(i)) {
+            Ok(q) => quads.push(q),
+            Err(reason) => errors.push((i as usize, reason))
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::InvalidQuads(errors))
+    }
+
+    Ok(quads)
+}
+
+
+fn js_option_bool(options: &JsValue, key: &str) -> Option<bool> {
+    if !options.is_object() {
+        return None
+    }
+
+    match js_sys::Reflect::get(options, &JsValue::from_str(key)) {
+        Ok(v) => v.as_bool(),
+        Err(_) => None
+    }
+}
+
+
 fn values_to_via(values: Vec<Value>) -> path::Via {
     if values.is_empty() {
         return path::Via::None
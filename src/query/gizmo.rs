@@ -0,0 +1,654 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{Graph, Path, value_to_js, hash_map_to_js_obj};
+use crate::error::Error;
+use crate::format::term;
+
+use gizmo_db::graph::value::Value;
+
+
+// Runs a query written like the JavaScript method chain, e.g.
+//
+//   g.V("<alice>").out("<follows>").has("<age>", gt(30)).all()
+//
+// Steps and finals take the same arguments as the methods of Path in gizmo.js: strings, numbers,
+// BigInts (1n), booleans, null, arrays, objects, other paths (g.M()... or g.V()...), new Date(...)
+// and the filter and value helpers lt, lte, gt, gte, regex, like, iri, bnode and literal,
+// optionally written as gizmo.gt(...). A query without a final runs all().
+pub fn run(graph: &Graph, text: &str) -> Result<JsValue, JsValue> {
+    let chain = parse(text)?;
+    let path = eval_path(graph, &chain)?;
+
+    match &chain.last {
+        Some(step) => final_step(graph, path, step),
+        None => Ok(path.all().into())
+    }
+}
+
+
+// a query as it was written, without being run
+pub fn parse(text: &str) -> Result<Chain, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+
+    let chain = match parser.expr()? {
+        Expr::Chain(chain) => chain,
+        _ => return Err(parser.error("expected a query starting with g.V() or g.M()"))
+    };
+
+    parser.eat(&Token::Punct(';'));
+
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("unexpected text after the query"))
+    }
+
+    Ok(chain)
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Str(String),
+    Num(f64),
+    BigInt(String),
+    Bool(bool),
+    Null,
+    Undefined,
+    Array(Vec<Expr>),
+    Object(Vec<(String, Expr)>),
+    // a helper such as gt(5), or new Date(...)
+    Call(String, Vec<Expr>),
+    Chain(Chain),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chain {
+    pub steps: Vec<Step>,
+    // the final of the query, if it has one
+    pub last: Option<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub name: String,
+    pub args: Vec<Expr>,
+    // the column of the step, for errors
+    pub column: usize,
+}
+
+
+fn is_final(name: &str) -> bool {
+    match name {
+        "all" | "getLimit" | "iterTags" | "iterValues" | "toArray" | "toValue" | "tagArray" | "tagValue" | "count" | "toTree" | "explain" => true,
+        _ => false
+    }
+}
+
+
+// a step argument, which is passed to Path the way gizmo.js passes it
+enum Arg {
+    Js(JsValue),
+    Path(Path),
+}
+
+impl Arg {
+    fn js(&self) -> JsValue {
+        match self {
+            Arg::Js(v) => v.clone(),
+            Arg::Path(_) => JsValue::undefined()
+        }
+    }
+}
+
+
+fn eval_path(graph: &Graph, chain: &Chain) -> Result<Path, JsValue> {
+    let mut steps = chain.steps.iter();
+
+    let first = match steps.next() {
+        Some(s) => s,
+        None => return Err(Error::InvalidInput("empty query".to_string()).into())
+    };
+
+    let mut path = match first.name.as_str() {
+        "V" | "Vertex" => {
+            let args = eval_args(graph, &first.args)?;
+            graph.v(&args_array(&args))
+        },
+        "M" | "Morphism" => graph.m(),
+        name => return Err(step_error(first, &format!("a query starts with V or M, not {}", name)))
+    };
+
+    for step in steps {
+        path = apply(graph, path, step)?;
+    }
+
+    Ok(path)
+}
+
+
+fn eval_args(graph: &Graph, args: &[Expr]) -> Result<Vec<Arg>, JsValue> {
+    args.iter().map(|a| eval(graph, a)).collect()
+}
+
+
+fn eval(graph: &Graph, expr: &Expr) -> Result<Arg, JsValue> {
+    if let Expr::Chain(chain) = expr {
+        if let Some(step) = &chain.last {
+            return Err(step_error(step, "a path argument can not end with a final"))
+        }
+        return Ok(Arg::Path(eval_path(graph, chain)?))
+    }
+
+    Ok(Arg::Js(to_js(graph, expr)?))
+}
+
+
+fn to_js(graph: &Graph, expr: &Expr) -> Result<JsValue, JsValue> {
+    let js = match expr {
+        Expr::Str(s) => JsValue::from_str(s),
+        Expr::Num(n) => JsValue::from_f64(*n),
+        Expr::BigInt(digits) => match term::integer(digits) {
+            Some(n) => value_to_js(&Value::Number(n)),
+            None => return Err(Error::InvalidInput(format!("{}n does not fit in 64 bits", digits)).into())
+        },
+        Expr::Bool(b) => JsValue::from_bool(*b),
+        Expr::Null => JsValue::null(),
+        Expr::Undefined => JsValue::undefined(),
+        Expr::Array(items) => {
+            let arr = js_sys::Array::new();
+            for item in items {
+                arr.push(&to_js(graph, item)?);
+            }
+            arr.into()
+        },
+        Expr::Object(entries) => {
+            let obj = js_sys::Object::new();
+            for (key, value) in entries {
+                js_sys::Reflect::set(&obj, &JsValue::from_str(key), &to_js(graph, value)?)?;
+            }
+            obj.into()
+        },
+        Expr::Call(name, args) => helper(graph, name, args)?,
+        Expr::Chain(_) => return Err(Error::InvalidInput("a path can only be a step argument".to_string()).into())
+    };
+
+    Ok(js)
+}
+
+
+// the filter and value helpers of gizmo.js
+fn helper(graph: &Graph, name: &str, args: &[Expr]) -> Result<JsValue, JsValue> {
+    let args: Vec<JsValue> = args.iter().map(|a| to_js(graph, a)).collect::<Result<_, _>>()?;
+    let arg = |i: usize| args.get(i).cloned().unwrap_or(JsValue::undefined());
+
+    let object = |entries: &[(&str, JsValue)]| -> JsValue {
+        let obj = js_sys::Object::new();
+        for (key, value) in entries {
+            if !value.is_undefined() && !value.is_null() {
+                let _ = js_sys::Reflect::set(&obj, &JsValue::from_str(key), value);
+            }
+        }
+        obj.into()
+    };
+
+    let js = match name {
+        "lt" | "lte" | "gt" | "gte" => object(&[(name, arg(0))]),
+        "regex" => object(&[("regex", arg(0)), ("iri", arg(1))]),
+        "like" => object(&[("like", arg(0))]),
+        "iri" => object(&[("iri", arg(0))]),
+        "bnode" => object(&[("bnode", arg(0))]),
+        "literal" => object(&[("literal", arg(0)), ("datatype", arg(1)), ("lang", arg(2))]),
+        "Date" => js_sys::Date::new(&arg(0)).into(),
+        name => return Err(Error::InvalidInput(format!("unknown function {}", name)).into())
+    };
+
+    Ok(js)
+}
+
+
+fn args_array(args: &[Arg]) -> JsValue {
+    let arr = js_sys::Array::new();
+    for a in args {
+        arr.push(&a.js());
+    }
+    arr.into()
+}
+
+
+fn step_error(step: &Step, message: &str) -> JsValue {
+    Error::InvalidInput(format!("column {}: {}", step.column, message)).into()
+}
+
+
+// a Date, {iri}, {bnode} or {literal, datatype, lang}, see is_value_object in gizmo.js
+fn is_value_object(v: &JsValue) -> bool {
+    if v.is_instance_of::<js_sys::Date>() {
+        return true
+    }
+
+    let has = |key: &str| js_sys::Reflect::has(v, &JsValue::from_str(key)).unwrap_or(false);
+
+    has("iri") || has("bnode") || has("literal")
+}
+
+
+// an object or an array of objects, that are not values, see has_filter in gizmo.js
+fn is_filter(v: &JsValue) -> bool {
+    if js_sys::Array::is_array(v) {
+        let first = js_sys::Array::from(v).get(0);
+        return first.is_object() && !is_value_object(&first)
+    }
+
+    v.is_object() && !is_value_object(v)
+}
+
+
+// one step, as the Path methods of gizmo.js dispatch it
+fn apply(graph: &Graph, mut path: Path, step: &Step) -> Result<Path, JsValue> {
+    let args = eval_args(graph, &step.args)?;
+
+    let js = |i: usize| args.get(i).map(|a| a.js()).unwrap_or(JsValue::undefined());
+    let sub_path = |i: usize| match args.get(i) {
+        Some(Arg::Path(p)) => Some(p),
+        _ => None
+    };
+    let need_path = |i: usize| sub_path(i).ok_or_else(|| step_error(step, &format!("{} takes a path", step.name)));
+    let number = |i: usize| js(i).as_f64().ok_or_else(|| step_error(step, &format!("{} takes a number", step.name)));
+    let string = |i: usize| js(i).as_string().ok_or_else(|| step_error(step, &format!("{} takes a string", step.name)));
+    let opt_string = |i: usize| js(i).as_string();
+
+    match step.name.as_str() {
+        "is" => path.is(&args_array(&args)),
+        "in" | "out" | "both" => {
+            match (step.name.as_str(), sub_path(0)) {
+                ("in", Some(p)) => path.in_path(p, &js(1)),
+                ("out", Some(p)) => path.out_path(p, &js(1)),
+                ("both", Some(p)) => path.both_path(p, &js(1)),
+                ("in", None) => path.in_values(&js(0), &js(1)),
+                ("out", None) => path.out_values(&js(0), &js(1)),
+                (_, None) => path.both_values(&js(0), &js(1)),
+                _ => unreachable!()
+            }
+        },
+        "follow" => path.follow(need_path(0)?),
+        "followR" => path.follow_r(need_path(0)?),
+        "followRecursive" => {
+            let depth = js(2).as_f64().map(|d| d as i32);
+            match sub_path(0) {
+                Some(p) => path.follow_recursive_path(p, &js(1), depth),
+                None => path.follow_recursive_values(&js(0), &js(1), depth)
+            }
+        },
+        "and" | "intersect" => path.intersect(need_path(0)?),
+        "or" | "union" => path.union(need_path(0)?),
+        "except" | "difference" => path.except(need_path(0)?),
+        "back" => path.back(string(0)?),
+        "tag" | "as" => path.tag(&args_array(&args)),
+        "has" | "hasR" => {
+            let rev = step.name == "hasR";
+            let object = js(1);
+            match (sub_path(0), is_filter(&object)) {
+                (Some(p), true) => path.has_path_filter(p, &object, rev),
+                (Some(p), false) => path.has_path_value(p, &object, rev),
+                (None, true) => path.has_value_filter(&js(0), &object, rev),
+                (None, false) => path.has_value_value(&js(0), &object, rev)
+            }
+        },
+        "save" | "saveR" | "saveOpt" | "saveOptR" => {
+            let rev = step.name.ends_with('R');
+            let opt = step.name.starts_with("saveOpt");
+            match sub_path(0) {
+                Some(p) => path.save_path(p, string(1)?, rev, opt),
+                None => path.save_values(&js(0), opt_string(1), rev, opt)
+            }
+        },
+        "unique" => path.unique(),
+        "labels" => path.labels(),
+        "inPredicates" => path.in_predicates(),
+        "outPredicates" => path.out_predicates(),
+        "saveInPredicates" => path.save_in_predicates(string(0)?),
+        "saveOutPredicates" => path.save_out_predicates(string(0)?),
+        "labelContext" => match sub_path(0) {
+            Some(p) => path.label_context_path(p, &js(1)),
+            None => path.label_context_values(&js(0), &js(1))
+        },
+        "filter" => path.filter(&args_array(&args)),
+        "limit" => path.limit(number(0)? as i32),
+        "skip" => path.skip(number(0)? as i32),
+        "order" => path.order(),
+        name => Err(step_error(step, &format!("unknown step {}", name)))
+    }
+}
+
+
+fn final_step(graph: &Graph, mut path: Path, step: &Step) -> Result<JsValue, JsValue> {
+    if step.name == "toTree" {
+        let template = match step.args.first() {
+            Some(arg) => to_js(graph, arg)?,
+            None => return Err(step_error(step, "toTree takes a template"))
+        };
+        return path.to_tree(&template)
+    }
+
+    let limit = match step.args.first() {
+        Some(Expr::Num(n)) => Some(*n as usize),
+        Some(_) => return Err(step_error(step, &format!("{} takes a number", step.name))),
+        None => None
+    };
+
+    let js = match step.name.as_str() {
+        "all" => path.all().into(),
+        "getLimit" | "iterTags" => path.iter_tags(limit).into(),
+        "iterValues" => path.iter_values(limit).into(),
+        "count" => JsValue::from(path.count()),
+        "explain" => path.explain(),
+        "toArray" => path.iter_values(limit).iterator.map(|v| value_to_js(&v)).collect::<js_sys::Array>().into(),
+        "tagArray" => path.iter_tags(limit).iterator.map(|t| hash_map_to_js_obj(&t)).collect::<js_sys::Array>().into(),
+        "toValue" => path.iter_values(Some(1)).iterator.next().map(|v| value_to_js(&v)).unwrap_or(JsValue::undefined()),
+        "tagValue" => path.iter_tags(Some(1)).iterator.next().map(|t| hash_map_to_js_obj(&t)).unwrap_or(JsValue::undefined()),
+        name => return Err(step_error(step, &format!("unknown final {}", name)))
+    };
+
+    Ok(js)
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    BigInt(String),
+    Punct(char),
+}
+
+
+// the tokens and the column each starts at
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    let error = |pos: usize, message: &str| Error::InvalidInput(format!("column {}: {}", pos + 1, message));
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+
+        if c.is_whitespace() {
+            pos += 1;
+            continue
+        }
+
+        if c.is_alphabetic() || c == '_' || c == '$' {
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_' || chars[pos] == '$') {
+                pos += 1;
+            }
+            tokens.push((Token::Ident(chars[start..pos].iter().collect()), start + 1));
+            continue
+        }
+
+        if c.is_ascii_digit() || (c == '.' && pos + 1 < chars.len() && chars[pos + 1].is_ascii_digit()) {
+            while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.') {
+                pos += 1;
+            }
+            if pos < chars.len() && (chars[pos] == 'e' || chars[pos] == 'E') {
+                pos += 1;
+                if pos < chars.len() && (chars[pos] == '+' || chars[pos] == '-') {
+                    pos += 1;
+                }
+                while pos < chars.len() && chars[pos].is_ascii_digit() {
+                    pos += 1;
+                }
+            }
+
+            let digits: String = chars[start..pos].iter().collect();
+
+            if pos < chars.len() && chars[pos] == 'n' {
+                pos += 1;
+                if !digits.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(error(start, "invalid BigInt"))
+                }
+                tokens.push((Token::BigInt(digits), start + 1));
+                continue
+            }
+
+            match digits.parse::<f64>() {
+                Ok(n) => tokens.push((Token::Num(n), start + 1)),
+                Err(_) => return Err(error(start, &format!("invalid number {}", digits)))
+            }
+            continue
+        }
+
+        if c == '"' || c == '\'' {
+            pos += 1;
+            let mut s = String::new();
+
+            loop {
+                match chars.get(pos) {
+                    Some(q) if *q == c => {
+                        pos += 1;
+                        break
+                    },
+                    Some('\\') => {
+                        let escaped = match chars.get(pos + 1) {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('b') => '\u{8}',
+                            Some('f') => '\u{c}',
+                            Some('0') => '\0',
+                            Some('u') => {
+                                let hex: String = chars.iter().skip(pos + 2).take(4).collect();
+                                let c = u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32);
+                                match c {
+                                    Some(c) if hex.len() == 4 => {
+                                        pos += 4;
+                                        c
+                                    },
+                                    _ => return Err(error(pos, "invalid unicode escape"))
+                                }
+                            },
+                            Some(c) => *c,
+                            None => return Err(error(start, "unterminated string"))
+                        };
+                        s.push(escaped);
+                        pos += 2;
+                    },
+                    Some(c) => {
+                        s.push(*c);
+                        pos += 1;
+                    },
+                    None => return Err(error(start, "unterminated string"))
+                }
+            }
+
+            tokens.push((Token::Str(s), start + 1));
+            continue
+        }
+
+        match c {
+            '.' | ',' | '(' | ')' | '[' | ']' | '{' | '}' | ':' | ';' | '-' => {
+                tokens.push((Token::Punct(c), start + 1));
+                pos += 1;
+            },
+            c => return Err(error(start, &format!("unexpected {:?}", c)))
+        }
+    }
+
+    Ok(tokens)
+}
+
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn column(&self) -> usize {
+        match self.tokens.get(self.pos) {
+            Some((_, column)) => *column,
+            None => self.tokens.last().map(|(_, c)| c + 1).unwrap_or(1)
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidInput(format!("column {}: {}", self.column(), message))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.peek().cloned();
+        if t.is_some() {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            return true
+        }
+        false
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        if self.eat(&Token::Punct(c)) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, Error> {
+        match self.peek().cloned() {
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                Ok(name)
+            },
+            _ => Err(self.error("expected a name"))
+        }
+    }
+
+    // comma separated expressions up to the closing character, which may follow a trailing comma
+    fn list(&mut self, close: char) -> Result<Vec<Expr>, Error> {
+        let mut items = Vec::new();
+
+        while !self.eat(&Token::Punct(close)) {
+            items.push(self.expr()?);
+
+            if !self.eat(&Token::Punct(',')) {
+                self.expect(close)?;
+                break
+            }
+        }
+
+        Ok(items)
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        let column = self.column();
+
+        let token = match self.next() {
+            Some(t) => t,
+            None => return Err(self.error("unexpected end of query"))
+        };
+
+        match token {
+            Token::Str(s) => Ok(Expr::Str(s)),
+            Token::Num(n) => Ok(Expr::Num(n)),
+            Token::BigInt(d) => Ok(Expr::BigInt(d)),
+            Token::Punct('-') => {
+                let n = match self.peek().cloned() {
+                    Some(Token::Num(n)) => Expr::Num(-n),
+                    Some(Token::BigInt(d)) => Expr::BigInt(format!("-{}", d)),
+                    _ => return Err(self.error("expected a number"))
+                };
+                self.pos += 1;
+                Ok(n)
+            },
+            Token::Punct('[') => Ok(Expr::Array(self.list(']')?)),
+            Token::Punct('{') => {
+                let mut entries = Vec::new();
+
+                while !self.eat(&Token::Punct('}')) {
+                    let key = match self.peek().cloned() {
+                        Some(Token::Ident(k)) | Some(Token::Str(k)) => k,
+                        _ => return Err(self.error("expected a property name"))
+                    };
+                    self.pos += 1;
+                    self.expect(':')?;
+                    entries.push((key, self.expr()?));
+
+                    if !self.eat(&Token::Punct(',')) {
+                        self.expect('}')?;
+                        break
+                    }
+                }
+
+                Ok(Expr::Object(entries))
+            },
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "null" => Ok(Expr::Null),
+                "undefined" => Ok(Expr::Undefined),
+                "new" => {
+                    let class = self.ident()?;
+                    if class != "Date" {
+                        return Err(self.error("only new Date(...) is supported"))
+                    }
+                    self.expect('(')?;
+                    Ok(Expr::Call(class, self.list(')')?))
+                },
+                "g" | "graph" => self.chain(column),
+                "gizmo" => {
+                    self.expect('.')?;
+                    let helper = self.ident()?;
+                    self.expect('(')?;
+                    Ok(Expr::Call(helper, self.list(')')?))
+                },
+                _ => {
+                    self.expect('(')?;
+                    Ok(Expr::Call(name, self.list(')')?))
+                }
+            },
+            _ => {
+                self.pos -= 1;
+                Err(self.error("unexpected token"))
+            }
+        }
+    }
+
+    fn chain(&mut self, column: usize) -> Result<Expr, Error> {
+        let mut steps = Vec::new();
+
+        while self.eat(&Token::Punct('.')) {
+            let column = self.column();
+            let name = self.ident()?;
+            self.expect('(')?;
+            let args = self.list(')')?;
+
+            steps.push(Step { name, args, column });
+        }
+
+        if steps.is_empty() {
+            return Err(Error::InvalidInput(format!("column {}: expected g.V() or g.M()", column)))
+        }
+
+        let last = if steps.len() > 1 && is_final(&steps[steps.len() - 1].name) {
+            steps.pop()
+        } else {
+            None
+        };
+
+        Ok(Expr::Chain(Chain { steps, last }))
+    }
+}
@@ -0,0 +1,520 @@
+use wasm_bindgen::prelude::*;
+
+use gizmo_db::query::path;
+use gizmo_db::graph::value::Value;
+
+use crate::{Graph, Path, values_to_via, value_to_js, js_array_to_values_vec, js_object_to_value_filters};
+use crate::error::Error;
+
+
+// Runs a query shaped like the JSON it returns, as in Cayley:
+//
+//   {
+//     nodes(id: <alice>) {
+//       id
+//       follows {
+//         id
+//         status @opt
+//       }
+//       followers: follows @rev { id }
+//       status @label(v: <smart_graph>)
+//       age @filter(gt: 30)
+//     }
+//   }
+//
+// The root field selects the nodes (all of them without an id argument) and the result has a key
+// named after it holding an array of objects. Every other field is a predicate, written as a name
+// or as an IRI in <>, which is followed out of the node, or in with @rev. A field without a selection
+// holds the values it reaches, a field with one holds an object for each of them. A field that
+// reaches one value holds it directly, otherwise an array. Fields can be renamed with alias: field.
+//
+// Arguments of a field keep the nodes that have the value for that predicate, except id which keeps
+// the given nodes, and first and offset which limit and skip them. @filter takes the same conditions
+// as filter() (gt, lt, regex, like, ...), @label(v: ...) restricts the field and the fields under it
+// to a label, and @opt keeps a node when the field reaches nothing, which drops the node otherwise.
+// id is the node itself.
+pub fn run(graph: &Graph, text: &str) -> Result<JsValue, Error> {
+    let fields = parse(text)?;
+
+    let result = js_sys::Object::new();
+
+    for field in &fields {
+        let selection = match &field.selection {
+            Some(s) => s,
+            None => return Err(Error::InvalidInput(format!("the root field {} needs a selection of fields", field.name)))
+        };
+
+        let mut ids = Vec::new();
+        let mut args = Vec::new();
+
+        for (name, value) in &field.args {
+            if name == "id" {
+                ids.extend(js_array_to_values_vec(value));
+            } else {
+                args.push((name.clone(), value.clone()));
+            }
+        }
+
+        let qs = graph.session.borrow().qs.clone();
+        let mut p = path::Path::start_path(Some(qs), ids);
+
+        let label = field.label()?;
+        if let Some(l) = &label {
+            p.label_context_with_tags(values_to_via(l.clone()), Vec::new());
+        }
+
+        constrain(&mut p, field, &args)?;
+
+        let nodes = js_sys::Array::new();
+        for node in values(graph, p) {
+            if let Some(obj) = object(graph, &node, selection, &label)? {
+                nodes.push(&obj);
+            }
+        }
+
+        set(&result, field.key(), &nodes);
+    }
+
+    Ok(result.into())
+}
+
+
+struct Field {
+    alias: Option<String>,
+    name: String,
+    args: Vec<(String, JsValue)>,
+    directives: Vec<(String, Vec<(String, JsValue)>)>,
+    selection: Option<Vec<Field>>,
+    column: usize,
+}
+
+impl Field {
+    fn key(&self) -> &str {
+        self.alias.as_ref().unwrap_or(&self.name)
+    }
+
+    fn directive(&self, name: &str) -> Option<&Vec<(String, JsValue)>> {
+        self.directives.iter().find(|(n, _)| n == name).map(|(_, args)| args)
+    }
+
+    fn label(&self) -> Result<Option<Vec<Value>>, Error> {
+        match self.directive("label") {
+            Some(args) => match args.iter().find(|(n, _)| n == "v") {
+                Some((_, v)) => Ok(Some(js_array_to_values_vec(v))),
+                None => Err(Error::InvalidInput(format!("column {}: @label needs a v argument", self.column)))
+            },
+            None => Ok(None)
+        }
+    }
+}
+
+
+// the object of a node with the selected fields, None if a required field reaches nothing
+fn object(graph: &Graph, node: &Value, selection: &[Field], label: &Option<Vec<Value>>) -> Result<Option<JsValue>, Error> {
+    let obj = js_sys::Object::new();
+
+    for field in selection {
+        if field.name == "id" && field.selection.is_none() {
+            set(&obj, field.key(), &value_to_js(node));
+            continue
+        }
+
+        let optional = field.directive("opt").is_some();
+
+        // a label applies to everything under the field
+        let label = match field.label()? {
+            Some(l) => Some(l),
+            None => label.clone()
+        };
+
+        let qs = graph.session.borrow().qs.clone();
+        let mut p = path::Path::start_path(Some(qs), vec![node.clone()]);
+
+        if let Some(l) = &label {
+            p.label_context_with_tags(values_to_via(l.clone()), Vec::new());
+        }
+
+        let via = values_to_via(vec![predicate(&field.name)]);
+        if field.directive("rev").is_some() {
+            p.in_with_tags(Vec::new(), via);
+        } else {
+            p.out_with_tags(Vec::new(), via);
+        }
+
+        constrain(&mut p, field, &field.args)?;
+
+        let found: Vec<JsValue> = match &field.selection {
+            Some(selection) => {
+                let mut objects = Vec::new();
+                for value in values(graph, p) {
+                    if let Some(o) = object(graph, &value, selection, &label)? {
+                        objects.push(o);
+                    }
+                }
+                objects
+            },
+            None => values(graph, p).iter().map(value_to_js).collect()
+        };
+
+        match found.len() {
+            0 if optional => {},
+            0 => return Ok(None),
+            1 => set(&obj, field.key(), &found[0]),
+            _ => set(&obj, field.key(), &found.into_iter().collect::<js_sys::Array>()),
+        }
+    }
+
+    Ok(Some(obj.into()))
+}
+
+
+// applies the arguments and the @filter of a field to the nodes it reaches
+fn constrain(p: &mut path::Path, field: &Field, args: &[(String, JsValue)]) -> Result<(), Error> {
+    let mut first = None;
+    let mut offset = None;
+
+    for (name, value) in args {
+        match name.as_str() {
+            "first" | "offset" => {
+                let n = match value.as_f64() {
+                    Some(n) if n >= 0.0 && n.fract() == 0.0 => n as i64,
+                    _ => return Err(Error::InvalidInput(format!("column {}: {} must be a whole number", field.column, name)))
+                };
+                if name == "first" {
+                    first = Some(n);
+                } else {
+                    offset = Some(n);
+                }
+            },
+            "id" => {
+                p.is(js_array_to_values_vec(value));
+            },
+            _ => {
+                p.has(values_to_via(vec![predicate(name)]), false, js_array_to_values_vec(value));
+            }
+        }
+    }
+
+    if let Some(args) = field.directive("filter") {
+        let obj = js_sys::Object::new();
+        for (k, v) in args {
+            set(&obj, k, v);
+        }
+        p.filters(js_object_to_value_filters(&obj));
+    }
+
+    if let Some(n) = offset {
+        p.skip(n);
+    }
+
+    if let Some(n) = first {
+        p.limit(n);
+    }
+
+    Ok(())
+}
+
+
+// a field name is the IRI of a predicate, unless it is written as <iri>
+fn predicate(name: &str) -> Value {
+    if name.starts_with('<') {
+        Value::from(name.to_string())
+    } else {
+        Value::from(format!("<{}>", name))
+    }
+}
+
+
+fn values(graph: &Graph, p: path::Path) -> Vec<Value> {
+    Path::new(graph.session.clone(), true, p).iter_values(None).iterator.collect()
+}
+
+
+fn set(obj: &js_sys::Object, key: &str, value: &JsValue) {
+    let _ = js_sys::Reflect::set(obj, &JsValue::from_str(key), value);
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    // an IRI with its <>
+    Iri(String),
+    Str(String),
+    Num(f64),
+    Punct(char),
+}
+
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize, usize)>, Error> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+        let column = pos - line_start + 1;
+
+        // commas are whitespace in GraphQL
+        if c == '\n' {
+            pos += 1;
+            line += 1;
+            line_start = pos;
+            continue
+        }
+
+        if c.is_whitespace() || c == ',' {
+            pos += 1;
+            continue
+        }
+
+        if c == '#' {
+            while pos < chars.len() && chars[pos] != '\n' {
+                pos += 1;
+            }
+            continue
+        }
+
+        let error = |message: &str| Error::InvalidInput(format!("line {}, column {}: {}", line, column, message));
+
+        let token = if c.is_alphabetic() || c == '_' {
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            Token::Name(chars[start..pos].iter().collect())
+        } else if c == '<' {
+            match chars[pos..].iter().position(|c| *c == '>') {
+                Some(end) => {
+                    pos += end + 1;
+                    Token::Iri(chars[start..pos].iter().collect())
+                },
+                None => return Err(error("unterminated IRI"))
+            }
+        } else if c == '"' {
+            pos += 1;
+            let mut s = String::new();
+            loop {
+                match chars.get(pos) {
+                    Some('"') => {
+                        pos += 1;
+                        break
+                    },
+                    Some('\\') => {
+                        let escaped = match chars.get(pos + 1) {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some(c) => *c,
+                            None => return Err(error("unterminated string"))
+                        };
+                        s.push(escaped);
+                        pos += 2;
+                    },
+                    Some('\n') | None => return Err(error("unterminated string")),
+                    Some(c) => {
+                        s.push(*c);
+                        pos += 1;
+                    }
+                }
+            }
+            Token::Str(s)
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(pos + 1).map(|c| c.is_ascii_digit()).unwrap_or(false)) {
+            pos += 1;
+            while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.' || chars[pos] == 'e' || chars[pos] == 'E'
+                || ((chars[pos] == '-' || chars[pos] == '+') && (chars[pos - 1] == 'e' || chars[pos - 1] == 'E'))) {
+                pos += 1;
+            }
+            let s: String = chars[start..pos].iter().collect();
+            match s.parse() {
+                Ok(n) => Token::Num(n),
+                Err(_) => return Err(error(&format!("invalid number {}", s)))
+            }
+        } else if "{}()[]:@".contains(c) {
+            pos += 1;
+            Token::Punct(c)
+        } else {
+            return Err(error(&format!("unexpected {:?}", c)))
+        };
+
+        tokens.push((token, line, column));
+    }
+
+    Ok(tokens)
+}
+
+
+fn parse(text: &str) -> Result<Vec<Field>, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+
+    // an optional query keyword and name before the selection
+    if parser.peek() == Some(&Token::Name("query".to_string())) {
+        parser.pos += 1;
+        if let Some(Token::Name(_)) = parser.peek() {
+            parser.pos += 1;
+        }
+    }
+
+    let fields = parser.selection()?;
+
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("unexpected text after the query"))
+    }
+
+    if fields.is_empty() {
+        return Err(parser.error("expected a root field such as nodes"))
+    }
+
+    Ok(fields)
+}
+
+
+struct Parser {
+    tokens: Vec<(Token, usize, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> Error {
+        match self.tokens.get(self.pos) {
+            Some((_, line, column)) => Error::InvalidInput(format!("line {}, column {}: {}", line, column, message)),
+            None => Error::InvalidInput(format!("end of query: {}", message))
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _, _)| t)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            return true
+        }
+        false
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        match self.peek().cloned() {
+            Some(Token::Name(n)) | Some(Token::Iri(n)) => {
+                self.pos += 1;
+                Ok(n)
+            },
+            _ => Err(self.error("expected a name"))
+        }
+    }
+
+    // { field field ... }
+    fn selection(&mut self) -> Result<Vec<Field>, Error> {
+        self.expect('{')?;
+
+        let mut fields = Vec::new();
+
+        while !self.eat('}') {
+            fields.push(self.field()?);
+        }
+
+        Ok(fields)
+    }
+
+    fn field(&mut self) -> Result<Field, Error> {
+        let column = self.tokens.get(self.pos).map(|(_, _, c)| *c).unwrap_or(0);
+
+        let mut name = self.name()?;
+        let mut alias = None;
+
+        if self.eat(':') {
+            alias = Some(name);
+            name = self.name()?;
+        }
+
+        let args = self.args()?;
+
+        let mut directives = Vec::new();
+        while self.eat('@') {
+            // the @ the directive starts at, for the error
+            let at = self.pos - 1;
+            let directive = self.name()?;
+            if !["filter", "label", "opt", "rev"].contains(&directive.as_str()) {
+                self.pos = at;
+                return Err(self.error(&format!("unknown directive @{}", directive)))
+            }
+            directives.push((directive, self.args()?));
+        }
+
+        let selection = if self.peek() == Some(&Token::Punct('{')) {
+            Some(self.selection()?)
+        } else {
+            None
+        };
+
+        Ok(Field {
+            alias,
+            name,
+            args,
+            directives,
+            selection,
+            column,
+        })
+    }
+
+    // (name: value ...), if there are any
+    fn args(&mut self) -> Result<Vec<(String, JsValue)>, Error> {
+        let mut args = Vec::new();
+
+        if !self.eat('(') {
+            return Ok(args)
+        }
+
+        while !self.eat(')') {
+            let name = self.name()?;
+            self.expect(':')?;
+            args.push((name, self.value()?));
+        }
+
+        Ok(args)
+    }
+
+    fn value(&mut self) -> Result<JsValue, Error> {
+        let token = match self.peek().cloned() {
+            Some(t) => t,
+            None => return Err(self.error("expected a value"))
+        };
+
+        self.pos += 1;
+
+        let value = match token {
+            Token::Str(s) | Token::Iri(s) => JsValue::from_str(&s),
+            Token::Num(n) => JsValue::from_f64(n),
+            Token::Name(n) if n == "true" => JsValue::TRUE,
+            Token::Name(n) if n == "false" => JsValue::FALSE,
+            Token::Name(n) if n == "null" => JsValue::NULL,
+            Token::Punct('[') => {
+                let arr = js_sys::Array::new();
+                while !self.eat(']') {
+                    arr.push(&self.value()?);
+                }
+                arr.into()
+            },
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("expected a string, a number, an IRI or a list"))
+            }
+        };
+
+        Ok(value)
+    }
+}
@@ -0,0 +1,5 @@
+// Query languages that are parsed and compiled in Rust instead of built with the JavaScript method chain
+
+pub mod gizmo;
+pub mod graphql;
+pub mod sparql;
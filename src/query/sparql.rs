@@ -0,0 +1,1150 @@
+use gizmo_db::query::path;
+use gizmo_db::query::shape;
+use gizmo_db::graph::iterator::value_filter::Operator;
+use gizmo_db::graph::value::Value;
+
+use crate::{Graph, Path, TagIterator, comparison, values_to_via};
+use crate::error::Error;
+use crate::format::term::{self, Term};
+
+use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+
+
+// A subset of SPARQL SELECT:
+//
+//   PREFIX ex: <http://example.org/>
+//   SELECT DISTINCT ?name ?age WHERE {
+//       ?person a ex:Person ; ex:name ?name .
+//       GRAPH ?g { ?person ex:knows ?friend }
+//       OPTIONAL { ?person ex:age ?age }
+//       FILTER (?name >= "B" && regex(?name, "^B"))
+//   } ORDER BY DESC(?age) LIMIT 10 OFFSET 5
+//
+// The triple patterns have to form a tree, which is compiled into a path that starts at one variable,
+// follows each pattern with out or in, tags the variable it reaches and goes back. Predicates can not
+// be variables. An OPTIONAL group is a single pattern that binds a variable no other pattern uses.
+// FILTER supports <, <=, >, >=, = and && between a variable and a constant, regex, CONTAINS, STRSTARTS
+// and STRENDS. A variable GRAPH holds a single pattern between two variables. ORDER BY can use any
+// variable. Each row holds the selected variables that are bound, named without the ?.
+pub fn run(graph: &Graph, text: &str) -> Result<TagIterator, Error> {
+    let query = parse(text)?;
+
+    let qs = graph.session.borrow().qs.clone();
+    let start = path::Path::start_path(Some(qs), Vec::new());
+
+    let path = Compiler::new(&query)?.compile(start)?;
+
+    let mut rows: Box<dyn Iterator<Item = HashMap<String, Value>>> = Path::new(graph.session.clone(), true, path).iter_tags(None).iterator;
+
+    // the rows are sorted before they are projected, ORDER BY can use variables that are not selected
+    if !query.order.is_empty() {
+        let mut sorted: Vec<HashMap<String, Value>> = rows.collect();
+        let order = query.order.clone();
+
+        sorted.sort_by(|a, b| {
+            for (var, desc) in &order {
+                let ord = compare(a.get(var), b.get(var));
+                if ord != Ordering::Equal {
+                    return if *desc { ord.reverse() } else { ord }
+                }
+            }
+            Ordering::Equal
+        });
+
+        rows = Box::new(sorted.into_iter());
+    }
+
+    let vars = query.projection();
+    rows = Box::new(rows.map(move |row| {
+        row.into_iter().filter(|(k, _)| vars.contains(k)).collect()
+    }));
+
+    if query.distinct {
+        let mut seen = HashSet::new();
+        rows = Box::new(rows.filter(move |row| seen.insert(row_key(row))));
+    }
+
+    if let Some(offset) = query.offset {
+        rows = Box::new(rows.skip(offset));
+    }
+
+    if let Some(limit) = query.limit {
+        rows = Box::new(rows.take(limit));
+    }
+
+    Ok(TagIterator {
+        iterator: rows
+    })
+}
+
+
+#[derive(Clone)]
+enum Node {
+    Var(String),
+    Const(Value),
+}
+
+impl Node {
+    fn is_var(&self, name: &str) -> bool {
+        match self {
+            Node::Var(v) => v == name,
+            Node::Const(_) => false
+        }
+    }
+
+    // tells nodes apart without comparing values
+    fn key(&self) -> String {
+        match self {
+            Node::Var(v) => format!("?{}", v),
+            Node::Const(c) => Term::from_value(c).map(|t| t.to_ntriples()).unwrap_or_default()
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Pattern {
+    subject: Node,
+    predicate: Node,
+    object: Node,
+    graph: Option<Node>,
+    column: usize,
+}
+
+enum Filter {
+    // <, <=, > or >=
+    Compare(&'static str, Value),
+    Equals(Value),
+    Like(String),
+    Regex(String, bool),
+}
+
+struct Query {
+    // None for SELECT *
+    select: Option<Vec<String>>,
+    distinct: bool,
+    patterns: Vec<Pattern>,
+    optionals: Vec<Pattern>,
+    filters: Vec<(String, Filter)>,
+    // the variable and whether it is descending
+    order: Vec<(String, bool)>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+impl Query {
+    fn projection(&self) -> HashSet<String> {
+        match &self.select {
+            Some(vars) => vars.iter().cloned().collect(),
+            None => {
+                let mut vars = HashSet::new();
+                for p in self.patterns.iter().chain(self.optionals.iter()) {
+                    for node in &[&p.subject, &p.object] {
+                        if let Node::Var(v) = node {
+                            if !v.starts_with("_:") {
+                                vars.insert(v.clone());
+                            }
+                        }
+                    }
+                    if let Some(Node::Var(g)) = &p.graph {
+                        vars.insert(g.clone());
+                    }
+                }
+                vars
+            }
+        }
+    }
+}
+
+
+// turns the patterns into a path, see run
+struct Compiler<'a> {
+    query: &'a Query,
+    used: Vec<bool>,
+    // the required variable each OPTIONAL pattern is saved from
+    anchors: Vec<String>,
+    applied: Vec<bool>,
+    visited: HashSet<String>,
+    // the graph the path is in
+    context: Option<Node>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(query: &'a Query) -> Result<Compiler<'a>, Error> {
+        for p in query.patterns.iter().chain(query.optionals.iter()) {
+            if let Node::Var(_) = p.predicate {
+                return Err(unsupported(p.column, "variable predicates"))
+            }
+        }
+
+        // a variable graph is tagged with the label of the quad a traversal follows, so it is only bound
+        // by a single required pattern between two variables, nothing joins the graphs of several patterns
+        let mut graphs = HashSet::new();
+        let required = query.patterns.iter().map(|p| (p, false));
+        for (p, optional) in required.chain(query.optionals.iter().map(|p| (p, true))) {
+            let g = match &p.graph {
+                Some(Node::Var(g)) => g,
+                _ => continue
+            };
+
+            if !graphs.insert(g.clone()) {
+                return Err(unsupported(p.column, "variable GRAPHs with more than one pattern"))
+            }
+
+            let bound = query.patterns.iter().chain(query.optionals.iter()).any(|q| q.subject.is_var(g) || q.object.is_var(g));
+            if bound {
+                return Err(unsupported(p.column, "GRAPH variables that are also subjects or objects"))
+            }
+
+            let traversal = match (&p.subject, &p.object) {
+                (Node::Var(_), Node::Var(_)) => true,
+                _ => false
+            };
+
+            if optional || !traversal {
+                return Err(unsupported(p.column, "variable GRAPHs around an OPTIONAL or a pattern with a constant"))
+            }
+        }
+
+        // an OPTIONAL pattern is saved from a variable that a required pattern binds, onto a variable
+        // that nothing else binds, checked here so it does not depend on the order the patterns are visited
+        let required: HashSet<&str> = query.patterns.iter()
+            .flat_map(|p| vec![&p.subject, &p.object])
+            .filter_map(|n| match n {
+                Node::Var(v) => Some(v.as_str()),
+                Node::Const(_) => None
+            })
+            .collect();
+
+        let mut bound = HashSet::new();
+        let mut anchors = Vec::new();
+        for p in &query.optionals {
+            let (anchor, other) = match (&p.subject, &p.object) {
+                (Node::Var(s), o) if required.contains(s.as_str()) => (s, o),
+                (s, Node::Var(o)) if required.contains(o.as_str()) => (o, s),
+                _ => return Err(unsupported(p.column, "OPTIONAL patterns that are not joined to a required pattern"))
+            };
+
+            let new = match other {
+                Node::Var(w) => !required.contains(w.as_str()) && bound.insert(w.clone()),
+                Node::Const(_) => false
+            };
+
+            if !new {
+                return Err(unsupported(p.column, "OPTIONAL patterns that do not bind a new variable"))
+            }
+
+            anchors.push(anchor.clone());
+        }
+
+        Ok(Compiler {
+            query,
+            used: vec![false; query.patterns.len()],
+            anchors,
+            applied: vec![false; query.optionals.len()],
+            visited: HashSet::new(),
+            context: None,
+        })
+    }
+
+    fn compile(mut self, mut path: path::Path) -> Result<path::Path, Error> {
+        let root = self.query.patterns.iter().find_map(|p| match (&p.subject, &p.object) {
+            (Node::Var(v), _) | (_, Node::Var(v)) => Some(v.clone()),
+            _ => None
+        });
+
+        let root = match root {
+            Some(r) => r,
+            None => return Err(Error::InvalidInput("the query needs a triple pattern with a variable".to_string()))
+        };
+
+        // variables that only OPTIONAL binds can not be filtered, they are saved instead of traversed
+        for (var, _) in &self.query.filters {
+            if !self.query.patterns.iter().any(|p| p.subject.is_var(var) || p.object.is_var(var)) {
+                return Err(Error::InvalidInput(format!("FILTER on ?{} is not supported, it is not bound by a required pattern", var)))
+            }
+        }
+
+        path.tag(vec![root.clone()]);
+        self.visited.insert(root.clone());
+        self.visit(&mut path, &root)?;
+
+        if let Some(i) = self.used.iter().position(|u| !u) {
+            return Err(unsupported(self.query.patterns[i].column, "patterns that are not connected to the others"))
+        }
+
+        if let Some(i) = self.applied.iter().position(|a| !a) {
+            return Err(unsupported(self.query.optionals[i].column, "OPTIONAL patterns that are not joined to a required pattern"))
+        }
+
+        Ok(path)
+    }
+
+    fn visit(&mut self, path: &mut path::Path, var: &str) -> Result<(), Error> {
+        let query = self.query;
+
+        for (v, filter) in &query.filters {
+            if v == var {
+                match filter {
+                    Filter::Compare(op, value) => {
+                        let op = match *op {
+                            "<" => Operator::LT,
+                            "<=" => Operator::LTE,
+                            ">" => Operator::GT,
+                            _ => Operator::GTE
+                        };
+                        path.filters(comparison(op, value.clone()));
+                    },
+                    Filter::Equals(value) => {
+                        path.is(vec![value.clone()]);
+                    },
+                    Filter::Like(pattern) => {
+                        path.filters(vec![Rc::new(shape::Wildcard::new(pattern.clone().into()))]);
+                    },
+                    Filter::Regex(pattern, iri) => regex(path, pattern, *iri)?,
+                }
+            }
+        }
+
+        for (i, p) in query.patterns.iter().enumerate() {
+            if self.used[i] {
+                continue
+            }
+
+
+            let (other, rev) = if p.subject.is_var(var) {
+                (&p.object, false)
+            } else if p.object.is_var(var) {
+                (&p.subject, true)
+            } else {
+                continue
+            };
+
+            self.used[i] = true;
+
+            let predicate = match &p.predicate {
+                Node::Const(v) => v.clone(),
+                Node::Var(_) => unreachable!()
+            };
+
+            self.set_context(path, &p.graph);
+
+            match other {
+                Node::Const(value) => {
+                    path.has(values_to_via(vec![predicate]), rev, vec![value.clone()]);
+                },
+                Node::Var(w) if self.visited.contains(w) => {
+                    return Err(unsupported(p.column, "patterns that form a cycle"))
+                },
+                Node::Var(w) => {
+                    let w = w.clone();
+
+                    if rev {
+                        path.in_with_tags(Vec::new(), values_to_via(vec![predicate]));
+                    } else {
+                        path.out_with_tags(Vec::new(), values_to_via(vec![predicate]));
+                    }
+                    path.tag(vec![w.clone()]);
+                    self.visited.insert(w.clone());
+
+                    self.visit(path, &w)?;
+
+                    if let Some(back) = path.back(var.to_string()) {
+                        *path = back;
+                    }
+                }
+            }
+        }
+
+        for (i, p) in query.optionals.iter().enumerate() {
+            if self.applied[i] || self.anchors[i] != var {
+                continue
+            }
+
+            let (other, rev) = if p.subject.is_var(var) {
+                (&p.object, false)
+            } else {
+                (&p.subject, true)
+            };
+
+            let w = match other {
+                Node::Var(w) => w.clone(),
+                Node::Const(_) => unreachable!()
+            };
+
+            let predicate = match &p.predicate {
+                Node::Const(v) => v.clone(),
+                Node::Var(_) => unreachable!()
+            };
+
+            self.set_context(path, &p.graph);
+            path.save(values_to_via(vec![predicate]), w.clone(), rev, true);
+            self.visited.insert(w);
+            self.applied[i] = true;
+        }
+
+        Ok(())
+    }
+
+    fn set_context(&mut self, path: &mut path::Path, graph: &Option<Node>) {
+        if self.context.as_ref().map(Node::key) == graph.as_ref().map(Node::key) {
+            return
+        }
+
+        match graph {
+            Some(Node::Const(label)) => {
+                path.label_context_with_tags(values_to_via(vec![label.clone()]), Vec::new());
+            },
+            Some(Node::Var(tag)) => {
+                path.label_context_with_tags(path::Via::None, vec![tag.clone()]);
+            },
+            None => {
+                path.label_context_with_tags(path::Via::None, Vec::new());
+            }
+        }
+
+        self.context = graph.clone();
+    }
+}
+
+
+#[cfg(feature = "regex")]
+fn regex(path: &mut path::Path, pattern: &str, iri: bool) -> Result<(), Error> {
+    path.filters(vec![Rc::new(shape::Regexp::new(pattern.to_string().into(), iri))]);
+    Ok(())
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex(_path: &mut path::Path, _pattern: &str, _iri: bool) -> Result<(), Error> {
+    Err(Error::InvalidInput("regex needs the regex feature".to_string()))
+}
+
+
+fn unsupported(column: usize, what: &str) -> Error {
+    Error::InvalidInput(format!("column {}: {} are not supported", column, what))
+}
+
+
+// identifies a row for DISTINCT
+fn row_key(row: &HashMap<String, Value>) -> String {
+    let mut entries: Vec<String> = row.iter().map(|(k, v)| {
+        format!("{}={}", k, Term::from_value(v).map(|t| t.to_ntriples()).unwrap_or_default())
+    }).collect();
+    entries.sort();
+    entries.join(" ")
+}
+
+
+// unbound values first, then booleans, numbers, IRIs and strings, as in ORDER BY
+fn compare(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    fn rank(v: Option<&Value>) -> u8 {
+        match v {
+            None | Some(Value::None) | Some(Value::Null) => 0,
+            Some(Value::Bool(_)) => 1,
+            Some(Value::Number(_)) => 2,
+            Some(Value::IRI(_)) => 3,
+            Some(Value::String(_)) => 4,
+        }
+    }
+
+    match (a, b) {
+        (Some(Value::Bool(x)), Some(Value::Bool(y))) => x.cmp(y),
+        (Some(Value::Number(x)), Some(Value::Number(y))) => {
+            if let (Some(x), Some(y)) = (x.as_i64(), y.as_i64()) {
+                return x.cmp(&y)
+            }
+            let (x, y) = (x.as_f64().unwrap_or(0.0), y.as_f64().unwrap_or(0.0));
+            x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+        },
+        (Some(x @ Value::IRI(_)), Some(y @ Value::IRI(_))) => x.to_string().cmp(&y.to_string()),
+        (Some(Value::String(x)), Some(Value::String(y))) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b))
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Iri(String),
+    // a prefixed name, the prefix and the local part
+    Name(String, String),
+    Var(String),
+    BlankNode(String),
+    Str(String),
+    Num(String),
+    Word(String),
+    LangTag(String),
+    Punct(&'static str),
+}
+
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize, usize)>, Error> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.';
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+        let column = pos - line_start + 1;
+
+        let error = |message: &str| Error::InvalidInput(format!("line {}, column {}: {}", line, column, message));
+
+        if c == '\n' {
+            pos += 1;
+            line += 1;
+            line_start = pos;
+            continue
+        }
+
+        if c.is_whitespace() {
+            pos += 1;
+            continue
+        }
+
+        if c == '#' {
+            while pos < chars.len() && chars[pos] != '\n' {
+                pos += 1;
+            }
+            continue
+        }
+
+        let token = if c == '<' && {
+            // an IRI, unless this is the less than operator
+            let end = chars[pos + 1..].iter().position(|c| *c == '>' || c.is_whitespace() || *c == '"' || *c == '<');
+            end.map(|e| chars[pos + 1 + e] == '>').unwrap_or(false)
+        } {
+            let end = pos + 1 + chars[pos + 1..].iter().position(|c| *c == '>').unwrap();
+            let iri: String = chars[pos + 1..end].iter().collect();
+            pos = end + 1;
+            Token::Iri(iri)
+        } else if c == '?' || c == '$' {
+            pos += 1;
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            if pos == start + 1 {
+                return Err(error("empty variable name"))
+            }
+            Token::Var(chars[start + 1..pos].iter().collect())
+        } else if c == '_' && chars.get(pos + 1) == Some(&':') {
+            pos += 2;
+            while pos < chars.len() && is_name_char(chars[pos]) {
+                pos += 1;
+            }
+            while chars[pos - 1] == '.' {
+                pos -= 1;
+            }
+            Token::BlankNode(chars[start + 2..pos].iter().collect())
+        } else if c == '"' || c == '\'' {
+            pos += 1;
+            let mut s = String::new();
+            loop {
+                match chars.get(pos) {
+                    Some(q) if *q == c => {
+                        pos += 1;
+                        break
+                    },
+                    Some('\\') => {
+                        let escaped = match chars.get(pos + 1) {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('b') => '\u{8}',
+                            Some('f') => '\u{c}',
+                            Some(c) => *c,
+                            None => return Err(error("unterminated string"))
+                        };
+                        s.push(escaped);
+                        pos += 2;
+                    },
+                    Some('\n') | None => return Err(error("unterminated string")),
+                    Some(c) => {
+                        s.push(*c);
+                        pos += 1;
+                    }
+                }
+            }
+            Token::Str(s)
+        } else if c == '@' {
+            pos += 1;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '-') {
+                pos += 1;
+            }
+            Token::LangTag(chars[start + 1..pos].iter().collect())
+        } else if c.is_ascii_digit() || ((c == '-' || c == '+' || c == '.') && chars.get(pos + 1).map(|c| c.is_ascii_digit()).unwrap_or(false)) {
+            pos += 1;
+            while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.' || chars[pos] == 'e' || chars[pos] == 'E'
+                || ((chars[pos] == '-' || chars[pos] == '+') && (chars[pos - 1] == 'e' || chars[pos - 1] == 'E'))) {
+                pos += 1;
+            }
+            // a number can not end with the '.' that ends a triple
+            while chars[pos - 1] == '.' {
+                pos -= 1;
+            }
+            Token::Num(chars[start..pos].iter().collect())
+        } else if c.is_alphabetic() || c == ':' {
+            while pos < chars.len() && (is_name_char(chars[pos]) || chars[pos] == ':') {
+                pos += 1;
+            }
+            while chars[pos - 1] == '.' {
+                pos -= 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            match word.find(':') {
+                Some(i) => Token::Name(word[..i].to_string(), word[i + 1..].to_string()),
+                None => Token::Word(word)
+            }
+        } else {
+            let two: String = chars[pos..(pos + 2).min(chars.len())].iter().collect();
+            let punct = ["&&", "||", "<=", ">=", "!=", "^^"].iter().find(|p| **p == two);
+
+            match punct {
+                Some(p) => {
+                    pos += 2;
+                    Token::Punct(*p)
+                },
+                None => {
+                    pos += 1;
+                    match c {
+                        '{' => Token::Punct("{"),
+                        '}' => Token::Punct("}"),
+                        '(' => Token::Punct("("),
+                        ')' => Token::Punct(")"),
+                        '.' => Token::Punct("."),
+                        ';' => Token::Punct(";"),
+                        ',' => Token::Punct(","),
+                        '*' => Token::Punct("*"),
+                        '[' => Token::Punct("["),
+                        ']' => Token::Punct("]"),
+                        '<' => Token::Punct("<"),
+                        '>' => Token::Punct(">"),
+                        '=' => Token::Punct("="),
+                        c => return Err(error(&format!("unexpected {:?}", c)))
+                    }
+                }
+            }
+        };
+
+        tokens.push((token, line, column));
+    }
+
+    Ok(tokens)
+}
+
+
+fn parse(text: &str) -> Result<Query, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        prefixes: HashMap::new(),
+        blank_nodes: 0,
+    };
+
+    parser.query()
+}
+
+
+struct Parser {
+    tokens: Vec<(Token, usize, usize)>,
+    pos: usize,
+    prefixes: HashMap<String, String>,
+    // the number of [] blank nodes so far, which are variables without a name
+    blank_nodes: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> Error {
+        match self.tokens.get(self.pos) {
+            Some((_, line, column)) => Error::InvalidInput(format!("line {}, column {}: {}", line, column, message)),
+            None => Error::InvalidInput(format!("end of query: {}", message))
+        }
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map(|(_, _, c)| *c).unwrap_or(0)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _, _)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.peek().cloned();
+        if t.is_some() {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        match self.peek() {
+            Some(Token::Punct(q)) => *q == p,
+            _ => false
+        }
+    }
+
+    fn eat(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.pos += 1;
+            return true
+        }
+        false
+    }
+
+    fn expect(&mut self, p: &str) -> Result<(), Error> {
+        if self.eat(p) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", p)))
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) => w.eq_ignore_ascii_case(keyword),
+            _ => false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            return true
+        }
+        false
+    }
+
+    fn query(&mut self) -> Result<Query, Error> {
+        loop {
+            if self.eat_keyword("PREFIX") {
+                let prefix = match self.next() {
+                    Some(Token::Name(prefix, local)) if local.is_empty() => prefix,
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected a prefix such as ex:"))
+                    }
+                };
+                match self.next() {
+                    Some(Token::Iri(iri)) => { self.prefixes.insert(prefix, iri); },
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected an IRI"))
+                    }
+                }
+            } else if self.is_keyword("BASE") {
+                return Err(self.error("BASE is not supported"))
+            } else {
+                break
+            }
+        }
+
+        if !self.eat_keyword("SELECT") {
+            return Err(self.error("expected SELECT, only SELECT queries are supported"))
+        }
+
+        let distinct = self.eat_keyword("DISTINCT") || self.eat_keyword("REDUCED");
+
+        let select = if self.eat("*") {
+            None
+        } else {
+            let mut vars = Vec::new();
+            while let Some(Token::Var(v)) = self.peek().cloned() {
+                self.pos += 1;
+                vars.push(v);
+            }
+            if vars.is_empty() {
+                return Err(self.error("expected * or variables"))
+            }
+            Some(vars)
+        };
+
+        self.eat_keyword("WHERE");
+
+        let mut query = Query {
+            select,
+            distinct,
+            patterns: Vec::new(),
+            optionals: Vec::new(),
+            filters: Vec::new(),
+            order: Vec::new(),
+            limit: None,
+            offset: None,
+        };
+
+        self.group(&mut query, &None, false)?;
+
+        loop {
+            if self.eat_keyword("ORDER") {
+                if !self.eat_keyword("BY") {
+                    return Err(self.error("expected BY"))
+                }
+                loop {
+                    let desc = if self.eat_keyword("DESC") {
+                        Some(true)
+                    } else if self.eat_keyword("ASC") {
+                        Some(false)
+                    } else {
+                        None
+                    };
+
+                    if desc.is_some() {
+                        self.expect("(")?;
+                    }
+
+                    match self.next() {
+                        Some(Token::Var(v)) => query.order.push((v, desc.unwrap_or(false))),
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("expected a variable, ORDER BY only supports variables"))
+                        }
+                    }
+
+                    if desc.is_some() {
+                        self.expect(")")?;
+                    }
+
+                    match self.peek() {
+                        Some(Token::Var(_)) => {},
+                        Some(Token::Word(w)) if w.eq_ignore_ascii_case("ASC") || w.eq_ignore_ascii_case("DESC") => {},
+                        _ => break
+                    }
+                }
+            } else if self.eat_keyword("LIMIT") {
+                query.limit = Some(self.count()?);
+            } else if self.eat_keyword("OFFSET") {
+                query.offset = Some(self.count()?);
+            } else {
+                break
+            }
+        }
+
+        if self.pos < self.tokens.len() {
+            return Err(self.error("unexpected text after the query"))
+        }
+
+        Ok(query)
+    }
+
+    fn count(&mut self) -> Result<usize, Error> {
+        match self.next() {
+            Some(Token::Num(n)) => n.parse().map_err(|_| {
+                self.pos -= 1;
+                self.error("expected a whole number")
+            }),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected a number"))
+            }
+        }
+    }
+
+    // { patterns, GRAPH, OPTIONAL and FILTER }
+    fn group(&mut self, query: &mut Query, graph: &Option<Node>, optional: bool) -> Result<(), Error> {
+        self.expect("{")?;
+
+        loop {
+            if self.eat("}") {
+                return Ok(())
+            }
+
+            if self.eat(".") {
+                continue
+            }
+
+            if self.eat_keyword("GRAPH") {
+                let g = self.term()?;
+                self.group(query, &Some(g), optional)?;
+            } else if self.eat_keyword("OPTIONAL") {
+                if optional {
+                    return Err(self.error("nested OPTIONAL groups are not supported"))
+                }
+
+                let column = self.column();
+                let mut inner = Query {
+                    select: None,
+                    distinct: false,
+                    patterns: Vec::new(),
+                    optionals: Vec::new(),
+                    filters: Vec::new(),
+                    order: Vec::new(),
+                    limit: None,
+                    offset: None,
+                };
+
+                self.group(&mut inner, graph, true)?;
+
+                if inner.patterns.len() != 1 || !inner.filters.is_empty() {
+                    return Err(unsupported(column, "OPTIONAL groups with more than one pattern or a FILTER"))
+                }
+
+                query.optionals.extend(inner.patterns);
+            } else if self.eat_keyword("FILTER") {
+                self.filter(query)?;
+            } else if self.is_keyword("UNION") || self.is_keyword("MINUS") || self.is_keyword("BIND") || self.is_keyword("VALUES") {
+                return Err(self.error("only patterns, GRAPH, OPTIONAL and FILTER are supported"))
+            } else {
+                self.triples(query, graph)?;
+            }
+        }
+    }
+
+    // a subject followed by predicates and objects, separated by ; and ,
+    fn triples(&mut self, query: &mut Query, graph: &Option<Node>) -> Result<(), Error> {
+        let column = self.column();
+        let subject = self.term()?;
+
+        loop {
+            let predicate = if self.eat_keyword("a") {
+                Node::Const(Term::Iri(RDF_TYPE.to_string()).to_value())
+            } else {
+                self.term()?
+            };
+
+            loop {
+                let object = self.term()?;
+
+                query.patterns.push(Pattern {
+                    subject: subject.clone(),
+                    predicate: predicate.clone(),
+                    object,
+                    graph: graph.clone(),
+                    column,
+                });
+
+                if !self.eat(",") {
+                    break
+                }
+            }
+
+            if !self.eat(";") {
+                break
+            }
+
+            // a ; can end the predicate list
+            if self.is_punct(".") || self.is_punct("}") {
+                break
+            }
+        }
+
+        if !self.is_punct("}") {
+            self.expect(".")?;
+        }
+
+        Ok(())
+    }
+
+    fn iri(&self, token: &Token) -> Result<Option<String>, Error> {
+        match token {
+            Token::Iri(iri) => Ok(Some(iri.clone())),
+            Token::Name(prefix, local) => match self.prefixes.get(prefix) {
+                Some(ns) => Ok(Some(format!("{}{}", ns, local))),
+                None => Err(self.error(&format!("unknown prefix {}:", prefix)))
+            },
+            _ => Ok(None)
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, Error> {
+        let token = match self.peek().cloned() {
+            Some(t) => t,
+            None => return Err(self.error("unexpected end of query"))
+        };
+
+        if let Some(iri) = self.iri(&token)? {
+            self.pos += 1;
+            return Ok(Node::Const(Term::Iri(iri).to_value()))
+        }
+
+        self.pos += 1;
+
+        let term = match token {
+            Token::Var(v) => return Ok(Node::Var(v)),
+            Token::BlankNode(label) => return Ok(Node::Var(format!("_:{}", label))),
+            Token::Punct("[") => {
+                self.expect("]")?;
+                self.blank_nodes += 1;
+                return Ok(Node::Var(format!("_:{}", self.blank_nodes)))
+            },
+            Token::Num(n) => {
+                let datatype = if n.contains('e') || n.contains('E') {
+                    "double"
+                } else if n.contains('.') {
+                    "decimal"
+                } else {
+                    "integer"
+                };
+                Term::Literal { value: n, datatype: Some(format!("{}{}", term::XSD, datatype)), lang: None }
+            },
+            Token::Word(w) if w == "true" || w == "false" => {
+                Term::Literal { value: w, datatype: Some(format!("{}boolean", term::XSD)), lang: None }
+            },
+            Token::Str(value) => {
+                if let Some(Token::LangTag(lang)) = self.peek().cloned() {
+                    self.pos += 1;
+                    Term::Literal { value, datatype: None, lang: Some(lang) }
+                } else if self.eat("^^") {
+                    let token = self.next().unwrap_or(Token::Punct(""));
+                    match self.iri(&token)? {
+                        Some(datatype) => Term::Literal { value, datatype: Some(datatype), lang: None },
+                        None => {
+                            self.pos -= 1;
+                            return Err(self.error("expected a datatype IRI"))
+                        }
+                    }
+                } else {
+                    Term::Literal { value, datatype: None, lang: None }
+                }
+            },
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("expected an IRI, a literal or a variable"))
+            }
+        };
+
+        Ok(Node::Const(term.to_value()))
+    }
+
+    // FILTER ( relation && relation ... ) or FILTER builtin(...)
+    fn filter(&mut self, query: &mut Query) -> Result<(), Error> {
+        let parens = self.eat("(");
+
+        loop {
+            let filter = self.constraint()?;
+            query.filters.push(filter);
+
+            if !parens || !self.eat("&&") {
+                break
+            }
+        }
+
+        if parens {
+            if self.is_punct("||") {
+                return Err(self.error("|| is not supported in FILTER"))
+            }
+            self.expect(")")?;
+        }
+
+        Ok(())
+    }
+
+    fn constraint(&mut self) -> Result<(String, Filter), Error> {
+        if let Some(Token::Word(w)) = self.peek().cloned() {
+            let name = w.to_uppercase();
+
+            if name == "REGEX" || name == "CONTAINS" || name == "STRSTARTS" || name == "STRENDS" {
+                self.pos += 1;
+                self.expect("(")?;
+
+                // str(?x) also matches IRIs
+                let str_fn = self.eat_keyword("STR");
+                if str_fn {
+                    self.expect("(")?;
+                }
+                let var = self.var()?;
+                if str_fn {
+                    self.expect(")")?;
+                }
+
+                self.expect(",")?;
+
+                let pattern = match self.next() {
+                    Some(Token::Str(s)) => s,
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected a string"))
+                    }
+                };
+
+                if name == "REGEX" && self.eat(",") {
+                    match self.next() {
+                        Some(Token::Str(flags)) if flags.is_empty() => {},
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("regex flags are not supported"))
+                        }
+                    }
+                }
+
+                self.expect(")")?;
+
+                // * and ? are the wildcards of like, which can not be escaped
+                if name != "REGEX" && (pattern.contains('*') || pattern.contains('?')) {
+                    return Err(self.error(&format!("{} does not support * and ?, use regex", name)))
+                }
+
+                let filter = match name.as_str() {
+                    "REGEX" => Filter::Regex(pattern, str_fn),
+                    "CONTAINS" => Filter::Like(format!("*{}*", pattern)),
+                    "STRSTARTS" => Filter::Like(format!("{}*", pattern)),
+                    _ => Filter::Like(format!("*{}", pattern)),
+                };
+
+                return Ok((var, filter))
+            }
+        }
+
+        let left = self.operand()?;
+
+        let op = match self.next() {
+            Some(Token::Punct(op)) if ["<", "<=", ">", ">=", "="].contains(&op) => op,
+            Some(Token::Punct("!=")) => {
+                self.pos -= 1;
+                return Err(self.error("!= is not supported in FILTER"))
+            },
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("expected <, <=, >, >= or ="))
+            }
+        };
+
+        let right = self.operand()?;
+
+        // the variable goes on the left, so 5 < ?x becomes ?x > 5
+        let (var, value, op) = match (left, right) {
+            (Node::Var(v), Node::Const(c)) => (v, c, op),
+            (Node::Const(c), Node::Var(v)) => (v, c, match op {
+                "<" => ">",
+                "<=" => ">=",
+                ">" => "<",
+                ">=" => "<=",
+                op => op
+            }),
+            _ => return Err(self.error("a FILTER compares a variable with a constant"))
+        };
+
+        let filter = match op {
+            "=" => Filter::Equals(value),
+            op => Filter::Compare(op, value),
+        };
+
+        Ok((var, filter))
+    }
+
+    fn operand(&mut self) -> Result<Node, Error> {
+        if self.eat("(") {
+            let node = self.operand()?;
+            self.expect(")")?;
+            return Ok(node)
+        }
+        self.term()
+    }
+
+    fn var(&mut self) -> Result<String, Error> {
+        match self.next() {
+            Some(Token::Var(v)) => Ok(v),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected a variable"))
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use gizmo_db::graph::value::Value;
+
+use std::collections::HashMap;
+
+use crate::value_to_js;
+use crate::error::Error;
+use crate::format::term::Term;
+
+
+// Shapes the rows of a path into nested objects with a template like
+//
+//   {
+//     $key: "person",                       the tag the rows are grouped by, "id" by default
+//     name: "name",                         the value of a tag
+//     nicknames: ["nickname"],              the distinct values of a tag
+//     friends: { $key: "friend", name: "friend_name" }
+//   }
+//
+// which gives an object for each person, in the order they are first found, with an array of an object
+// for each of their friends, grouped from the rows of that person. A tag that has no value in any of
+// the rows of a group is left out, a nested template without rows is an empty array.
+pub struct Template {
+    key: String,
+    fields: Vec<(String, Field)>,
+}
+
+enum Field {
+    Tag(String),
+    Tags(String),
+    Nested(Template),
+}
+
+impl Template {
+    pub fn new(js: &JsValue) -> Result<Template, Error> {
+        Template::parse(js, Some("id"))
+    }
+
+    fn parse(js: &JsValue, default_key: Option<&str>) -> Result<Template, Error> {
+        if !js.is_object() || js_sys::Array::is_array(js) {
+            return Err(Error::InvalidInput("expected a template object".to_string()))
+        }
+
+        let key = match get(js, "$key").as_string() {
+            Some(k) => k,
+            None => match default_key {
+                Some(k) => k.to_string(),
+                None => return Err(Error::InvalidInput("a nested template needs a $key".to_string()))
+            }
+        };
+
+        let mut fields = Vec::new();
+
+        for name in js_sys::Object::keys(js.unchecked_ref()).iter() {
+            let name = name.as_string().unwrap_or_default();
+
+            if name == "$key" {
+                continue
+            }
+
+            let value = get(js, &name);
+
+            let field = if let Some(tag) = value.as_string() {
+                Field::Tag(tag)
+            } else if js_sys::Array::is_array(&value) {
+                match js_sys::Array::from(&value).get(0).as_string() {
+                    Some(tag) => Field::Tags(tag),
+                    None => return Err(Error::InvalidInput(format!("{} should be an array with the name of a tag", name)))
+                }
+            } else if value.is_object() {
+                Field::Nested(Template::parse(&value, None)?)
+            } else {
+                return Err(Error::InvalidInput(format!("{} should be a tag, an array with a tag or a template", name)))
+            };
+
+            fields.push((name, field));
+        }
+
+        Ok(Template {
+            key,
+            fields
+        })
+    }
+
+    pub fn build(&self, rows: &[HashMap<String, Value>]) -> JsValue {
+        let rows: Vec<&HashMap<String, Value>> = rows.iter().collect();
+        self.objects(&rows).into()
+    }
+
+    fn objects(&self, rows: &[&HashMap<String, Value>]) -> js_sys::Array {
+        // the rows of each key value, in the order the values are first found
+        let mut groups: Vec<Vec<&HashMap<String, Value>>> = Vec::new();
+        let mut index = HashMap::new();
+
+        for row in rows {
+            let id = match row.get(&self.key).and_then(value_id) {
+                Some(id) => id,
+                None => continue
+            };
+
+            match index.get(&id) {
+                Some(i) => groups[*i].push(*row),
+                None => {
+                    index.insert(id, groups.len());
+                    groups.push(vec![*row]);
+                }
+            }
+        }
+
+        let out = js_sys::Array::new();
+
+        for group in groups {
+            let obj = js_sys::Object::new();
+
+            for (name, field) in &self.fields {
+                let value = match field {
+                    Field::Tag(tag) => match group.iter().find_map(|row| row.get(tag)) {
+                        Some(v) => value_to_js(v),
+                        None => continue
+                    },
+                    Field::Tags(tag) => {
+                        let mut seen = Vec::new();
+                        let values = js_sys::Array::new();
+                        for v in group.iter().filter_map(|row| row.get(tag)) {
+                            if let Some(id) = value_id(v) {
+                                if !seen.contains(&id) {
+                                    seen.push(id);
+                                    values.push(&value_to_js(v));
+                                }
+                            }
+                        }
+                        values.into()
+                    },
+                    Field::Nested(template) => template.objects(&group).into()
+                };
+
+                let _ = js_sys::Reflect::set(&obj, &JsValue::from_str(name), &value);
+            }
+
+            out.push(&obj);
+        }
+
+        out
+    }
+}
+
+
+// tells values apart, so that "1" and 1 or <a> and "<a>" are different keys
+fn value_id(value: &Value) -> Option<String> {
+    Term::from_value(value).map(|t| t.to_ntriples())
+}
+
+
+fn get(obj: &JsValue, key: &str) -> JsValue {
+    js_sys::Reflect::get(obj, &JsValue::from_str(key)).unwrap_or(JsValue::undefined())
+}